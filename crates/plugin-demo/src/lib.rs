@@ -1,20 +1,283 @@
-use plugin_interface::{PluginInfo, PluginInterface, Version};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use plugin_interface::{
+    Host, LogLevel, OperationId, P2pEvent, P2pReply, PluginInterface, PluginUi, PluginWindow,
+    SubscriptionId, ToastLevel, WindowType, export_plugin,
+};
 
 struct Plugin {
-    info: PluginInfo,
+    host: Host,
+    ping_registered: bool,
+    count: Arc<Mutex<i64>>,
 }
 impl PluginInterface for Plugin {
-    fn plugin_info(&self) -> &PluginInfo {
-        &self.info
+    fn on_load(&mut self) {
+        self.host.log(LogLevel::Info, "插件已加载");
+    }
+    fn on_enable(&mut self) {
+        match self.host.p2p_register_protocol("ping") {
+            Ok(()) => self.ping_registered = true,
+            Err(err) => self
+                .host
+                .log(LogLevel::Warn, format!("注册 ping 协议失败: {}", err)),
+        }
+    }
+    fn on_update(&mut self) {
+        if !self.ping_registered {
+            return;
+        }
+        if let Ok(requests) = self.host.p2p_poll_requests("ping") {
+            for request in requests {
+                _ = self.host.p2p_respond(request.id, b"pong".to_vec());
+            }
+        }
+    }
+    fn on_disable(&mut self) {
+        self.ping_registered = false;
+        self.host.log(LogLevel::Info, "插件已停用");
+    }
+    fn on_unload(&mut self) {
+        self.host.log(LogLevel::Info, "插件已卸载");
+    }
+    fn save_state(&mut self) -> Option<Vec<u8>> {
+        Some(self.count.lock().to_le_bytes().to_vec())
+    }
+    fn restore_state(&mut self, state: Vec<u8>) {
+        if let Ok(count) = state.try_into() {
+            *self.count.lock() = i64::from_le_bytes(count);
+        }
+    }
+    fn window_types(&self) -> Vec<WindowType> {
+        vec![
+            WindowType::new("counter", "计数器"),
+            WindowType::new("chat", "聊天室"),
+        ]
     }
+    fn create_window(&mut self, id: &str) -> Option<Box<dyn PluginWindow>> {
+        match id {
+            "counter" => Some(Box::new(Counter::new(
+                self.host.clone(),
+                self.count.clone(),
+            ))),
+            "chat" => Some(Box::new(Chat::new(self.host.clone()))),
+            _ => None,
+        }
+    }
+}
+
+struct Counter {
+    host: Host,
+    count: Arc<Mutex<i64>>,
+    note: String,
+}
+impl Counter {
+    fn new(host: Host, count: Arc<Mutex<i64>>) -> Self {
+        Self {
+            note: host.setting("note").unwrap_or_default(),
+            host,
+            count,
+        }
+    }
+}
+impl PluginWindow for Counter {
+    fn update(&mut self, ui: &mut PluginUi) {
+        let mut count = self.count.lock();
+        ui.heading(format!("当前计数: {}", *count));
+        ui.horizontal(|ui| {
+            if ui.button("减一") {
+                *count -= 1;
+            }
+            if ui.button("加一") {
+                *count += 1;
+            }
+        });
+        ui.separator();
+        if ui.text_edit(&mut self.note) {
+            self.host.set_setting("note", &self.note);
+        }
+        ui.label(format!("备注: {}", self.note));
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button("提示") {
+                self.host
+                    .toast(ToastLevel::Success, format!("当前计数为 {}", *count));
+            }
+            if ui.button("后台任务") {
+                let host = self.host.clone();
+                self.host.spawn_cancellable(move |cancellation| {
+                    host.log(LogLevel::Info, "后台任务开始");
+                    #[cfg(not(target_family = "wasm"))]
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                    if cancellation.is_cancelled() {
+                        host.log(LogLevel::Info, "后台任务已取消");
+                        return;
+                    }
+                    host.toast(ToastLevel::Info, "后台任务完成");
+                });
+            }
+            if ui.button("再开一个") {
+                self.host.open_window("counter");
+            }
+            if ui.button("关闭") {
+                self.host.close_window(ui.window_id());
+            }
+        });
+    }
+}
+
+enum ChatOperation {
+    Ping(String),
+    Send(String),
+}
+
+struct Chat {
+    host: Host,
+    node_id: String,
+    peer: String,
+    message: String,
+    subscription: Option<SubscriptionId>,
+    messages: Vec<String>,
+    operations: Vec<(OperationId, ChatOperation)>,
+}
+impl Chat {
+    fn new(host: Host) -> Self {
+        let node_id = host
+            .p2p_node_id()
+            .unwrap_or_else(|err| format!("不可用: {}", err));
+        Self {
+            host,
+            node_id,
+            peer: String::new(),
+            message: String::new(),
+            subscription: None,
+            messages: vec![],
+            operations: vec![],
+        }
+    }
+    fn start(&mut self, operation: Result<OperationId, String>, kind: ChatOperation) {
+        match operation {
+            Ok(operation) => self.operations.push((operation, kind)),
+            Err(err) => self.host.toast(ToastLevel::Error, err),
+        }
+    }
+    fn poll_operations(&mut self) {
+        let mut pending = vec![];
+        for (operation, kind) in std::mem::take(&mut self.operations) {
+            let reply = match self.host.p2p_poll_operation(operation) {
+                Ok(None) => {
+                    pending.push((operation, kind));
+                    continue;
+                }
+                Ok(Some(reply)) => Ok(reply),
+                Err(err) => Err(err),
+            };
+            self.messages.push(match (kind, reply) {
+                (ChatOperation::Ping(peer), Ok(P2pReply::Data(data))) => format!(
+                    "{} 回复: {}",
+                    short_id(&peer),
+                    String::from_utf8_lossy(&data)
+                ),
+                (ChatOperation::Ping(_), Ok(_)) => "Ping 失败: 响应类型不匹配".to_string(),
+                (ChatOperation::Ping(_), Err(err)) => format!("Ping 失败: {}", err),
+                (ChatOperation::Send(message), Ok(_)) => format!("我: {}", message),
+                (ChatOperation::Send(message), Err(err)) => {
+                    format!("发送 {} 失败: {}", message, err)
+                }
+            });
+        }
+        self.operations = pending;
+    }
+    fn join(&mut self) {
+        let peers = match self.peer.trim() {
+            "" => vec![],
+            peer => vec![peer.to_string()],
+        };
+        if let Some(subscription) = self.subscription.take() {
+            _ = self.host.p2p_unsubscribe(subscription);
+        }
+        match self.host.p2p_subscribe("chat", peers) {
+            Ok(subscription) => self.subscription = Some(subscription),
+            Err(err) => self.host.toast(ToastLevel::Error, err),
+        }
+    }
+    fn poll(&mut self) {
+        let Some(subscription) = self.subscription else {
+            return;
+        };
+        let events = match self.host.p2p_poll_events(subscription) {
+            Ok(events) => events,
+            Err(err) => {
+                self.host.log(LogLevel::Warn, err);
+                return;
+            }
+        };
+        for event in events {
+            self.messages.push(match event {
+                P2pEvent::Message { from, data } => {
+                    format!("{}: {}", short_id(&from), String::from_utf8_lossy(&data))
+                }
+                P2pEvent::NeighborUp(node_id) => format!("{} 加入", short_id(&node_id)),
+                P2pEvent::NeighborDown(node_id) => format!("{} 离开", short_id(&node_id)),
+            });
+        }
+    }
+}
+impl Drop for Chat {
+    fn drop(&mut self) {
+        for (operation, _) in &self.operations {
+            _ = self.host.p2p_cancel_operation(*operation);
+        }
+        if let Some(subscription) = self.subscription {
+            _ = self.host.p2p_unsubscribe(subscription);
+        }
+    }
+}
+impl PluginWindow for Chat {
+    fn update(&mut self, ui: &mut PluginUi) {
+        self.poll();
+        self.poll_operations();
+        ui.label(format!("本机节点: {}", self.node_id));
+        ui.horizontal(|ui| {
+            ui.text_edit(&mut self.peer);
+            if ui.button("加入") {
+                self.join();
+            }
+            if ui.button("Ping") {
+                let peer = self.peer.trim().to_string();
+                let operation = self.host.p2p_request(&peer, "ping", b"ping".to_vec());
+                self.start(operation, ChatOperation::Ping(peer));
+            }
+        });
+        ui.separator();
+        for message in &self.messages {
+            ui.label(message);
+        }
+        ui.separator();
+        if let Some(subscription) = self.subscription {
+            ui.horizontal(|ui| {
+                ui.text_edit(&mut self.message);
+                if ui.button("发送") && !self.message.is_empty() {
+                    let message = std::mem::take(&mut self.message);
+                    let operation = self.host.p2p_broadcast(subscription, message.as_bytes());
+                    self.start(operation, ChatOperation::Send(message));
+                }
+            });
+        }
+    }
+}
+
+fn short_id(node_id: &str) -> &str {
+    node_id.get(..8).unwrap_or(node_id)
 }
 
-#[unsafe(no_mangle)]
-extern "C" fn instantiation() -> Box<Box<dyn PluginInterface>> {
-    Box::new(Box::new(Plugin {
-        info: PluginInfo {
-            name: "测试插件".into(),
-            version: Version::new(0, 1, 0),
-        },
-    }))
+export_plugin! {
+    name: "测试插件",
+    version: env!("CARGO_PKG_VERSION"),
+    host_requirement: "^0.7",
+    constructor: |host| Plugin {
+        host,
+        ping_registered: false,
+        count: Arc::new(Mutex::new(0)),
+    },
 }
@@ -0,0 +1,682 @@
+use std::{
+    any::Any,
+    ffi::c_void,
+    fmt,
+    marker::PhantomData,
+    panic::{AssertUnwindSafe, catch_unwind},
+};
+
+use semver::VersionReq;
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{
+    Lifecycle, PluginInfo, PluginInterface, Version,
+    host::{FfiHost, Host, LogLevel},
+    ui::{PluginUi, PluginWindow, UiEvent, Widget, WindowType},
+};
+
+pub const ABI_VERSION: u32 = 10;
+pub const ABI_VERSION_SYMBOL: &[u8] = b"plugin_abi_version";
+pub const METADATA_SYMBOL: &[u8] = b"plugin_metadata";
+pub const INSTANTIATION_SYMBOL: &[u8] = b"instantiation";
+
+pub type AbiVersionFn = extern "C" fn() -> u32;
+pub type InstantiationFn = extern "C" fn(FfiHost) -> FfiPlugin;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FfiStr {
+    ptr: *const u8,
+    len: usize,
+}
+impl FfiStr {
+    pub const fn new(str: &str) -> Self {
+        Self {
+            ptr: str.as_ptr(),
+            len: str.len(),
+        }
+    }
+    /// # Safety
+    /// 指针必须指向在 `'a` 内有效的 UTF-8 数据。
+    pub unsafe fn as_str<'a>(self) -> &'a str {
+        unsafe { std::str::from_utf8_unchecked(std::slice::from_raw_parts(self.ptr, self.len)) }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct PluginMetadata {
+    pub name: FfiStr,
+    pub version: FfiStr,
+    pub host_requirement: FfiStr,
+}
+unsafe impl Sync for PluginMetadata {}
+impl PluginMetadata {
+    pub const fn new(
+        name: &'static str,
+        version: &'static str,
+        host_requirement: &'static str,
+    ) -> Self {
+        Self {
+            name: FfiStr::new(name),
+            version: FfiStr::new(version),
+            host_requirement: FfiStr::new(host_requirement),
+        }
+    }
+}
+
+impl PluginInfo {
+    /// # Safety
+    /// `metadata` 中的字符串必须在调用期间有效。
+    pub unsafe fn from_metadata(metadata: &PluginMetadata) -> Result<Self, semver::Error> {
+        Ok(Self {
+            name: unsafe { metadata.name.as_str() }.to_string(),
+            version: Version::parse(unsafe { metadata.version.as_str() })?,
+            host_requirement: VersionReq::parse(unsafe { metadata.host_requirement.as_str() })?,
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FfiBytes {
+    ptr: *const u8,
+    len: usize,
+}
+impl FfiBytes {
+    pub fn new(bytes: &[u8]) -> Self {
+        Self {
+            ptr: bytes.as_ptr(),
+            len: bytes.len(),
+        }
+    }
+    /// # Safety
+    /// 指针必须指向在 `'a` 内有效的数据。
+    pub unsafe fn as_slice<'a>(self) -> &'a [u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+#[repr(C)]
+pub struct FfiBuffer {
+    ptr: *mut u8,
+    len: usize,
+    cap: usize,
+}
+impl FfiBuffer {
+    pub(crate) fn null() -> Self {
+        Self {
+            ptr: std::ptr::null_mut(),
+            len: 0,
+            cap: 0,
+        }
+    }
+    pub(crate) fn is_null(&self) -> bool {
+        self.ptr.is_null()
+    }
+    pub(crate) fn from_vec(bytes: Vec<u8>) -> Self {
+        let mut bytes = std::mem::ManuallyDrop::new(bytes);
+        Self {
+            ptr: bytes.as_mut_ptr(),
+            len: bytes.len(),
+            cap: bytes.capacity(),
+        }
+    }
+    pub(crate) unsafe fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+pub(crate) unsafe extern "C" fn free_buffer(buffer: FfiBuffer) {
+    drop(unsafe { Vec::from_raw_parts(buffer.ptr, buffer.len, buffer.cap) });
+}
+
+pub(crate) fn encode(value: &impl Serialize) -> FfiBuffer {
+    FfiBuffer::from_vec(postcard::to_stdvec(value).unwrap_or_default())
+}
+
+#[derive(Debug)]
+pub enum AbiError {
+    Panicked(String),
+    Decode(postcard::Error),
+    MissingVTable,
+}
+impl fmt::Display for AbiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Panicked(message) => write!(f, "插件发生 panic: {}", message),
+            Self::Decode(err) => write!(f, "插件数据无效: {}", err),
+            Self::MissingVTable => write!(f, "插件返回的窗口缺少函数表"),
+        }
+    }
+}
+impl std::error::Error for AbiError {}
+impl From<postcard::Error> for AbiError {
+    fn from(err: postcard::Error) -> Self {
+        Self::Decode(err)
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "未知错误".to_string(),
+        },
+    }
+}
+pub(crate) fn catch_panic<R>(f: impl FnOnce() -> R) -> Result<R, String> {
+    catch_unwind(AssertUnwindSafe(f)).map_err(panic_message)
+}
+pub(crate) fn encode_status(status: Result<(), String>) -> FfiBuffer {
+    match status {
+        Ok(()) => FfiBuffer::null(),
+        Err(message) => FfiBuffer::from_vec(message.into_bytes()),
+    }
+}
+/// # Safety
+/// `buffer` 必须由 [`encode_status`] 产生，且 `free_buffer` 与分配它的一侧匹配。
+pub(crate) unsafe fn decode_status(
+    buffer: FfiBuffer,
+    free_buffer: unsafe extern "C" fn(FfiBuffer),
+) -> Result<(), AbiError> {
+    if buffer.is_null() {
+        return Ok(());
+    }
+    let message = String::from_utf8_lossy(unsafe { buffer.as_slice() }).into_owned();
+    unsafe { free_buffer(buffer) };
+    Err(AbiError::Panicked(message))
+}
+fn encode_result<T: Serialize>(result: Result<T, String>) -> FfiBuffer {
+    encode(&result)
+}
+/// # Safety
+/// `buffer` 必须由 [`encode_result`] 产生，且 `free_buffer` 与分配它的一侧匹配。
+unsafe fn decode_result<T: DeserializeOwned>(
+    buffer: FfiBuffer,
+    free_buffer: unsafe extern "C" fn(FfiBuffer),
+) -> Result<T, AbiError> {
+    let result = postcard::from_bytes::<Result<T, String>>(unsafe { buffer.as_slice() });
+    unsafe { free_buffer(buffer) };
+    result?.map_err(AbiError::Panicked)
+}
+
+#[repr(C)]
+pub struct PluginVTable {
+    pub window_types: unsafe extern "C" fn(*const c_void) -> FfiBuffer,
+    pub create_window: unsafe extern "C" fn(*mut c_void, FfiStr, *mut FfiWindow) -> FfiBuffer,
+    pub lifecycle: unsafe extern "C" fn(*mut c_void, u32) -> FfiBuffer,
+    pub save_state: unsafe extern "C" fn(*mut c_void) -> FfiBuffer,
+    pub restore_state: unsafe extern "C" fn(*mut c_void, FfiBytes) -> FfiBuffer,
+    pub free_buffer: unsafe extern "C" fn(FfiBuffer),
+    pub drop: unsafe extern "C" fn(*mut c_void) -> FfiBuffer,
+}
+
+#[repr(C)]
+pub struct FfiPlugin {
+    data: *mut c_void,
+    vtable: *const PluginVTable,
+}
+impl FfiPlugin {
+    pub fn new<T: PluginInterface + 'static>(plugin: T) -> Self {
+        Self {
+            data: Box::into_raw(Box::new(plugin)).cast(),
+            vtable: &VTableFor::<T>::VTABLE,
+        }
+    }
+    /// # Safety
+    /// `host` 必须来自 ABI 版本与 [`ABI_VERSION`] 相同的宿主。
+    pub unsafe fn instantiate<T: PluginInterface + 'static>(
+        host: FfiHost,
+        constructor: impl FnOnce(Host) -> T,
+    ) -> Self {
+        let host = unsafe { Host::from_ffi(host) };
+        let reporter = host.clone();
+        match catch_panic(|| constructor(host)) {
+            Ok(plugin) => Self::new(plugin),
+            Err(message) => {
+                reporter.log(
+                    LogLevel::Error,
+                    format!("插件初始化时发生 panic: {}", message),
+                );
+                Self {
+                    data: std::ptr::null_mut(),
+                    vtable: std::ptr::null(),
+                }
+            }
+        }
+    }
+}
+
+struct VTableFor<T>(PhantomData<T>);
+impl<T: PluginInterface + 'static> VTableFor<T> {
+    const VTABLE: PluginVTable = PluginVTable {
+        window_types: window_types::<T>,
+        create_window: create_window::<T>,
+        lifecycle: lifecycle::<T>,
+        save_state: save_state::<T>,
+        restore_state: restore_state::<T>,
+        free_buffer,
+        drop: drop_plugin::<T>,
+    };
+}
+
+unsafe extern "C" fn window_types<T: PluginInterface>(data: *const c_void) -> FfiBuffer {
+    encode_result(catch_panic(|| unsafe { &*data.cast::<T>() }.window_types()))
+}
+unsafe extern "C" fn create_window<T: PluginInterface>(
+    data: *mut c_void,
+    id: FfiStr,
+    window: *mut FfiWindow,
+) -> FfiBuffer {
+    let plugin = unsafe { &mut *data.cast::<T>() };
+    let created = catch_panic(|| plugin.create_window(unsafe { id.as_str() }));
+    let status = created.map(|created| {
+        if let Some(created) = created {
+            unsafe {
+                (*window).data = Box::into_raw(Box::new(created)).cast();
+                (*window).vtable = &WINDOW_VTABLE;
+            }
+        }
+    });
+    encode_status(status)
+}
+unsafe extern "C" fn lifecycle<T: PluginInterface>(data: *mut c_void, lifecycle: u32) -> FfiBuffer {
+    encode_status(catch_panic(|| {
+        if let Some(lifecycle) = Lifecycle::from_u32(lifecycle) {
+            lifecycle.dispatch(unsafe { &mut *data.cast::<T>() });
+        }
+    }))
+}
+unsafe extern "C" fn save_state<T: PluginInterface>(data: *mut c_void) -> FfiBuffer {
+    encode_result(catch_panic(|| {
+        unsafe { &mut *data.cast::<T>() }.save_state()
+    }))
+}
+unsafe extern "C" fn restore_state<T: PluginInterface>(
+    data: *mut c_void,
+    state: FfiBytes,
+) -> FfiBuffer {
+    encode_status(catch_panic(|| {
+        unsafe { &mut *data.cast::<T>() }.restore_state(unsafe { state.as_slice() }.to_vec())
+    }))
+}
+unsafe extern "C" fn drop_plugin<T>(data: *mut c_void) -> FfiBuffer {
+    encode_status(catch_panic(|| {
+        drop(unsafe { Box::from_raw(data.cast::<T>()) })
+    }))
+}
+
+#[repr(C)]
+pub struct WindowVTable {
+    pub update: unsafe extern "C" fn(*mut c_void, u64, FfiBytes) -> FfiBuffer,
+    pub free_buffer: unsafe extern "C" fn(FfiBuffer),
+    pub drop: unsafe extern "C" fn(*mut c_void) -> FfiBuffer,
+}
+
+#[repr(C)]
+pub struct FfiWindow {
+    data: *mut c_void,
+    vtable: *const WindowVTable,
+}
+
+static WINDOW_VTABLE: WindowVTable = WindowVTable {
+    update: update_window,
+    free_buffer,
+    drop: drop_window,
+};
+
+unsafe extern "C" fn update_window(
+    data: *mut c_void,
+    window_id: u64,
+    events: FfiBytes,
+) -> FfiBuffer {
+    encode_result(catch_panic(|| {
+        let window = unsafe { &mut *data.cast::<Box<dyn PluginWindow>>() };
+        let mut ui = PluginUi::new(
+            window_id,
+            postcard::from_bytes(unsafe { events.as_slice() }).unwrap_or_default(),
+        );
+        window.update(&mut ui);
+        ui.into_widgets()
+    }))
+}
+unsafe extern "C" fn drop_window(data: *mut c_void) -> FfiBuffer {
+    encode_status(catch_panic(|| {
+        drop(unsafe { Box::from_raw(data.cast::<Box<dyn PluginWindow>>()) })
+    }))
+}
+
+pub struct PluginHandle {
+    raw: FfiPlugin,
+}
+impl PluginHandle {
+    /// # Safety
+    /// `raw` 必须来自 ABI 版本与 [`ABI_VERSION`] 相同的插件，且插件库在句柄存活期间保持加载。
+    pub unsafe fn from_ffi(raw: FfiPlugin) -> Option<Self> {
+        if raw.data.is_null() || raw.vtable.is_null() {
+            return None;
+        }
+        Some(Self { raw })
+    }
+    fn vtable(&self) -> &PluginVTable {
+        unsafe { &*self.raw.vtable }
+    }
+    pub fn window_types(&self) -> Result<Vec<WindowType>, AbiError> {
+        let vtable = self.vtable();
+        unsafe { decode_result((vtable.window_types)(self.raw.data), vtable.free_buffer) }
+    }
+    pub fn create_window(&mut self, id: &str) -> Result<Option<WindowHandle>, AbiError> {
+        let vtable = self.vtable();
+        let mut raw = FfiWindow {
+            data: std::ptr::null_mut(),
+            vtable: std::ptr::null(),
+        };
+        unsafe {
+            decode_status(
+                (vtable.create_window)(self.raw.data, FfiStr::new(id), &mut raw),
+                vtable.free_buffer,
+            )?
+        };
+        if raw.data.is_null() {
+            return Ok(None);
+        }
+        if raw.vtable.is_null() {
+            return Err(AbiError::MissingVTable);
+        }
+        Ok(Some(WindowHandle { raw }))
+    }
+    pub fn lifecycle(&mut self, lifecycle: Lifecycle) -> Result<(), AbiError> {
+        let vtable = self.vtable();
+        unsafe {
+            decode_status(
+                (vtable.lifecycle)(self.raw.data, lifecycle as u32),
+                vtable.free_buffer,
+            )
+        }
+    }
+    pub fn save_state(&mut self) -> Result<Option<Vec<u8>>, AbiError> {
+        let vtable = self.vtable();
+        unsafe { decode_result((vtable.save_state)(self.raw.data), vtable.free_buffer) }
+    }
+    pub fn restore_state(&mut self, state: &[u8]) -> Result<(), AbiError> {
+        let vtable = self.vtable();
+        unsafe {
+            decode_status(
+                (vtable.restore_state)(self.raw.data, FfiBytes::new(state)),
+                vtable.free_buffer,
+            )
+        }
+    }
+}
+impl Drop for PluginHandle {
+    fn drop(&mut self) {
+        let vtable = self.vtable();
+        if let Err(err) = unsafe { decode_status((vtable.drop)(self.raw.data), vtable.free_buffer) }
+        {
+            log::error!("释放插件失败: {}", err);
+        }
+    }
+}
+
+pub struct WindowHandle {
+    raw: FfiWindow,
+}
+impl WindowHandle {
+    fn vtable(&self) -> &WindowVTable {
+        unsafe { &*self.raw.vtable }
+    }
+    pub fn update(&mut self, window_id: u64, events: &[UiEvent]) -> Result<Vec<Widget>, AbiError> {
+        let events = postcard::to_stdvec(events)?;
+        let vtable = self.vtable();
+        unsafe {
+            decode_result(
+                (vtable.update)(self.raw.data, window_id, FfiBytes::new(&events)),
+                vtable.free_buffer,
+            )
+        }
+    }
+}
+impl Drop for WindowHandle {
+    fn drop(&mut self) {
+        let vtable = self.vtable();
+        if let Err(err) = unsafe { decode_status((vtable.drop)(self.raw.data), vtable.free_buffer) }
+        {
+            log::error!("释放插件窗口失败: {}", err);
+        }
+    }
+}
+
+#[cfg(not(all(feature = "wasm-guest", target_family = "wasm")))]
+#[macro_export]
+macro_rules! export_plugin {
+    (
+        name: $name:expr,
+        version: $version:expr,
+        host_requirement: $host_requirement:expr,
+        constructor: $constructor:expr $(,)?
+    ) => {
+        #[unsafe(no_mangle)]
+        extern "C" fn plugin_abi_version() -> u32 {
+            $crate::ABI_VERSION
+        }
+        #[unsafe(no_mangle)]
+        #[allow(non_upper_case_globals)]
+        static plugin_metadata: $crate::PluginMetadata =
+            $crate::PluginMetadata::new($name, $version, $host_requirement);
+        #[unsafe(no_mangle)]
+        extern "C" fn instantiation(host: $crate::FfiHost) -> $crate::FfiPlugin {
+            unsafe { $crate::FfiPlugin::instantiate(host, $constructor) }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ptr::NonNull,
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use super::*;
+    use crate::{Cancellation, HostServices, P2pCall, P2pReply, PluginTask, ToastLevel};
+
+    #[derive(Default)]
+    struct TestHost {
+        logs: Mutex<Vec<String>>,
+    }
+    impl HostServices for TestHost {
+        fn toast(&self, _level: ToastLevel, _text: &str) {}
+        fn open_url(&self, _url: &str) -> bool {
+            panic!("open_url");
+        }
+        fn log(&self, _level: LogLevel, message: &str) {
+            self.logs.lock().unwrap().push(message.to_string());
+        }
+        fn setting(&self, _key: &str) -> Option<String> {
+            None
+        }
+        fn set_setting(&self, _key: &str, _value: &str) {}
+        fn remove_setting(&self, _key: &str) {}
+        fn open_window(&self, _window_type: &str) {}
+        fn close_window(&self, _window_id: u64) {}
+        fn spawn(&self, task: PluginTask) {
+            let cancellation = Cancellation::default();
+            cancellation.cancel();
+            if let Err(err) = task.run(&cancellation) {
+                self.logs.lock().unwrap().push(err.to_string());
+            }
+        }
+        fn p2p(&self, _call: P2pCall) -> Result<P2pReply, String> {
+            panic!("p2p");
+        }
+    }
+
+    struct PanicWindow;
+    impl PluginWindow for PanicWindow {
+        fn update(&mut self, _ui: &mut PluginUi) {
+            panic!("update");
+        }
+    }
+
+    struct PanicPlugin;
+    impl PluginInterface for PanicPlugin {
+        fn on_load(&mut self) {
+            panic!("load");
+        }
+        fn save_state(&mut self) -> Option<Vec<u8>> {
+            Some(vec![1, 2, 3])
+        }
+        fn restore_state(&mut self, _state: Vec<u8>) {
+            panic!("restore");
+        }
+        fn window_types(&self) -> Vec<WindowType> {
+            vec![WindowType::new("main", "Main")]
+        }
+        fn create_window(&mut self, id: &str) -> Option<Box<dyn PluginWindow>> {
+            match id {
+                "main" => Some(Box::new(PanicWindow)),
+                "missing" => None,
+                _ => panic!("create"),
+            }
+        }
+    }
+
+    fn panicked(err: AbiError) -> String {
+        match err {
+            AbiError::Panicked(message) => message,
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn plugin_panics_are_reported_to_the_host() {
+        let mut plugin = unsafe { PluginHandle::from_ffi(FfiPlugin::new(PanicPlugin)) }.unwrap();
+        assert_eq!(
+            panicked(plugin.lifecycle(Lifecycle::Load).unwrap_err()),
+            "load"
+        );
+        plugin.lifecycle(Lifecycle::Enable).unwrap();
+        assert_eq!(panicked(plugin.restore_state(&[1]).unwrap_err()), "restore");
+        assert_eq!(plugin.save_state().unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(plugin.window_types().unwrap()[0].id, "main");
+        assert!(plugin.create_window("missing").unwrap().is_none());
+        assert_eq!(
+            panicked(plugin.create_window("other").err().unwrap()),
+            "create"
+        );
+        let mut window = plugin.create_window("main").unwrap().unwrap();
+        assert_eq!(panicked(window.update(1, &[]).unwrap_err()), "update");
+    }
+
+    #[test]
+    fn constructor_panic_yields_no_plugin() {
+        let host = Arc::new(TestHost::default());
+        let raw = unsafe {
+            FfiPlugin::instantiate(FfiHost::new(host.clone()), |_host| -> PanicPlugin {
+                panic!("constructor")
+            })
+        };
+        assert!(unsafe { PluginHandle::from_ffi(raw) }.is_none());
+        assert_eq!(
+            *host.logs.lock().unwrap(),
+            ["插件初始化时发生 panic: constructor"]
+        );
+        assert_eq!(Arc::strong_count(&host), 1);
+    }
+
+    #[test]
+    fn host_panics_do_not_unwind_into_the_plugin() {
+        let host = Arc::new(TestHost::default());
+        let plugin_host = unsafe { Host::from_ffi(FfiHost::new(host.clone())) };
+        assert!(!plugin_host.open_url("https://example.com"));
+        let err = plugin_host.p2p(&P2pCall::NodeId).unwrap_err();
+        assert!(err.contains("p2p"), "{}", err);
+        plugin_host.spawn(|| panic!("task"));
+        assert_eq!(*host.logs.lock().unwrap(), ["插件发生 panic: task"]);
+    }
+
+    #[test]
+    fn tasks_observe_host_cancellation() {
+        let host = Arc::new(TestHost::default());
+        let plugin_host = unsafe { Host::from_ffi(FfiHost::new(host.clone())) };
+        let observed = Arc::new(AtomicUsize::new(0));
+        plugin_host.spawn_cancellable({
+            let observed = observed.clone();
+            move |cancellation| {
+                if cancellation.is_cancelled() {
+                    observed.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+        assert_eq!(observed.load(Ordering::SeqCst), 1);
+        assert!(host.logs.lock().unwrap().is_empty());
+    }
+
+    static UPDATES: AtomicUsize = AtomicUsize::new(0);
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    static TEST_WINDOW_VTABLE: WindowVTable = WindowVTable {
+        update: test_update,
+        free_buffer,
+        drop: test_drop,
+    };
+
+    unsafe extern "C" fn test_update(
+        _data: *mut c_void,
+        _window_id: u64,
+        _events: FfiBytes,
+    ) -> FfiBuffer {
+        UPDATES.fetch_add(1, Ordering::SeqCst);
+        encode_result::<Vec<Widget>>(Ok(vec![]))
+    }
+    unsafe extern "C" fn test_drop(_data: *mut c_void) -> FfiBuffer {
+        DROPS.fetch_add(1, Ordering::SeqCst);
+        encode_status(Ok(()))
+    }
+
+    unsafe extern "C" fn test_create_window(
+        _data: *mut c_void,
+        id: FfiStr,
+        window: *mut FfiWindow,
+    ) -> FfiBuffer {
+        unsafe {
+            (*window).data = NonNull::<u8>::dangling().as_ptr().cast();
+            if id.as_str() == "own" {
+                (*window).vtable = &TEST_WINDOW_VTABLE;
+            }
+        }
+        encode_status(Ok(()))
+    }
+
+    fn test_plugin() -> FfiPlugin {
+        let raw = FfiPlugin::new(PanicPlugin);
+        let vtable = Box::leak(Box::new(PluginVTable {
+            create_window: test_create_window,
+            ..VTableFor::<PanicPlugin>::VTABLE
+        }));
+        FfiPlugin {
+            data: raw.data,
+            vtable,
+        }
+    }
+
+    #[test]
+    fn windows_use_the_vtable_provided_by_the_plugin() {
+        let mut plugin = unsafe { PluginHandle::from_ffi(test_plugin()) }.unwrap();
+        let mut window = plugin.create_window("own").unwrap().unwrap();
+        assert!(std::ptr::eq(window.raw.vtable, &TEST_WINDOW_VTABLE));
+        assert!(window.update(1, &[]).unwrap().is_empty());
+        assert_eq!(UPDATES.load(Ordering::SeqCst), 1);
+        drop(window);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            plugin.create_window("missing-vtable").err().unwrap(),
+            AbiError::MissingVTable
+        ));
+    }
+}
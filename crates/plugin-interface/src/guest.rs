@@ -0,0 +1,186 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    sync::Arc,
+};
+
+use crate::{
+    Cancellation, FfiHost, Host, HostServices, Lifecycle, LogLevel, P2pCall, P2pReply,
+    PluginInterface, PluginTask, PluginUi, PluginWindow, ToastLevel, Widget,
+};
+
+pub mod bindings {
+    wit_bindgen::generate!({
+        path: "wit",
+        world: "plugin",
+        pub_export_macro: true,
+        export_macro_name: "export_guest",
+        default_bindings_module: "plugin_interface::guest::bindings",
+    });
+}
+
+use bindings::starlink::plugin::host as wit_host;
+
+struct WitHost;
+impl HostServices for WitHost {
+    fn toast(&self, level: ToastLevel, text: &str) {
+        let level = match level {
+            ToastLevel::Info => wit_host::ToastLevel::Info,
+            ToastLevel::Success => wit_host::ToastLevel::Success,
+            ToastLevel::Warning => wit_host::ToastLevel::Warning,
+            ToastLevel::Error => wit_host::ToastLevel::Error,
+        };
+        wit_host::toast(level, text);
+    }
+    fn open_url(&self, url: &str) -> bool {
+        wit_host::open_url(url)
+    }
+    fn log(&self, level: LogLevel, message: &str) {
+        let level = match level {
+            LogLevel::Error => wit_host::LogLevel::Error,
+            LogLevel::Warn => wit_host::LogLevel::Warn,
+            LogLevel::Info => wit_host::LogLevel::Info,
+            LogLevel::Debug => wit_host::LogLevel::Debug,
+            LogLevel::Trace => wit_host::LogLevel::Trace,
+        };
+        wit_host::log(level, message);
+    }
+    fn setting(&self, key: &str) -> Option<String> {
+        wit_host::setting(key)
+    }
+    fn set_setting(&self, key: &str, value: &str) {
+        wit_host::set_setting(key, value);
+    }
+    fn remove_setting(&self, key: &str) {
+        wit_host::remove_setting(key);
+    }
+    fn open_window(&self, window_type: &str) {
+        wit_host::open_window(window_type);
+    }
+    fn close_window(&self, window_id: u64) {
+        wit_host::close_window(window_id);
+    }
+    fn spawn(&self, task: PluginTask) {
+        if let Err(err) = task.run(&Cancellation::default()) {
+            self.log(LogLevel::Error, &err.to_string());
+        }
+    }
+    fn p2p(&self, call: P2pCall) -> Result<P2pReply, String> {
+        let call = postcard::to_stdvec(&call).map_err(|err| err.to_string())?;
+        let reply = wit_host::p2p(&call)?;
+        postcard::from_bytes(&reply).map_err(|err| format!("无法解析 P2P 响应: {}", err))
+    }
+}
+
+thread_local! {
+    static PLUGIN: RefCell<Option<Box<dyn PluginInterface>>> = const { RefCell::new(None) };
+    static WINDOWS: RefCell<HashMap<u64, Box<dyn PluginWindow>>> = RefCell::new(HashMap::new());
+    static NEXT_WINDOW: Cell<u64> = const { Cell::new(1) };
+}
+
+pub fn host() -> Host {
+    unsafe { Host::from_ffi(FfiHost::new(Arc::new(WitHost))) }
+}
+pub fn instantiate(plugin: impl PluginInterface + 'static) {
+    PLUGIN.with_borrow_mut(|slot| *slot = Some(Box::new(plugin)));
+}
+pub fn lifecycle(lifecycle: bindings::Lifecycle) {
+    let lifecycle = match lifecycle {
+        bindings::Lifecycle::Load => Lifecycle::Load,
+        bindings::Lifecycle::Enable => Lifecycle::Enable,
+        bindings::Lifecycle::Update => Lifecycle::Update,
+        bindings::Lifecycle::Disable => Lifecycle::Disable,
+        bindings::Lifecycle::Unload => Lifecycle::Unload,
+    };
+    PLUGIN.with_borrow_mut(|plugin| {
+        if let Some(plugin) = plugin {
+            lifecycle.dispatch(plugin.as_mut());
+        }
+    });
+}
+pub fn save_state() -> Option<Vec<u8>> {
+    PLUGIN.with_borrow_mut(|plugin| plugin.as_mut()?.save_state())
+}
+pub fn restore_state(state: Vec<u8>) {
+    PLUGIN.with_borrow_mut(|plugin| {
+        if let Some(plugin) = plugin {
+            plugin.restore_state(state);
+        }
+    });
+}
+pub fn window_types() -> Vec<bindings::WindowType> {
+    PLUGIN.with_borrow(|plugin| {
+        plugin
+            .iter()
+            .flat_map(|plugin| plugin.window_types())
+            .map(|window_type| bindings::WindowType {
+                id: window_type.id,
+                title: window_type.title,
+            })
+            .collect()
+    })
+}
+pub fn create_window(id: &str) -> Option<u64> {
+    let window = PLUGIN.with_borrow_mut(|plugin| plugin.as_mut()?.create_window(id))?;
+    let handle = NEXT_WINDOW.replace(NEXT_WINDOW.get() + 1);
+    WINDOWS.with_borrow_mut(|windows| windows.insert(handle, window));
+    Some(handle)
+}
+pub fn update_window(handle: u64, window_id: u64, events: &[u8]) -> Vec<u8> {
+    let Some(mut window) = WINDOWS.with_borrow_mut(|windows| windows.remove(&handle)) else {
+        return postcard::to_stdvec(&Vec::<Widget>::new()).unwrap_or_default();
+    };
+    let mut ui = PluginUi::new(window_id, postcard::from_bytes(events).unwrap_or_default());
+    window.update(&mut ui);
+    WINDOWS.with_borrow_mut(|windows| windows.insert(handle, window));
+    postcard::to_stdvec(&ui.into_widgets()).unwrap_or_default()
+}
+pub fn drop_window(handle: u64) {
+    WINDOWS.with_borrow_mut(|windows| windows.remove(&handle));
+}
+
+#[macro_export]
+macro_rules! export_plugin {
+    (
+        name: $name:expr,
+        version: $version:expr,
+        host_requirement: $host_requirement:expr,
+        constructor: $constructor:expr $(,)?
+    ) => {
+        struct PluginComponent;
+        impl $crate::guest::bindings::Guest for PluginComponent {
+            fn metadata() -> $crate::guest::bindings::PluginMetadata {
+                $crate::guest::bindings::PluginMetadata {
+                    name: $name.to_string(),
+                    version: $version.to_string(),
+                    host_requirement: $host_requirement.to_string(),
+                }
+            }
+            fn instantiate() {
+                $crate::guest::instantiate($constructor($crate::guest::host()));
+            }
+            fn lifecycle(lifecycle: $crate::guest::bindings::Lifecycle) {
+                $crate::guest::lifecycle(lifecycle);
+            }
+            fn save_state() -> Option<Vec<u8>> {
+                $crate::guest::save_state()
+            }
+            fn restore_state(state: Vec<u8>) {
+                $crate::guest::restore_state(state);
+            }
+            fn window_types() -> Vec<$crate::guest::bindings::WindowType> {
+                $crate::guest::window_types()
+            }
+            fn create_window(id: String) -> Option<u64> {
+                $crate::guest::create_window(&id)
+            }
+            fn update_window(handle: u64, window_id: u64, events: Vec<u8>) -> Vec<u8> {
+                $crate::guest::update_window(handle, window_id, &events)
+            }
+            fn drop_window(handle: u64) {
+                $crate::guest::drop_window(handle)
+            }
+        }
+        $crate::guest::bindings::export_guest!(PluginComponent with_types_in $crate::guest::bindings);
+    };
+}
@@ -0,0 +1,438 @@
+use std::{
+    ffi::c_void,
+    marker::PhantomData,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use crate::{
+    abi::{
+        AbiError, FfiBuffer, FfiBytes, FfiStr, catch_panic, decode_status, encode, encode_status,
+        free_buffer,
+    },
+    p2p::{OperationId, P2pCall, P2pEvent, P2pReply, P2pRequest, RequestId, SubscriptionId},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToastLevel {
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+impl ToastLevel {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            1 => Self::Success,
+            2 => Self::Warning,
+            3 => Self::Error,
+            _ => Self::Info,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+impl LogLevel {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            0 => Self::Error,
+            1 => Self::Warn,
+            3 => Self::Debug,
+            4 => Self::Trace,
+            _ => Self::Info,
+        }
+    }
+}
+
+pub trait HostServices: Send + Sync {
+    fn toast(&self, level: ToastLevel, text: &str);
+    fn open_url(&self, url: &str) -> bool;
+    fn log(&self, level: LogLevel, message: &str);
+    fn setting(&self, key: &str) -> Option<String>;
+    fn set_setting(&self, key: &str, value: &str);
+    fn remove_setting(&self, key: &str);
+    fn open_window(&self, window_type: &str);
+    fn close_window(&self, window_id: u64);
+    fn spawn(&self, task: PluginTask);
+    fn p2p(&self, call: P2pCall) -> Result<P2pReply, String>;
+}
+
+#[repr(transparent)]
+#[derive(Debug, Default)]
+pub struct Cancellation(AtomicBool);
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+#[repr(C)]
+pub struct FfiTask {
+    data: *mut c_void,
+    run: unsafe extern "C" fn(*mut c_void, *const Cancellation) -> FfiBuffer,
+    drop: unsafe extern "C" fn(*mut c_void) -> FfiBuffer,
+    free_buffer: unsafe extern "C" fn(FfiBuffer),
+}
+
+type BoxedTask = Box<dyn FnOnce(&Cancellation) + Send>;
+
+unsafe extern "C" fn run_task(data: *mut c_void, cancellation: *const Cancellation) -> FfiBuffer {
+    encode_status(catch_panic(|| {
+        (unsafe { Box::from_raw(data.cast::<BoxedTask>()) })(unsafe { &*cancellation })
+    }))
+}
+unsafe extern "C" fn drop_task(data: *mut c_void) -> FfiBuffer {
+    encode_status(catch_panic(|| {
+        drop(unsafe { Box::from_raw(data.cast::<BoxedTask>()) })
+    }))
+}
+
+pub struct PluginTask {
+    raw: FfiTask,
+}
+unsafe impl Send for PluginTask {}
+impl PluginTask {
+    pub fn run(self, cancellation: &Cancellation) -> Result<(), AbiError> {
+        let task = std::mem::ManuallyDrop::new(self);
+        unsafe {
+            decode_status(
+                (task.raw.run)(task.raw.data, cancellation),
+                task.raw.free_buffer,
+            )
+        }
+    }
+}
+impl Drop for PluginTask {
+    fn drop(&mut self) {
+        if let Err(err) =
+            unsafe { decode_status((self.raw.drop)(self.raw.data), self.raw.free_buffer) }
+        {
+            log::error!("释放插件任务失败: {}", err);
+        }
+    }
+}
+
+#[repr(C)]
+pub struct HostVTable {
+    pub toast: unsafe extern "C" fn(*const c_void, u32, FfiStr),
+    pub open_url: unsafe extern "C" fn(*const c_void, FfiStr) -> bool,
+    pub log: unsafe extern "C" fn(*const c_void, u32, FfiStr),
+    pub setting: unsafe extern "C" fn(*const c_void, FfiStr) -> FfiBuffer,
+    pub set_setting: unsafe extern "C" fn(*const c_void, FfiStr, FfiStr),
+    pub remove_setting: unsafe extern "C" fn(*const c_void, FfiStr),
+    pub open_window: unsafe extern "C" fn(*const c_void, FfiStr),
+    pub close_window: unsafe extern "C" fn(*const c_void, u64),
+    pub spawn: unsafe extern "C" fn(*const c_void, FfiTask),
+    pub p2p: unsafe extern "C" fn(*const c_void, FfiBytes) -> FfiBuffer,
+    pub free_buffer: unsafe extern "C" fn(FfiBuffer),
+    pub clone: unsafe extern "C" fn(*const c_void),
+    pub drop: unsafe extern "C" fn(*const c_void),
+}
+
+#[repr(C)]
+pub struct FfiHost {
+    data: *const c_void,
+    vtable: *const HostVTable,
+}
+impl FfiHost {
+    pub fn new<T: HostServices + 'static>(services: Arc<T>) -> Self {
+        Self {
+            data: Arc::into_raw(services).cast(),
+            vtable: &HostVTableFor::<T>::VTABLE,
+        }
+    }
+}
+
+struct HostVTableFor<T>(PhantomData<T>);
+impl<T: HostServices + 'static> HostVTableFor<T> {
+    const VTABLE: HostVTable = HostVTable {
+        toast: host_toast::<T>,
+        open_url: host_open_url::<T>,
+        log: host_log::<T>,
+        setting: host_setting::<T>,
+        set_setting: host_set_setting::<T>,
+        remove_setting: host_remove_setting::<T>,
+        open_window: host_open_window::<T>,
+        close_window: host_close_window::<T>,
+        spawn: host_spawn::<T>,
+        p2p: host_p2p::<T>,
+        free_buffer,
+        clone: host_clone::<T>,
+        drop: host_drop::<T>,
+    };
+}
+
+unsafe fn services<'a, T>(data: *const c_void) -> &'a T {
+    unsafe { &*data.cast::<T>() }
+}
+unsafe extern "C" fn host_toast<T: HostServices>(data: *const c_void, level: u32, text: FfiStr) {
+    _ = catch_panic(|| unsafe {
+        services::<T>(data).toast(ToastLevel::from_u32(level), text.as_str())
+    });
+}
+unsafe extern "C" fn host_open_url<T: HostServices>(data: *const c_void, url: FfiStr) -> bool {
+    catch_panic(|| unsafe { services::<T>(data).open_url(url.as_str()) }).unwrap_or(false)
+}
+unsafe extern "C" fn host_log<T: HostServices>(data: *const c_void, level: u32, message: FfiStr) {
+    _ = catch_panic(|| unsafe {
+        services::<T>(data).log(LogLevel::from_u32(level), message.as_str())
+    });
+}
+unsafe extern "C" fn host_setting<T: HostServices>(data: *const c_void, key: FfiStr) -> FfiBuffer {
+    match catch_panic(|| unsafe { services::<T>(data).setting(key.as_str()) }) {
+        Ok(Some(value)) => FfiBuffer::from_vec(value.into_bytes()),
+        Ok(None) | Err(_) => FfiBuffer::null(),
+    }
+}
+unsafe extern "C" fn host_set_setting<T: HostServices>(
+    data: *const c_void,
+    key: FfiStr,
+    value: FfiStr,
+) {
+    _ = catch_panic(|| unsafe { services::<T>(data).set_setting(key.as_str(), value.as_str()) });
+}
+unsafe extern "C" fn host_remove_setting<T: HostServices>(data: *const c_void, key: FfiStr) {
+    _ = catch_panic(|| unsafe { services::<T>(data).remove_setting(key.as_str()) });
+}
+unsafe extern "C" fn host_open_window<T: HostServices>(data: *const c_void, window_type: FfiStr) {
+    _ = catch_panic(|| unsafe { services::<T>(data).open_window(window_type.as_str()) });
+}
+unsafe extern "C" fn host_close_window<T: HostServices>(data: *const c_void, window_id: u64) {
+    _ = catch_panic(|| unsafe { services::<T>(data).close_window(window_id) });
+}
+unsafe extern "C" fn host_spawn<T: HostServices>(data: *const c_void, task: FfiTask) {
+    _ = catch_panic(|| unsafe { services::<T>(data).spawn(PluginTask { raw: task }) });
+}
+unsafe extern "C" fn host_p2p<T: HostServices>(data: *const c_void, call: FfiBytes) -> FfiBuffer {
+    let reply = catch_panic(|| match postcard::from_bytes(unsafe { call.as_slice() }) {
+        Ok(call) => unsafe { services::<T>(data).p2p(call) },
+        Err(err) => Err(format!("无法解析 P2P 调用: {}", err)),
+    })
+    .unwrap_or_else(|message| Err(format!("宿主处理 P2P 调用时发生 panic: {}", message)));
+    encode(&reply)
+}
+unsafe extern "C" fn host_clone<T>(data: *const c_void) {
+    unsafe { Arc::increment_strong_count(data.cast::<T>()) }
+}
+unsafe extern "C" fn host_drop<T>(data: *const c_void) {
+    _ = catch_panic(|| unsafe { Arc::decrement_strong_count(data.cast::<T>()) });
+}
+
+pub struct Host {
+    raw: FfiHost,
+}
+unsafe impl Send for Host {}
+unsafe impl Sync for Host {}
+impl Host {
+    /// # Safety
+    /// `raw` 必须来自 ABI 版本与 [`crate::ABI_VERSION`] 相同的宿主。
+    pub unsafe fn from_ffi(raw: FfiHost) -> Self {
+        Self { raw }
+    }
+    fn vtable(&self) -> &HostVTable {
+        unsafe { &*self.raw.vtable }
+    }
+    pub fn toast(&self, level: ToastLevel, text: impl AsRef<str>) {
+        unsafe { (self.vtable().toast)(self.raw.data, level as u32, FfiStr::new(text.as_ref())) }
+    }
+    pub fn open_url(&self, url: impl AsRef<str>) -> bool {
+        unsafe { (self.vtable().open_url)(self.raw.data, FfiStr::new(url.as_ref())) }
+    }
+    pub fn log(&self, level: LogLevel, message: impl AsRef<str>) {
+        unsafe { (self.vtable().log)(self.raw.data, level as u32, FfiStr::new(message.as_ref())) }
+    }
+    pub fn setting(&self, key: impl AsRef<str>) -> Option<String> {
+        unsafe {
+            let buffer = (self.vtable().setting)(self.raw.data, FfiStr::new(key.as_ref()));
+            if buffer.is_null() {
+                return None;
+            }
+            let value = String::from_utf8_lossy(buffer.as_slice()).into_owned();
+            (self.vtable().free_buffer)(buffer);
+            Some(value)
+        }
+    }
+    pub fn set_setting(&self, key: impl AsRef<str>, value: impl AsRef<str>) {
+        unsafe {
+            (self.vtable().set_setting)(
+                self.raw.data,
+                FfiStr::new(key.as_ref()),
+                FfiStr::new(value.as_ref()),
+            )
+        }
+    }
+    pub fn remove_setting(&self, key: impl AsRef<str>) {
+        unsafe { (self.vtable().remove_setting)(self.raw.data, FfiStr::new(key.as_ref())) }
+    }
+    pub fn open_window(&self, window_type: impl AsRef<str>) {
+        unsafe { (self.vtable().open_window)(self.raw.data, FfiStr::new(window_type.as_ref())) }
+    }
+    pub fn close_window(&self, window_id: u64) {
+        unsafe { (self.vtable().close_window)(self.raw.data, window_id) }
+    }
+    pub fn spawn(&self, task: impl FnOnce() + Send + 'static) {
+        self.spawn_cancellable(move |_| task());
+    }
+    pub fn spawn_cancellable(&self, task: impl FnOnce(&Cancellation) + Send + 'static) {
+        let task: BoxedTask = Box::new(task);
+        unsafe {
+            (self.vtable().spawn)(
+                self.raw.data,
+                FfiTask {
+                    data: Box::into_raw(Box::new(task)).cast(),
+                    run: run_task,
+                    drop: drop_task,
+                    free_buffer,
+                },
+            )
+        }
+    }
+    pub fn p2p(&self, call: &P2pCall) -> Result<P2pReply, String> {
+        let call = postcard::to_stdvec(call).map_err(|err| err.to_string())?;
+        unsafe {
+            let buffer = (self.vtable().p2p)(self.raw.data, FfiBytes::new(&call));
+            let reply = postcard::from_bytes(buffer.as_slice());
+            (self.vtable().free_buffer)(buffer);
+            reply.map_err(|err| format!("无法解析 P2P 响应: {}", err))?
+        }
+    }
+    fn p2p_expect<T>(
+        &self,
+        call: P2pCall,
+        f: impl FnOnce(P2pReply) -> Option<T>,
+    ) -> Result<T, String> {
+        f(self.p2p(&call)?).ok_or_else(|| "P2P 响应类型不匹配".to_string())
+    }
+    pub fn p2p_node_id(&self) -> Result<String, String> {
+        self.p2p_expect(P2pCall::NodeId, |reply| match reply {
+            P2pReply::NodeId(node_id) => Some(node_id),
+            _ => None,
+        })
+    }
+    pub fn p2p_subscribe(
+        &self,
+        topic: impl Into<String>,
+        peers: Vec<String>,
+    ) -> Result<SubscriptionId, String> {
+        let call = P2pCall::Subscribe {
+            topic: topic.into(),
+            peers,
+        };
+        self.p2p_expect(call, |reply| match reply {
+            P2pReply::Subscription(subscription) => Some(subscription),
+            _ => None,
+        })
+    }
+    fn p2p_operation(&self, call: P2pCall) -> Result<OperationId, String> {
+        self.p2p_expect(call, |reply| match reply {
+            P2pReply::Operation(operation) => Some(operation),
+            _ => None,
+        })
+    }
+    pub fn p2p_poll_operation(&self, operation: OperationId) -> Result<Option<P2pReply>, String> {
+        match self.p2p(&P2pCall::PollOperation { operation })? {
+            P2pReply::Pending => Ok(None),
+            reply => Ok(Some(reply)),
+        }
+    }
+    pub fn p2p_cancel_operation(&self, operation: OperationId) -> Result<(), String> {
+        self.p2p_expect(P2pCall::CancelOperation { operation }, |reply| {
+            matches!(reply, P2pReply::Done).then_some(())
+        })
+    }
+    pub fn p2p_broadcast(
+        &self,
+        subscription: SubscriptionId,
+        data: impl Into<Vec<u8>>,
+    ) -> Result<OperationId, String> {
+        self.p2p_operation(P2pCall::Broadcast {
+            subscription,
+            data: data.into(),
+        })
+    }
+    pub fn p2p_poll_events(&self, subscription: SubscriptionId) -> Result<Vec<P2pEvent>, String> {
+        self.p2p_expect(P2pCall::PollEvents { subscription }, |reply| match reply {
+            P2pReply::Events(events) => Some(events),
+            _ => None,
+        })
+    }
+    pub fn p2p_unsubscribe(&self, subscription: SubscriptionId) -> Result<(), String> {
+        self.p2p_expect(P2pCall::Unsubscribe { subscription }, |reply| {
+            matches!(reply, P2pReply::Done).then_some(())
+        })
+    }
+    pub fn p2p_share(&self, data: impl Into<Vec<u8>>) -> Result<OperationId, String> {
+        self.p2p_operation(P2pCall::Share { data: data.into() })
+    }
+    pub fn p2p_download(&self, ticket: impl Into<String>) -> Result<OperationId, String> {
+        self.p2p_operation(P2pCall::Download {
+            ticket: ticket.into(),
+        })
+    }
+    pub fn p2p_register_protocol(&self, name: impl Into<String>) -> Result<(), String> {
+        self.p2p_expect(P2pCall::RegisterProtocol { name: name.into() }, |reply| {
+            matches!(reply, P2pReply::Done).then_some(())
+        })
+    }
+    pub fn p2p_unregister_protocol(&self, name: impl Into<String>) -> Result<(), String> {
+        self.p2p_expect(P2pCall::UnregisterProtocol { name: name.into() }, |reply| {
+            matches!(reply, P2pReply::Done).then_some(())
+        })
+    }
+    pub fn p2p_poll_requests(&self, name: impl Into<String>) -> Result<Vec<P2pRequest>, String> {
+        self.p2p_expect(
+            P2pCall::PollRequests { name: name.into() },
+            |reply| match reply {
+                P2pReply::Requests(requests) => Some(requests),
+                _ => None,
+            },
+        )
+    }
+    pub fn p2p_respond(&self, request: RequestId, data: impl Into<Vec<u8>>) -> Result<(), String> {
+        let call = P2pCall::Respond {
+            request,
+            data: data.into(),
+        };
+        self.p2p_expect(call, |reply| matches!(reply, P2pReply::Done).then_some(()))
+    }
+    pub fn p2p_request(
+        &self,
+        peer: impl Into<String>,
+        name: impl Into<String>,
+        data: impl Into<Vec<u8>>,
+    ) -> Result<OperationId, String> {
+        self.p2p_operation(P2pCall::Request {
+            peer: peer.into(),
+            name: name.into(),
+            data: data.into(),
+        })
+    }
+}
+impl Clone for Host {
+    fn clone(&self) -> Self {
+        unsafe { (self.vtable().clone)(self.raw.data) };
+        Self {
+            raw: FfiHost {
+                data: self.raw.data,
+                vtable: self.raw.vtable,
+            },
+        }
+    }
+}
+impl Drop for Host {
+    fn drop(&mut self) {
+        unsafe { (self.vtable().drop)(self.raw.data) }
+    }
+}
@@ -1,11 +1,102 @@
-pub use semver::Version;
+mod abi;
+#[cfg(all(feature = "wasm-guest", target_family = "wasm"))]
+#[doc(hidden)]
+pub mod guest;
+mod host;
+mod manifest;
+mod p2p;
+mod ui;
+
+pub use abi::{
+    ABI_VERSION, ABI_VERSION_SYMBOL, AbiError, AbiVersionFn, FfiBuffer, FfiBytes, FfiPlugin,
+    FfiStr, FfiWindow, INSTANTIATION_SYMBOL, InstantiationFn, METADATA_SYMBOL, PluginHandle,
+    PluginMetadata, PluginVTable, WindowHandle, WindowVTable,
+};
+pub use host::{
+    Cancellation, FfiHost, FfiTask, Host, HostServices, HostVTable, LogLevel, PluginTask,
+    ToastLevel,
+};
+pub use manifest::{MANIFEST_FILE, PACKAGE_EXTENSION, Permission, PluginManifest, WASM_PLATFORM};
+pub use p2p::{OperationId, P2pCall, P2pEvent, P2pReply, P2pRequest, RequestId, SubscriptionId};
+pub use semver::{Version, VersionReq};
+pub use ui::{PluginUi, PluginWindow, UiEvent, Widget, WidgetId, WindowType};
+
+pub const API_VERSION: Version = Version::new(0, 7, 0);
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lifecycle {
+    Load = 0,
+    Enable = 1,
+    Update = 2,
+    Disable = 3,
+    Unload = 4,
+}
+impl Lifecycle {
+    const ALL: [Self; 5] = [
+        Self::Load,
+        Self::Enable,
+        Self::Update,
+        Self::Disable,
+        Self::Unload,
+    ];
+    fn from_u32(value: u32) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|lifecycle| *lifecycle as u32 == value)
+    }
+    fn dispatch(self, plugin: &mut (impl PluginInterface + ?Sized)) {
+        match self {
+            Self::Load => plugin.on_load(),
+            Self::Enable => plugin.on_enable(),
+            Self::Update => plugin.on_update(),
+            Self::Disable => plugin.on_disable(),
+            Self::Unload => plugin.on_unload(),
+        }
+    }
+}
 
 pub trait PluginInterface {
-    fn plugin_info(&self) -> &PluginInfo;
+    fn on_load(&mut self) {}
+    fn on_enable(&mut self) {}
+    fn on_update(&mut self) {}
+    fn on_disable(&mut self) {}
+    fn on_unload(&mut self) {}
+    fn save_state(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+    fn restore_state(&mut self, _state: Vec<u8>) {}
+    fn window_types(&self) -> Vec<WindowType> {
+        vec![]
+    }
+    fn create_window(&mut self, _id: &str) -> Option<Box<dyn PluginWindow>> {
+        None
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PluginInfo {
     pub name: String,
     pub version: Version,
+    pub host_requirement: VersionReq,
+}
+impl PluginInfo {
+    pub fn is_compatible(&self) -> bool {
+        self.host_requirement.matches(&API_VERSION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lifecycle_round_trips_through_u32() {
+        for lifecycle in Lifecycle::ALL {
+            assert_eq!(Lifecycle::from_u32(lifecycle as u32), Some(lifecycle));
+        }
+        assert_eq!(Lifecycle::Load as u32, 0);
+        assert_eq!(Lifecycle::Unload as u32, 4);
+        assert_eq!(Lifecycle::from_u32(5), None);
+    }
 }
@@ -0,0 +1,59 @@
+use std::collections::BTreeMap;
+
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+
+use crate::API_VERSION;
+
+pub const MANIFEST_FILE: &str = "plugin.toml";
+pub const PACKAGE_EXTENSION: &str = "slpkg";
+pub const WASM_PLATFORM: &str = "wasm";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Permission {
+    OpenUrl,
+    Network,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PluginManifest {
+    pub id: String,
+    pub name: String,
+    pub version: Version,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub description: String,
+    pub icon: Option<String>,
+    pub host_requirement: VersionReq,
+    pub libraries: BTreeMap<String, String>,
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+}
+impl PluginManifest {
+    pub fn current_platform() -> String {
+        format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+    }
+    pub fn is_valid_id(id: &str) -> bool {
+        !id.is_empty()
+            && id.chars().all(|char| {
+                char.is_ascii_lowercase()
+                    || char.is_ascii_digit()
+                    || matches!(char, '.' | '-' | '_')
+            })
+    }
+    pub fn library(&self) -> Option<&str> {
+        self.libraries
+            .get(&Self::current_platform())
+            .or_else(|| self.libraries.get(WASM_PLATFORM))
+            .map(String::as_str)
+    }
+    pub fn is_compatible(&self) -> bool {
+        self.host_requirement.matches(&API_VERSION)
+    }
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+}
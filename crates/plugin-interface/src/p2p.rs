@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+pub type SubscriptionId = u64;
+pub type RequestId = u64;
+pub type OperationId = u64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum P2pCall {
+    NodeId,
+    Subscribe {
+        topic: String,
+        peers: Vec<String>,
+    },
+    Broadcast {
+        subscription: SubscriptionId,
+        data: Vec<u8>,
+    },
+    PollEvents {
+        subscription: SubscriptionId,
+    },
+    Unsubscribe {
+        subscription: SubscriptionId,
+    },
+    Share {
+        data: Vec<u8>,
+    },
+    Download {
+        ticket: String,
+    },
+    RegisterProtocol {
+        name: String,
+    },
+    UnregisterProtocol {
+        name: String,
+    },
+    PollRequests {
+        name: String,
+    },
+    Respond {
+        request: RequestId,
+        data: Vec<u8>,
+    },
+    Request {
+        peer: String,
+        name: String,
+        data: Vec<u8>,
+    },
+    PollOperation {
+        operation: OperationId,
+    },
+    CancelOperation {
+        operation: OperationId,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum P2pReply {
+    Done,
+    NodeId(String),
+    Subscription(SubscriptionId),
+    Events(Vec<P2pEvent>),
+    Ticket(String),
+    Data(Vec<u8>),
+    Requests(Vec<P2pRequest>),
+    Operation(OperationId),
+    Pending,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum P2pEvent {
+    Message { from: String, data: Vec<u8> },
+    NeighborUp(String),
+    NeighborDown(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct P2pRequest {
+    pub id: RequestId,
+    pub peer: String,
+    pub data: Vec<u8>,
+}
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+
+pub type WidgetId = u64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowType {
+    pub id: String,
+    pub title: String,
+}
+impl WindowType {
+    pub fn new(id: impl Into<String>, title: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            title: title.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Widget {
+    Label(String),
+    Heading(String),
+    Button {
+        id: WidgetId,
+        text: String,
+    },
+    TextEdit {
+        id: WidgetId,
+        text: String,
+        multiline: bool,
+    },
+    Checkbox {
+        id: WidgetId,
+        text: String,
+        checked: bool,
+    },
+    Separator,
+    Horizontal(Vec<Widget>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum UiEvent {
+    Clicked(WidgetId),
+    TextChanged(WidgetId, String),
+    Toggled(WidgetId, bool),
+}
+
+pub trait PluginWindow {
+    fn update(&mut self, ui: &mut PluginUi);
+}
+
+pub struct PluginUi {
+    window_id: u64,
+    events: Vec<UiEvent>,
+    widgets: Vec<Widget>,
+    next_id: WidgetId,
+}
+impl PluginUi {
+    pub(crate) fn new(window_id: u64, events: Vec<UiEvent>) -> Self {
+        Self {
+            window_id,
+            events,
+            widgets: vec![],
+            next_id: 0,
+        }
+    }
+    pub(crate) fn into_widgets(self) -> Vec<Widget> {
+        self.widgets
+    }
+    pub fn window_id(&self) -> u64 {
+        self.window_id
+    }
+    fn next_id(&mut self) -> WidgetId {
+        self.next_id += 1;
+        self.next_id
+    }
+    pub fn label(&mut self, text: impl Into<String>) {
+        self.widgets.push(Widget::Label(text.into()));
+    }
+    pub fn heading(&mut self, text: impl Into<String>) {
+        self.widgets.push(Widget::Heading(text.into()));
+    }
+    pub fn separator(&mut self) {
+        self.widgets.push(Widget::Separator);
+    }
+    pub fn button(&mut self, text: impl Into<String>) -> bool {
+        let id = self.next_id();
+        self.widgets.push(Widget::Button {
+            id,
+            text: text.into(),
+        });
+        self.events
+            .iter()
+            .any(|event| matches!(event, UiEvent::Clicked(event_id) if *event_id == id))
+    }
+    pub fn text_edit(&mut self, text: &mut String) -> bool {
+        self.add_text_edit(text, false)
+    }
+    pub fn text_edit_multiline(&mut self, text: &mut String) -> bool {
+        self.add_text_edit(text, true)
+    }
+    fn add_text_edit(&mut self, text: &mut String, multiline: bool) -> bool {
+        let id = self.next_id();
+        let changed = self.events.iter().rev().find_map(|event| match event {
+            UiEvent::TextChanged(event_id, new_text) if *event_id == id => Some(new_text.clone()),
+            _ => None,
+        });
+        if let Some(new_text) = &changed {
+            text.clone_from(new_text);
+        }
+        self.widgets.push(Widget::TextEdit {
+            id,
+            text: text.clone(),
+            multiline,
+        });
+        changed.is_some()
+    }
+    pub fn checkbox(&mut self, checked: &mut bool, text: impl Into<String>) -> bool {
+        let id = self.next_id();
+        let toggled = self.events.iter().rev().find_map(|event| match event {
+            UiEvent::Toggled(event_id, new_checked) if *event_id == id => Some(*new_checked),
+            _ => None,
+        });
+        if let Some(new_checked) = toggled {
+            *checked = new_checked;
+        }
+        self.widgets.push(Widget::Checkbox {
+            id,
+            text: text.into(),
+            checked: *checked,
+        });
+        toggled.is_some()
+    }
+    pub fn horizontal(&mut self, add_contents: impl FnOnce(&mut PluginUi)) {
+        let mut child = PluginUi {
+            window_id: self.window_id,
+            events: std::mem::take(&mut self.events),
+            widgets: vec![],
+            next_id: self.next_id,
+        };
+        add_contents(&mut child);
+        self.events = child.events;
+        self.next_id = child.next_id;
+        self.widgets.push(Widget::Horizontal(child.widgets));
+    }
+}
@@ -0,0 +1,405 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use eframe::egui;
+use parking_lot::{Condvar, Mutex};
+use plugin_interface::{
+    Cancellation, HostServices, LogLevel, P2pCall, P2pReply, Permission, PluginInfo, PluginTask,
+    ToastLevel,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{
+    plugin_instance::PLUGINS_DIR,
+    plugin_p2p::{NamespaceRegistry, P2pNode, PluginP2p},
+    plugin_package::PluginPackage,
+    utils::open_by_os,
+};
+
+const TASK_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub enum HostCommand {
+    Toast(ToastLevel, String),
+    OpenWindow {
+        plugin: PathBuf,
+        window_type: String,
+    },
+    CloseWindow(u64),
+    PluginChanged(PathBuf),
+}
+
+#[derive(Default)]
+struct TaskState {
+    handles: Vec<JoinHandle<()>>,
+    running: usize,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct TaskTracker {
+    state: Mutex<TaskState>,
+    finished: Condvar,
+    cancellation: Arc<Cancellation>,
+}
+impl TaskTracker {
+    fn spawn(
+        self: &Arc<Self>,
+        runtime: &tokio::runtime::Handle,
+        task: impl FnOnce(&Cancellation) + Send + 'static,
+    ) -> bool {
+        let mut state = self.state.lock();
+        if state.closed {
+            return false;
+        }
+        state.handles.retain(|handle| !handle.is_finished());
+        state.running += 1;
+        let tracked = TrackedTask {
+            task,
+            _running: RunningTask(self.clone()),
+        };
+        let cancellation = self.cancellation.clone();
+        state.handles.push(runtime.spawn_blocking(move || {
+            let TrackedTask { task, _running } = tracked;
+            task(&cancellation);
+        }));
+        true
+    }
+    fn close(&self, timeout: Duration) -> bool {
+        let mut state = self.state.lock();
+        state.closed = true;
+        self.cancellation.cancel();
+        for handle in state.handles.drain(..) {
+            handle.abort();
+        }
+        !self
+            .finished
+            .wait_while_for(&mut state, |state| state.running > 0, timeout)
+            .timed_out()
+    }
+}
+
+struct TrackedTask<F> {
+    task: F,
+    _running: RunningTask,
+}
+
+struct RunningTask(Arc<TaskTracker>);
+impl Drop for RunningTask {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock();
+        state.running -= 1;
+        if state.running == 0 {
+            self.0.finished.notify_all();
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HostContext {
+    command_sender: mpsc::UnboundedSender<HostCommand>,
+    egui_ctx: egui::Context,
+    runtime: tokio::runtime::Handle,
+    p2p_node: Option<P2pNode>,
+    namespaces: NamespaceRegistry,
+}
+impl HostContext {
+    pub fn new(
+        egui_ctx: egui::Context,
+        p2p_node: Option<P2pNode>,
+    ) -> (Self, mpsc::UnboundedReceiver<HostCommand>) {
+        let (command_sender, command_receiver) = mpsc::unbounded_channel();
+        (
+            Self {
+                command_sender,
+                egui_ctx,
+                runtime: tokio::runtime::Handle::current(),
+                p2p_node,
+                namespaces: NamespaceRegistry::default(),
+            },
+            command_receiver,
+        )
+    }
+    pub fn send_command(&self, command: HostCommand) {
+        _ = self.command_sender.send(command);
+        self.egui_ctx.request_repaint();
+    }
+    pub fn plugin_services(
+        &self,
+        package: &PluginPackage,
+        info: &PluginInfo,
+    ) -> Result<Arc<PluginHost>> {
+        let settings_name = match package.manifest() {
+            Some(manifest) => manifest.id.clone(),
+            None => package.file_stem(),
+        };
+        let settings_path = Path::new(PLUGINS_DIR)
+            .join("settings")
+            .join(format!("{}.json", settings_name));
+        let p2p = PluginP2p::new(
+            self.p2p_node.clone(),
+            &self.namespaces,
+            package.namespace(),
+            self.egui_ctx.clone(),
+            self.runtime.clone(),
+        )?;
+        Ok(Arc::new(PluginHost {
+            context: self.clone(),
+            name: info.name.clone(),
+            path: package.path().to_path_buf(),
+            settings_path,
+            settings: Mutex::new(None),
+            enabled: AtomicBool::new(false),
+            tasks: Arc::new(TaskTracker::default()),
+            permissions: [Permission::OpenUrl, Permission::Network]
+                .into_iter()
+                .filter(|permission| package.has_permission(*permission))
+                .collect(),
+            p2p,
+        }))
+    }
+}
+
+pub struct PluginHost {
+    context: HostContext,
+    name: String,
+    path: PathBuf,
+    settings_path: PathBuf,
+    settings: Mutex<Option<HashMap<String, String>>>,
+    enabled: AtomicBool,
+    tasks: Arc<TaskTracker>,
+    permissions: Vec<Permission>,
+    p2p: PluginP2p,
+}
+impl PluginHost {
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Release);
+        if !enabled {
+            self.p2p.clear();
+        }
+    }
+    pub fn shutdown_tasks(&self) -> bool {
+        self.tasks.close(TASK_SHUTDOWN_TIMEOUT)
+    }
+    fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+    fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+    fn with_settings<R>(&self, f: impl FnOnce(&mut HashMap<String, String>) -> R) -> R {
+        let mut settings = self.settings.lock();
+        let settings = settings.get_or_insert_with(|| match std::fs::read(&self.settings_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+                log::error!("插件 {} 的设置已损坏: {}", self.name, err);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        });
+        f(settings)
+    }
+    fn save_settings(&self, settings: &HashMap<String, String>) -> Result<()> {
+        if let Some(parent) = self.settings_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.settings_path, serde_json::to_vec_pretty(settings)?)?;
+        Ok(())
+    }
+    fn update_settings(&self, f: impl FnOnce(&mut HashMap<String, String>)) {
+        self.with_settings(|settings| {
+            f(settings);
+            if let Err(err) = self.save_settings(settings) {
+                log::error!("保存插件 {} 的设置失败: {}", self.name, err);
+            }
+        });
+    }
+}
+impl HostServices for PluginHost {
+    fn toast(&self, level: ToastLevel, text: &str) {
+        self.context
+            .send_command(HostCommand::Toast(level, text.to_string()));
+    }
+    fn open_url(&self, url: &str) -> bool {
+        if !self.has_permission(Permission::OpenUrl) {
+            log::warn!("插件 {} 没有打开链接的权限", self.name);
+            return false;
+        }
+        match open_by_os(url) {
+            Ok(()) => true,
+            Err(err) => {
+                log::error!("插件 {} 打开 {} 失败: {}", self.name, url, err);
+                false
+            }
+        }
+    }
+    fn log(&self, level: LogLevel, message: &str) {
+        let level = match level {
+            LogLevel::Error => log::Level::Error,
+            LogLevel::Warn => log::Level::Warn,
+            LogLevel::Info => log::Level::Info,
+            LogLevel::Debug => log::Level::Debug,
+            LogLevel::Trace => log::Level::Trace,
+        };
+        log::log!(level, "[{}] {}", self.name, message);
+    }
+    fn setting(&self, key: &str) -> Option<String> {
+        self.with_settings(|settings| settings.get(key).cloned())
+    }
+    fn set_setting(&self, key: &str, value: &str) {
+        self.update_settings(|settings| {
+            settings.insert(key.to_string(), value.to_string());
+        });
+    }
+    fn remove_setting(&self, key: &str) {
+        self.update_settings(|settings| {
+            settings.remove(key);
+        });
+    }
+    fn open_window(&self, window_type: &str) {
+        if !self.is_enabled() {
+            log::warn!("插件 {} 已停用，无法打开应用 {}", self.name, window_type);
+            return;
+        }
+        self.context.send_command(HostCommand::OpenWindow {
+            plugin: self.path.clone(),
+            window_type: window_type.to_string(),
+        });
+    }
+    fn close_window(&self, window_id: u64) {
+        self.context
+            .send_command(HostCommand::CloseWindow(window_id));
+    }
+    fn spawn(&self, task: PluginTask) {
+        let name = self.name.clone();
+        let spawned = self
+            .tasks
+            .spawn(&self.context.runtime, move |cancellation| {
+                if let Err(err) = task.run(cancellation) {
+                    log::error!("插件 {} 的后台任务失败: {}", name, err);
+                }
+            });
+        if !spawned {
+            log::warn!("插件 {} 正在卸载，已丢弃新的后台任务", self.name);
+        }
+    }
+    fn p2p(&self, call: P2pCall) -> Result<P2pReply, String> {
+        if !self.is_enabled() {
+            return Err(format!("插件 {} 已停用", self.name));
+        }
+        if !self.has_permission(Permission::Network) {
+            return Err(format!("插件 {} 没有网络权限", self.name));
+        }
+        self.p2p.call(call).map_err(|err| {
+            log::warn!("插件 {} 的 P2P 调用失败: {}", self.name, err);
+            err.to_string()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Barrier,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn close_waits_for_running_tasks() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let tracker = Arc::new(TaskTracker::default());
+        let finished = Arc::new(AtomicUsize::new(0));
+        let started = Arc::new(Barrier::new(5));
+        for _ in 0..4 {
+            let finished = finished.clone();
+            let started = started.clone();
+            assert!(tracker.spawn(runtime.handle(), move |_| {
+                started.wait();
+                std::thread::sleep(Duration::from_millis(50));
+                finished.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+        started.wait();
+        assert!(tracker.close(TASK_SHUTDOWN_TIMEOUT));
+        assert_eq!(finished.load(Ordering::SeqCst), 4);
+        assert_eq!(tracker.state.lock().running, 0);
+        assert!(!tracker.spawn(runtime.handle(), |_| {}));
+    }
+
+    #[test]
+    fn close_drops_queued_tasks_before_returning() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .max_blocking_threads(1)
+            .build()
+            .unwrap();
+        let tracker = Arc::new(TaskTracker::default());
+        let dropped = Arc::new(AtomicUsize::new(0));
+        struct CountDrop(Arc<AtomicUsize>);
+        impl Drop for CountDrop {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        for _ in 0..8 {
+            let guard = CountDrop(dropped.clone());
+            tracker.spawn(runtime.handle(), move |_| {
+                let _guard = guard;
+                std::thread::sleep(Duration::from_millis(20));
+            });
+        }
+        assert!(tracker.close(TASK_SHUTDOWN_TIMEOUT));
+        assert_eq!(dropped.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn close_cancels_running_tasks() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let tracker = Arc::new(TaskTracker::default());
+        let started = Arc::new(Barrier::new(2));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        tracker.spawn(runtime.handle(), {
+            let started = started.clone();
+            let cancelled = cancelled.clone();
+            move |cancellation| {
+                started.wait();
+                while !cancellation.is_cancelled() {
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+                cancelled.store(true, Ordering::SeqCst);
+            }
+        });
+        started.wait();
+        assert!(tracker.close(TASK_SHUTDOWN_TIMEOUT));
+        assert!(cancelled.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn close_gives_up_on_tasks_that_ignore_cancellation() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let tracker = Arc::new(TaskTracker::default());
+        let started = Arc::new(Barrier::new(2));
+        let (release, blocked) = std::sync::mpsc::channel::<()>();
+        tracker.spawn(runtime.handle(), {
+            let started = started.clone();
+            move |_| {
+                started.wait();
+                _ = blocked.recv();
+            }
+        });
+        started.wait();
+        assert!(!tracker.close(Duration::from_millis(50)));
+        assert_eq!(tracker.state.lock().running, 1);
+        release.send(()).unwrap();
+    }
+}
@@ -3,8 +3,20 @@
     windows_subsystem = "windows"
 )]
 
+#[cfg(not(target_family = "wasm"))]
+mod host;
+#[cfg(not(target_family = "wasm"))]
+mod plugin_instance;
+#[cfg(not(target_family = "wasm"))]
+mod plugin_p2p;
+#[cfg(not(target_family = "wasm"))]
+mod plugin_package;
+#[cfg(not(target_family = "wasm"))]
+mod plugin_window;
 mod utils;
 mod viewport;
+#[cfg(not(target_family = "wasm"))]
+mod wasm_plugin;
 
 const APP_NAME: &str = "应用程序名称";
 
@@ -18,8 +30,9 @@ mod cei {
 
     use anyhow::{Result, anyhow};
     use eframe::egui;
+    use starlink::{DAEMON_ADDR, DaemonNode, DaemonOptions, Starlink};
 
-    use crate::{APP_NAME, viewport::Viewport};
+    use crate::{APP_NAME, plugin_p2p::P2pNode, utils::async_task, viewport::Viewport};
 
     #[tokio::main]
     pub async fn main() {
@@ -34,6 +47,7 @@ mod cei {
         }
     }
     async fn run() -> Result<()> {
+        let p2p_node = start_p2p_node().await;
         eframe::run_native(
             APP_NAME,
             eframe::NativeOptions {
@@ -53,11 +67,33 @@ mod cei {
                 },
                 ..Default::default()
             },
-            Box::new(|cc| Ok(Box::new(Viewport::new(cc)?))),
+            Box::new(|cc| Ok(Box::new(Viewport::new(cc, p2p_node)?))),
         )
         .map_err(|err| anyhow!("{}", err))?;
         Ok(())
     }
+    async fn start_p2p_node() -> Option<P2pNode> {
+        if let Ok(daemon) = DaemonNode::connect_default().await {
+            log::info!("已连接到运行中的 starlinkd，节点 {}", daemon.node_id());
+            return Some(P2pNode::Daemon(daemon));
+        }
+        let starlink = Starlink::new()
+            .await
+            .inspect_err(|err| log::error!("启动 P2P 节点失败: {}", err))
+            .ok()?;
+        async_task({
+            let starlink = starlink.clone();
+            async move {
+                if let Err(err) = starlink
+                    .serve_daemon(DAEMON_ADDR, DaemonOptions::default())
+                    .await
+                {
+                    log::error!("启动控制接口失败: {}", err);
+                }
+            }
+        });
+        Some(P2pNode::Embedded(Box::new(starlink)))
+    }
 }
 
 #[cfg(target_family = "wasm")]
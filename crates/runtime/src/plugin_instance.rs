@@ -0,0 +1,321 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use libloading::Library;
+use notify_debouncer_mini::{
+    DebounceEventResult, Debouncer, new_debouncer, notify::RecommendedWatcher,
+    notify::RecursiveMode,
+};
+use parking_lot::Mutex;
+use plugin_interface::{
+    ABI_VERSION, ABI_VERSION_SYMBOL, API_VERSION, AbiVersionFn, FfiHost, INSTANTIATION_SYMBOL,
+    InstantiationFn, Lifecycle, METADATA_SYMBOL, PluginHandle, PluginInfo, PluginMetadata,
+    WindowType,
+};
+
+use uuid::Uuid;
+
+use crate::{
+    host::{HostCommand, HostContext, PluginHost},
+    plugin_package::{LibraryKind, PluginLibrary, PluginPackage},
+    plugin_window::PluginWindowBackend,
+    wasm_plugin::{WasmPlugin, WasmWindow},
+};
+
+pub const PLUGINS_DIR: &str = "./plugins/";
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+struct ShadowCopy(PathBuf);
+impl ShadowCopy {
+    fn new(library: &PluginLibrary) -> Result<Self> {
+        let dir = std::env::temp_dir().join("starlink-plugins");
+        std::fs::create_dir_all(&dir)?;
+        let shadow_path = dir.join(format!(
+            "{}-{}.{}",
+            library.name,
+            Uuid::new_v4(),
+            std::env::consts::DLL_EXTENSION
+        ));
+        std::fs::write(&shadow_path, &library.bytes)?;
+        Ok(Self(shadow_path))
+    }
+}
+impl Drop for ShadowCopy {
+    fn drop(&mut self) {
+        _ = std::fs::remove_file(&self.0);
+    }
+}
+
+enum PluginBackend {
+    Native {
+        instance: PluginHandle,
+        library: Option<Library>,
+        _shadow_copy: ShadowCopy,
+    },
+    Wasm(Arc<Mutex<WasmPlugin>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluginState {
+    Enabled,
+    Disabled,
+}
+
+pub struct PluginInstance {
+    backend: PluginBackend,
+    host: Arc<PluginHost>,
+    state: PluginState,
+    info: PluginInfo,
+    window_types: Vec<WindowType>,
+    path: PathBuf,
+}
+impl PluginInstance {
+    fn library_info(library: &Library) -> Result<PluginInfo> {
+        unsafe {
+            let abi_version = library
+                .get::<AbiVersionFn>(ABI_VERSION_SYMBOL)
+                .map_err(|_| anyhow!("插件没有声明 ABI 版本"))?();
+            if abi_version != ABI_VERSION {
+                bail!(
+                    "插件 ABI 版本 {} 与运行时 ABI 版本 {} 不兼容",
+                    abi_version,
+                    ABI_VERSION
+                );
+            }
+            let metadata = library
+                .get::<*const PluginMetadata>(METADATA_SYMBOL)
+                .map_err(|_| anyhow!("插件没有导出元数据"))?;
+            Ok(PluginInfo::from_metadata(&**metadata)?)
+        }
+    }
+    fn check_compatible(info: &PluginInfo) -> Result<()> {
+        if !info.is_compatible() {
+            bail!(
+                "插件 {} 需要宿主 API {}，当前宿主 API 为 {}",
+                info.name,
+                info.host_requirement,
+                API_VERSION
+            );
+        }
+        Ok(())
+    }
+    fn load_native(
+        package: &PluginPackage,
+        library: &PluginLibrary,
+        host_context: &HostContext,
+        state: Option<Vec<u8>>,
+    ) -> Result<Self> {
+        let shadow_copy = ShadowCopy::new(library)?;
+        let library = unsafe { Library::new(&shadow_copy.0)? };
+        let info = Self::library_info(&library)?;
+        Self::check_compatible(&info)?;
+        package.check_info(&info)?;
+        let host = host_context.plugin_services(package, &info)?;
+        let instance = unsafe {
+            PluginHandle::from_ffi(library.get::<InstantiationFn>(INSTANTIATION_SYMBOL)?(
+                FfiHost::new(host.clone()),
+            ))
+        }
+        .with_context(|| format!("插件 {} 初始化失败", info.name))?;
+        let window_types = instance.window_types()?;
+        Self::start(
+            PluginBackend::Native {
+                instance,
+                library: Some(library),
+                _shadow_copy: shadow_copy,
+            },
+            host,
+            info,
+            window_types,
+            package.path(),
+            state,
+        )
+    }
+    fn load_wasm(
+        package: &PluginPackage,
+        library: &PluginLibrary,
+        host_context: &HostContext,
+        state: Option<Vec<u8>>,
+    ) -> Result<Self> {
+        let (info, mut plugin) = WasmPlugin::load(package, &library.bytes, host_context)?;
+        Self::check_compatible(&info)?;
+        package.check_info(&info)?;
+        let host = plugin.host().context("插件宿主未初始化")?;
+        let window_types = plugin.window_types()?;
+        Self::start(
+            PluginBackend::Wasm(Arc::new(Mutex::new(plugin))),
+            host,
+            info,
+            window_types,
+            package.path(),
+            state,
+        )
+    }
+    fn start(
+        backend: PluginBackend,
+        host: Arc<PluginHost>,
+        info: PluginInfo,
+        window_types: Vec<WindowType>,
+        path: &Path,
+        state: Option<Vec<u8>>,
+    ) -> Result<Self> {
+        let mut plugin = Self {
+            backend,
+            host,
+            state: PluginState::Disabled,
+            info,
+            window_types,
+            path: path.to_path_buf(),
+        };
+        plugin.lifecycle(Lifecycle::Load)?;
+        if let Some(state) = state {
+            plugin.restore_state(state)?;
+        }
+        plugin.enable()?;
+        Ok(plugin)
+    }
+    pub fn save_state(&mut self) -> Result<Option<Vec<u8>>> {
+        Ok(match &mut self.backend {
+            PluginBackend::Native { instance, .. } => instance.save_state()?,
+            PluginBackend::Wasm(plugin) => plugin.lock().save_state()?,
+        })
+    }
+    fn restore_state(&mut self, state: Vec<u8>) -> Result<()> {
+        match &mut self.backend {
+            PluginBackend::Native { instance, .. } => instance.restore_state(&state)?,
+            PluginBackend::Wasm(plugin) => plugin.lock().restore_state(state)?,
+        }
+        Ok(())
+    }
+    fn lifecycle(&mut self, lifecycle: Lifecycle) -> Result<()> {
+        match &mut self.backend {
+            PluginBackend::Native { instance, .. } => instance.lifecycle(lifecycle)?,
+            PluginBackend::Wasm(plugin) => plugin.lock().lifecycle(lifecycle)?,
+        }
+        Ok(())
+    }
+    pub fn enable(&mut self) -> Result<()> {
+        if self.state == PluginState::Enabled {
+            return Ok(());
+        }
+        self.state = PluginState::Enabled;
+        self.host.set_enabled(true);
+        self.lifecycle(Lifecycle::Enable)
+    }
+    pub fn disable(&mut self) -> Result<()> {
+        if self.state == PluginState::Disabled {
+            return Ok(());
+        }
+        let result = self.lifecycle(Lifecycle::Disable);
+        self.state = PluginState::Disabled;
+        self.host.set_enabled(false);
+        result
+    }
+    pub fn update(&mut self) -> Result<()> {
+        if self.state != PluginState::Enabled {
+            return Ok(());
+        }
+        self.lifecycle(Lifecycle::Update)
+    }
+    pub fn load(
+        package: &PluginPackage,
+        host_context: &HostContext,
+        state: Option<Vec<u8>>,
+    ) -> Result<Self> {
+        let library = package.library()?;
+        match library.kind {
+            LibraryKind::Native => Self::load_native(package, &library, host_context, state),
+            LibraryKind::Wasm => Self::load_wasm(package, &library, host_context, state),
+        }
+    }
+    pub fn watch_dir(
+        dir: impl AsRef<Path>,
+        host_context: &HostContext,
+    ) -> Result<Debouncer<RecommendedWatcher>> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let mut debouncer = new_debouncer(WATCH_DEBOUNCE, {
+            let dir = dir.clone();
+            let host_context = host_context.clone();
+            let canonical_dir = dir.canonicalize()?;
+            move |result: DebounceEventResult| match result {
+                Ok(events) => {
+                    let mut paths = events
+                        .iter()
+                        .filter_map(|event| {
+                            let relative_path = event
+                                .path
+                                .strip_prefix(&canonical_dir)
+                                .or_else(|_| event.path.strip_prefix(&dir))
+                                .ok()?;
+                            match relative_path.components().next()? {
+                                Component::Normal(name) => Some(dir.join(name)),
+                                _ => None,
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    paths.sort();
+                    paths.dedup();
+                    for path in paths {
+                        host_context.send_command(HostCommand::PluginChanged(path));
+                    }
+                }
+                Err(err) => log::error!("监视插件目录失败: {}", err),
+            }
+        })?;
+        debouncer.watcher().watch(&dir, RecursiveMode::Recursive)?;
+        Ok(debouncer)
+    }
+    pub fn state(&self) -> PluginState {
+        self.state
+    }
+    pub fn info(&self) -> &PluginInfo {
+        &self.info
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn window_types(&self) -> &[WindowType] {
+        &self.window_types
+    }
+    pub fn create_window(&mut self, id: &str) -> Result<Option<Box<dyn PluginWindowBackend>>> {
+        if self.state != PluginState::Enabled {
+            bail!("插件 {} 已停用", self.info.name);
+        }
+        Ok(match &mut self.backend {
+            PluginBackend::Native { instance, .. } => instance
+                .create_window(id)?
+                .map(|handle| Box::new(handle) as Box<dyn PluginWindowBackend>),
+            PluginBackend::Wasm(plugin) => {
+                let handle = plugin.lock().create_window(id)?;
+                handle.map(|handle| {
+                    Box::new(WasmWindow::new(plugin.clone(), handle))
+                        as Box<dyn PluginWindowBackend>
+                })
+            }
+        })
+    }
+}
+impl Drop for PluginInstance {
+    fn drop(&mut self) {
+        if let Err(err) = self
+            .disable()
+            .and_then(|()| self.lifecycle(Lifecycle::Unload))
+        {
+            log::error!("卸载插件 {} 失败: {}", self.info.name, err);
+        }
+        if !self.host.shutdown_tasks()
+            && let PluginBackend::Native { library, .. } = &mut self.backend
+        {
+            log::error!(
+                "插件 {} 的后台任务未能及时结束，插件库将保持加载",
+                self.info.name
+            );
+            std::mem::forget(library.take());
+        }
+    }
+}
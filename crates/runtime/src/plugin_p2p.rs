@@ -0,0 +1,797 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use anyhow::{Context, Result, bail};
+use eframe::egui;
+use iroh::{NodeAddr, NodeId};
+use iroh_gossip::{
+    net::{Event, GossipEvent, GossipReceiver, GossipSender},
+    proto::TopicId,
+};
+use n0_future::StreamExt;
+use parking_lot::Mutex;
+use plugin_interface::{
+    OperationId, P2pCall, P2pEvent, P2pReply, P2pRequest, RequestId, SubscriptionId,
+};
+use starlink::{BlobTicket, DaemonNode, ProtocolRequest, RemoteRequest, Starlink, TopicEvent};
+use tokio::{sync::watch, task::JoinHandle};
+
+const MAX_QUEUED_EVENTS: usize = 1024;
+const MAX_PENDING_OPERATIONS: usize = 64;
+
+pub type NamespaceRegistry = Arc<Mutex<HashSet<String>>>;
+
+#[derive(Clone)]
+pub enum P2pNode {
+    Embedded(Box<Starlink>),
+    Daemon(DaemonNode),
+}
+impl P2pNode {
+    fn node_id(&self) -> NodeId {
+        match self {
+            Self::Embedded(starlink) => starlink.node_id(),
+            Self::Daemon(daemon) => daemon.node_id(),
+        }
+    }
+    async fn share_bytes(&self, data: Vec<u8>) -> Result<String> {
+        match self {
+            Self::Embedded(starlink) => Ok(starlink.share_bytes(data).await?.to_string()),
+            Self::Daemon(daemon) => daemon.share_bytes(data).await,
+        }
+    }
+    async fn read_to_bytes(&self, ticket: BlobTicket) -> Result<Vec<u8>> {
+        match self {
+            Self::Embedded(starlink) => Ok(starlink.read_to_bytes(ticket).await?.to_vec()),
+            Self::Daemon(daemon) => daemon.read_to_bytes(ticket.to_string()).await,
+        }
+    }
+    async fn protocol_request(
+        &self,
+        node_addr: NodeAddr,
+        name: String,
+        data: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        match self {
+            Self::Embedded(starlink) => Ok(starlink
+                .protocol_request(node_addr, &name, data)
+                .await?
+                .to_vec()),
+            Self::Daemon(daemon) => daemon.protocol_request(node_addr, name, data).await,
+        }
+    }
+}
+
+struct Namespace {
+    registry: NamespaceRegistry,
+    name: String,
+}
+impl Namespace {
+    fn acquire(registry: &NamespaceRegistry, name: String) -> Result<Self> {
+        if !registry.lock().insert(name.clone()) {
+            bail!("插件命名空间 {} 已被占用", name);
+        }
+        Ok(Self {
+            registry: registry.clone(),
+            name,
+        })
+    }
+}
+impl Drop for Namespace {
+    fn drop(&mut self) {
+        self.registry.lock().remove(&self.name);
+    }
+}
+
+#[derive(Clone)]
+enum TopicSender {
+    Gossip(GossipSender),
+    Daemon {
+        daemon: DaemonNode,
+        topic: TopicId,
+        joined: watch::Receiver<bool>,
+    },
+}
+impl TopicSender {
+    async fn broadcast(self, data: Vec<u8>) -> Result<()> {
+        match self {
+            Self::Gossip(sender) => sender.broadcast(data.into()).await?,
+            Self::Daemon {
+                daemon,
+                topic,
+                mut joined,
+            } => {
+                if joined.wait_for(|joined| *joined).await.is_err() {
+                    bail!("守护进程未能加入该话题");
+                }
+                daemon.broadcast(topic, data).await?
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Subscription {
+    sender: TopicSender,
+    events: Arc<Mutex<VecDeque<P2pEvent>>>,
+    task: JoinHandle<()>,
+}
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+enum IncomingRequest {
+    Embedded(ProtocolRequest),
+    Daemon(DaemonNode, RemoteRequest),
+}
+impl IncomingRequest {
+    fn peer(&self) -> NodeId {
+        match self {
+            Self::Embedded(request) => request.peer(),
+            Self::Daemon(_, request) => request.peer,
+        }
+    }
+    fn data(&self) -> Vec<u8> {
+        match self {
+            Self::Embedded(request) => request.data().to_vec(),
+            Self::Daemon(_, request) => request.data.clone(),
+        }
+    }
+    fn respond(self, data: Vec<u8>, runtime: &tokio::runtime::Handle) {
+        match self {
+            Self::Embedded(request) => request.respond(data),
+            Self::Daemon(daemon, request) => {
+                runtime.spawn(async move {
+                    if let Err(err) = daemon.respond(request.id, data).await {
+                        log::warn!("通过守护进程响应插件协议请求失败: {}", err);
+                    }
+                });
+            }
+        }
+    }
+}
+
+struct Protocol {
+    node: P2pNode,
+    full_name: String,
+    requests: Arc<Mutex<VecDeque<IncomingRequest>>>,
+    task: JoinHandle<()>,
+}
+impl Drop for Protocol {
+    fn drop(&mut self) {
+        self.task.abort();
+        if let P2pNode::Embedded(starlink) = &self.node {
+            starlink.unregister_protocol(&self.full_name);
+        }
+    }
+}
+
+type OperationResult = Arc<Mutex<Option<Result<P2pReply, String>>>>;
+
+struct Operation {
+    result: OperationResult,
+    task: JoinHandle<()>,
+}
+impl Drop for Operation {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn push_bounded<T>(queue: &Mutex<VecDeque<T>>, item: T) {
+    let mut queue = queue.lock();
+    if queue.len() >= MAX_QUEUED_EVENTS {
+        queue.pop_front();
+    }
+    queue.push_back(item);
+}
+
+async fn forward_gossip_events(
+    mut receiver: GossipReceiver,
+    events: Arc<Mutex<VecDeque<P2pEvent>>>,
+    egui_ctx: egui::Context,
+) {
+    while let Some(event) = receiver.next().await {
+        let event = match event {
+            Ok(Event::Gossip(GossipEvent::Received(message))) => P2pEvent::Message {
+                from: message.delivered_from.to_string(),
+                data: message.content.to_vec(),
+            },
+            Ok(Event::Gossip(GossipEvent::NeighborUp(node_id))) => {
+                P2pEvent::NeighborUp(node_id.to_string())
+            }
+            Ok(Event::Gossip(GossipEvent::NeighborDown(node_id))) => {
+                P2pEvent::NeighborDown(node_id.to_string())
+            }
+            Ok(_) => continue,
+            Err(err) => {
+                log::warn!("插件话题接收失败: {}", err);
+                break;
+            }
+        };
+        push_bounded(&events, event);
+        egui_ctx.request_repaint();
+    }
+}
+
+async fn forward_daemon_events(
+    daemon: DaemonNode,
+    topic: TopicId,
+    peers: Vec<NodeAddr>,
+    joined: watch::Sender<bool>,
+    events: Arc<Mutex<VecDeque<P2pEvent>>>,
+    egui_ctx: egui::Context,
+) -> Result<()> {
+    daemon.join_topic(topic, peers).await?;
+    let mut client = daemon.listen(topic).await?;
+    _ = joined.send(true);
+    while let Some((_, event)) = client.next_event().await? {
+        let event = match event {
+            TopicEvent::Data { from, data } => P2pEvent::Message {
+                from: from.to_string(),
+                data,
+            },
+            TopicEvent::Message { from, content } => P2pEvent::Message {
+                from: from.to_string(),
+                data: content.into_bytes(),
+            },
+            TopicEvent::NeighborUp(node_id) => P2pEvent::NeighborUp(node_id.to_string()),
+            TopicEvent::NeighborDown(node_id) => P2pEvent::NeighborDown(node_id.to_string()),
+        };
+        push_bounded(&events, event);
+        egui_ctx.request_repaint();
+    }
+    Ok(())
+}
+
+pub struct PluginP2p {
+    node: Option<P2pNode>,
+    namespace: Namespace,
+    egui_ctx: egui::Context,
+    runtime: tokio::runtime::Handle,
+    next_id: AtomicU64,
+    subscriptions: Mutex<HashMap<SubscriptionId, Subscription>>,
+    protocols: Mutex<HashMap<String, Protocol>>,
+    pending_requests: Mutex<HashMap<RequestId, IncomingRequest>>,
+    operations: Mutex<HashMap<OperationId, Operation>>,
+}
+impl PluginP2p {
+    pub fn new(
+        node: Option<P2pNode>,
+        registry: &NamespaceRegistry,
+        namespace: String,
+        egui_ctx: egui::Context,
+        runtime: tokio::runtime::Handle,
+    ) -> Result<Self> {
+        Ok(Self {
+            node,
+            namespace: Namespace::acquire(registry, namespace)?,
+            egui_ctx,
+            runtime,
+            next_id: AtomicU64::new(1),
+            subscriptions: Mutex::new(HashMap::new()),
+            protocols: Mutex::new(HashMap::new()),
+            pending_requests: Mutex::new(HashMap::new()),
+            operations: Mutex::new(HashMap::new()),
+        })
+    }
+    pub fn clear(&self) {
+        self.subscriptions.lock().clear();
+        self.protocols.lock().clear();
+        self.pending_requests.lock().clear();
+        self.operations.lock().clear();
+    }
+    fn node(&self) -> Result<&P2pNode> {
+        self.node.as_ref().context("P2P 节点未启动")
+    }
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+    fn topic_id(&self, topic: &str) -> TopicId {
+        let mut hasher = blake3::Hasher::new_derive_key("starlink plugin topic");
+        hasher.update(&(self.namespace.name.len() as u64).to_le_bytes());
+        hasher.update(self.namespace.name.as_bytes());
+        hasher.update(topic.as_bytes());
+        TopicId::from_bytes(*hasher.finalize().as_bytes())
+    }
+    fn protocol_name(&self, name: &str) -> String {
+        format!(
+            "plugin:{}:{}:{}",
+            self.namespace.name.len(),
+            self.namespace.name,
+            name
+        )
+    }
+    fn start_operation(
+        &self,
+        future: impl Future<Output = Result<P2pReply>> + Send + 'static,
+    ) -> Result<P2pReply> {
+        let mut operations = self.operations.lock();
+        if operations.len() >= MAX_PENDING_OPERATIONS {
+            bail!("进行中的 P2P 操作过多");
+        }
+        let result = OperationResult::default();
+        let task = self.runtime.spawn({
+            let result = result.clone();
+            let egui_ctx = self.egui_ctx.clone();
+            async move {
+                let reply = future.await.map_err(|err| err.to_string());
+                *result.lock() = Some(reply);
+                egui_ctx.request_repaint();
+            }
+        });
+        let id = self.next_id();
+        operations.insert(id, Operation { result, task });
+        Ok(P2pReply::Operation(id))
+    }
+    fn poll_operation(&self, operation: OperationId) -> Result<P2pReply> {
+        let mut operations = self.operations.lock();
+        let result = operations
+            .get(&operation)
+            .context("操作不存在")?
+            .result
+            .lock()
+            .take();
+        let Some(result) = result else {
+            return Ok(P2pReply::Pending);
+        };
+        operations.remove(&operation);
+        result.map_err(anyhow::Error::msg)
+    }
+    pub fn call(&self, call: P2pCall) -> Result<P2pReply> {
+        let node = self.node()?.clone();
+        let reply =
+            match call {
+                P2pCall::NodeId => P2pReply::NodeId(node.node_id().to_string()),
+                P2pCall::Subscribe { topic, peers } => {
+                    P2pReply::Subscription(self.subscribe(node, &topic, peers)?)
+                }
+                P2pCall::Broadcast { subscription, data } => {
+                    let sender = self
+                        .subscriptions
+                        .lock()
+                        .get(&subscription)
+                        .map(|subscription| subscription.sender.clone())
+                        .context("订阅不存在")?;
+                    self.start_operation(async move {
+                        sender.broadcast(data).await?;
+                        Ok(P2pReply::Done)
+                    })?
+                }
+                P2pCall::PollEvents { subscription } => {
+                    let events = self
+                        .subscriptions
+                        .lock()
+                        .get(&subscription)
+                        .map(|subscription| subscription.events.clone())
+                        .context("订阅不存在")?;
+                    P2pReply::Events(events.lock().drain(..).collect())
+                }
+                P2pCall::Unsubscribe { subscription } => {
+                    self.subscriptions.lock().remove(&subscription);
+                    P2pReply::Done
+                }
+                P2pCall::Share { data } => self.start_operation(async move {
+                    Ok(P2pReply::Ticket(node.share_bytes(data).await?))
+                })?,
+                P2pCall::Download { ticket } => {
+                    let ticket = BlobTicket::from_str(&ticket)?;
+                    self.start_operation(async move {
+                        Ok(P2pReply::Data(node.read_to_bytes(ticket).await?))
+                    })?
+                }
+                P2pCall::RegisterProtocol { name } => {
+                    self.register_protocol(node, name)?;
+                    P2pReply::Done
+                }
+                P2pCall::UnregisterProtocol { name } => {
+                    self.protocols.lock().remove(&name);
+                    P2pReply::Done
+                }
+                P2pCall::PollRequests { name } => P2pReply::Requests(self.poll_requests(&name)?),
+                P2pCall::Respond { request, data } => {
+                    self.pending_requests
+                        .lock()
+                        .remove(&request)
+                        .context("请求不存在或已响应")?
+                        .respond(data, &self.runtime);
+                    P2pReply::Done
+                }
+                P2pCall::Request { peer, name, data } => {
+                    let node_addr = NodeAddr::new(NodeId::from_str(&peer)?);
+                    let name = self.protocol_name(&name);
+                    self.start_operation(async move {
+                        Ok(P2pReply::Data(
+                            node.protocol_request(node_addr, name, data).await?,
+                        ))
+                    })?
+                }
+                P2pCall::PollOperation { operation } => self.poll_operation(operation)?,
+                P2pCall::CancelOperation { operation } => {
+                    self.operations.lock().remove(&operation);
+                    P2pReply::Done
+                }
+            };
+        Ok(reply)
+    }
+    fn subscribe(&self, node: P2pNode, topic: &str, peers: Vec<String>) -> Result<SubscriptionId> {
+        let peers = peers
+            .iter()
+            .map(|peer| Ok(NodeAddr::new(NodeId::from_str(peer)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let topic = self.topic_id(topic);
+        let events = Arc::new(Mutex::new(VecDeque::new()));
+        let (sender, task) = match node {
+            P2pNode::Embedded(starlink) => {
+                let (sender, receiver) = {
+                    let _guard = self.runtime.enter();
+                    starlink.subscribe_topic_detached(topic, peers)?
+                };
+                let task = self.runtime.spawn(forward_gossip_events(
+                    receiver,
+                    events.clone(),
+                    self.egui_ctx.clone(),
+                ));
+                (TopicSender::Gossip(sender), task)
+            }
+            P2pNode::Daemon(daemon) => {
+                let (joined_sender, joined) = watch::channel(false);
+                let task = self.runtime.spawn({
+                    let daemon = daemon.clone();
+                    let events = events.clone();
+                    let egui_ctx = self.egui_ctx.clone();
+                    async move {
+                        if let Err(err) = forward_daemon_events(
+                            daemon,
+                            topic,
+                            peers,
+                            joined_sender,
+                            events,
+                            egui_ctx,
+                        )
+                        .await
+                        {
+                            log::warn!("通过守护进程订阅插件话题失败: {}", err);
+                        }
+                    }
+                });
+                (
+                    TopicSender::Daemon {
+                        daemon,
+                        topic,
+                        joined,
+                    },
+                    task,
+                )
+            }
+        };
+        let id = self.next_id();
+        self.subscriptions.lock().insert(
+            id,
+            Subscription {
+                sender,
+                events,
+                task,
+            },
+        );
+        Ok(id)
+    }
+    fn register_protocol(&self, node: P2pNode, name: String) -> Result<()> {
+        let mut protocols = self.protocols.lock();
+        if protocols.contains_key(&name) {
+            bail!("协议 {} 已注册", name);
+        }
+        let full_name = self.protocol_name(&name);
+        let requests = Arc::new(Mutex::new(VecDeque::new()));
+        let task = match &node {
+            P2pNode::Embedded(starlink) => {
+                let mut receiver = starlink.register_protocol(full_name.clone())?;
+                self.runtime.spawn({
+                    let requests = requests.clone();
+                    let egui_ctx = self.egui_ctx.clone();
+                    async move {
+                        while let Some(request) = receiver.recv().await {
+                            push_bounded(&requests, IncomingRequest::Embedded(request));
+                            egui_ctx.request_repaint();
+                        }
+                    }
+                })
+            }
+            P2pNode::Daemon(daemon) => self.runtime.spawn({
+                let daemon = daemon.clone();
+                let full_name = full_name.clone();
+                let requests = requests.clone();
+                let egui_ctx = self.egui_ctx.clone();
+                async move {
+                    let result = async {
+                        let mut client = daemon.register_protocol(full_name).await?;
+                        while let Some(request) = client.next_request().await? {
+                            push_bounded(
+                                &requests,
+                                IncomingRequest::Daemon(daemon.clone(), request),
+                            );
+                            egui_ctx.request_repaint();
+                        }
+                        anyhow::Ok(())
+                    }
+                    .await;
+                    if let Err(err) = result {
+                        log::warn!("通过守护进程注册插件协议失败: {}", err);
+                    }
+                }
+            }),
+        };
+        protocols.insert(
+            name,
+            Protocol {
+                node,
+                full_name,
+                requests,
+                task,
+            },
+        );
+        Ok(())
+    }
+    fn poll_requests(&self, name: &str) -> Result<Vec<P2pRequest>> {
+        let requests = self
+            .protocols
+            .lock()
+            .get(name)
+            .map(|protocol| protocol.requests.clone())
+            .context("协议未注册")?;
+        let requests: Vec<_> = requests.lock().drain(..).collect();
+        let mut pending_requests = self.pending_requests.lock();
+        Ok(requests
+            .into_iter()
+            .map(|request| {
+                let id = self.next_id();
+                let p2p_request = P2pRequest {
+                    id,
+                    peer: request.peer().to_string(),
+                    data: request.data(),
+                };
+                pending_requests.insert(id, request);
+                p2p_request
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        time::Duration,
+    };
+
+    use iroh::SecretKey;
+    use starlink::{DaemonRequest, DaemonResponse};
+
+    use super::*;
+
+    fn plugin_p2p(runtime: &tokio::runtime::Runtime, registry: &NamespaceRegistry) -> PluginP2p {
+        PluginP2p::new(
+            None,
+            registry,
+            "id:starlink.test".to_string(),
+            egui::Context::default(),
+            runtime.handle().clone(),
+        )
+        .unwrap()
+    }
+
+    fn wait_ready(p2p: &PluginP2p, operation: OperationId) -> Result<P2pReply> {
+        for _ in 0..100 {
+            match p2p.poll_operation(operation) {
+                Ok(P2pReply::Pending) => std::thread::sleep(Duration::from_millis(10)),
+                result => return result,
+            }
+        }
+        bail!("操作超时")
+    }
+
+    #[test]
+    fn duplicate_namespaces_are_rejected_until_released() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let registry = NamespaceRegistry::default();
+        let first = plugin_p2p(&runtime, &registry);
+        assert!(
+            PluginP2p::new(
+                None,
+                &registry,
+                "id:starlink.test".to_string(),
+                egui::Context::default(),
+                runtime.handle().clone(),
+            )
+            .is_err()
+        );
+        drop(first);
+        assert!(registry.lock().is_empty());
+        plugin_p2p(&runtime, &registry);
+    }
+
+    #[test]
+    fn operations_are_polled_without_blocking() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let p2p = plugin_p2p(&runtime, &NamespaceRegistry::default());
+        let (sender, receiver) = tokio::sync::oneshot::channel::<()>();
+        let P2pReply::Operation(operation) = p2p
+            .start_operation(async move {
+                receiver.await?;
+                Ok(P2pReply::Data(b"pong".to_vec()))
+            })
+            .unwrap()
+        else {
+            panic!("expected an operation id");
+        };
+        assert!(matches!(
+            p2p.poll_operation(operation).unwrap(),
+            P2pReply::Pending
+        ));
+        sender.send(()).unwrap();
+        assert!(matches!(
+            wait_ready(&p2p, operation).unwrap(),
+            P2pReply::Data(data) if data == b"pong"
+        ));
+        assert!(p2p.poll_operation(operation).is_err());
+    }
+
+    #[test]
+    fn failed_operations_report_their_error() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let p2p = plugin_p2p(&runtime, &NamespaceRegistry::default());
+        let P2pReply::Operation(operation) =
+            p2p.start_operation(async { bail!("连接失败") }).unwrap()
+        else {
+            panic!("expected an operation id");
+        };
+        let err = wait_ready(&p2p, operation).unwrap_err();
+        assert_eq!(err.to_string(), "连接失败");
+    }
+
+    #[test]
+    fn pending_operations_are_bounded() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let p2p = plugin_p2p(&runtime, &NamespaceRegistry::default());
+        for _ in 0..MAX_PENDING_OPERATIONS {
+            p2p.start_operation(n0_future::future::pending()).unwrap();
+        }
+        assert!(p2p.start_operation(n0_future::future::pending()).is_err());
+        p2p.clear();
+        assert!(p2p.operations.lock().is_empty());
+    }
+
+    type Responses = Arc<Mutex<Vec<(u64, Vec<u8>)>>>;
+
+    fn write_response(stream: &mut TcpStream, response: &DaemonResponse) {
+        let mut line = serde_json::to_vec(response).unwrap();
+        line.push(b'\n');
+        stream.write_all(&line).unwrap();
+    }
+
+    fn serve_fake_daemon(stream: TcpStream, responses: Responses) {
+        let mut writer = stream.try_clone().unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        lines.next().unwrap().unwrap();
+        write_response(&mut writer, &DaemonResponse::Ok(serde_json::Value::Null));
+        for line in lines {
+            let value = match serde_json::from_str(&line.unwrap()).unwrap() {
+                DaemonRequest::NodeAddr => {
+                    serde_json::to_value(NodeAddr::new(SecretKey::from_bytes(&[2; 32]).public()))
+                        .unwrap()
+                }
+                DaemonRequest::ShareBytes { data } => {
+                    serde_json::to_value(format!("ticket-{}", data.len())).unwrap()
+                }
+                DaemonRequest::RegisterProtocol { name } => {
+                    write_response(&mut writer, &DaemonResponse::Ok(serde_json::Value::Null));
+                    write_response(
+                        &mut writer,
+                        &DaemonResponse::Request(RemoteRequest {
+                            id: 7,
+                            peer: SecretKey::from_bytes(&[3; 32]).public(),
+                            data: name.into_bytes(),
+                        }),
+                    );
+                    continue;
+                }
+                DaemonRequest::Respond { id, data } => {
+                    responses.lock().push((id, data));
+                    serde_json::Value::Null
+                }
+                _ => panic!("unexpected daemon request"),
+            };
+            write_response(&mut writer, &DaemonResponse::Ok(value));
+        }
+    }
+
+    fn fake_daemon() -> (SocketAddr, Responses) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let responses = Responses::default();
+        std::thread::spawn({
+            let responses = responses.clone();
+            move || {
+                for stream in listener.incoming() {
+                    let responses = responses.clone();
+                    std::thread::spawn(move || serve_fake_daemon(stream.unwrap(), responses));
+                }
+            }
+        });
+        (addr, responses)
+    }
+
+    fn wait_for<T>(mut poll: impl FnMut() -> Option<T>) -> T {
+        for _ in 0..100 {
+            if let Some(value) = poll() {
+                return value;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("等待超时");
+    }
+
+    #[test]
+    fn plugin_calls_go_through_the_daemon() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (addr, responses) = fake_daemon();
+        let daemon = runtime
+            .block_on(DaemonNode::connect(addr, "secret"))
+            .unwrap();
+        let p2p = PluginP2p::new(
+            Some(P2pNode::Daemon(daemon)),
+            &NamespaceRegistry::default(),
+            "id:starlink.test".to_string(),
+            egui::Context::default(),
+            runtime.handle().clone(),
+        )
+        .unwrap();
+        assert!(matches!(
+            p2p.call(P2pCall::NodeId).unwrap(),
+            P2pReply::NodeId(node_id) if node_id == SecretKey::from_bytes(&[2; 32]).public().to_string()
+        ));
+        let P2pReply::Operation(operation) = p2p
+            .call(P2pCall::Share {
+                data: vec![1, 2, 3],
+            })
+            .unwrap()
+        else {
+            panic!("expected an operation id");
+        };
+        assert!(matches!(
+            wait_ready(&p2p, operation).unwrap(),
+            P2pReply::Ticket(ticket) if ticket == "ticket-3"
+        ));
+
+        p2p.call(P2pCall::RegisterProtocol {
+            name: "echo".to_string(),
+        })
+        .unwrap();
+        let request = wait_for(|| {
+            match p2p
+                .call(P2pCall::PollRequests {
+                    name: "echo".to_string(),
+                })
+                .unwrap()
+            {
+                P2pReply::Requests(mut requests) => requests.pop(),
+                _ => panic!("expected requests"),
+            }
+        });
+        assert_eq!(request.data, p2p.protocol_name("echo").into_bytes());
+        p2p.call(P2pCall::Respond {
+            request: request.id,
+            data: b"pong".to_vec(),
+        })
+        .unwrap();
+        assert_eq!(wait_for(|| responses.lock().pop()), (7, b"pong".to_vec()));
+    }
+}
@@ -0,0 +1,330 @@
+use std::{
+    io::Read,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, bail};
+use plugin_interface::{
+    API_VERSION, MANIFEST_FILE, PACKAGE_EXTENSION, Permission, PluginInfo, PluginManifest,
+};
+use zip::ZipArchive;
+
+const WASM_EXTENSION: &str = "wasm";
+const MAX_ENTRY_SIZE: u64 = 256 * 1024 * 1024;
+
+fn read_limited(reader: impl Read, name: &Path, limit: u64) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    reader.take(limit + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > limit {
+        bail!("插件包文件 {} 超过 {} 字节", name.display(), limit);
+    }
+    Ok(bytes)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LibraryKind {
+    Native,
+    Wasm,
+}
+
+pub struct PluginLibrary {
+    pub kind: LibraryKind,
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+pub struct PluginPackage {
+    path: PathBuf,
+    manifest: Option<PluginManifest>,
+    icon: Option<Arc<[u8]>>,
+}
+impl PluginPackage {
+    fn library_kind(path: &Path) -> Option<LibraryKind> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(std::env::consts::DLL_EXTENSION) => Some(LibraryKind::Native),
+            Some(WASM_EXTENSION) => Some(LibraryKind::Wasm),
+            _ => None,
+        }
+    }
+    fn is_archive(path: &Path) -> bool {
+        path.extension().and_then(|extension| extension.to_str()) == Some(PACKAGE_EXTENSION)
+    }
+    pub fn is_package_path(path: &Path) -> bool {
+        if path.is_dir() {
+            return path.join(MANIFEST_FILE).is_file();
+        }
+        path.is_file() && (Self::is_archive(path) || Self::library_kind(path).is_some())
+    }
+    fn read_entry(&self, name: &str) -> Result<Vec<u8>> {
+        let name = Path::new(name);
+        if !name
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!("插件包路径 {} 不合法", name.display());
+        }
+        if self.path.is_dir() {
+            let file = std::fs::File::open(self.path.join(name))?;
+            return read_limited(file, name, MAX_ENTRY_SIZE);
+        }
+        let mut archive = ZipArchive::new(std::fs::File::open(&self.path)?)?;
+        let name = name
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let entry = archive
+            .by_name(&name)
+            .with_context(|| format!("插件包中没有 {}", name))?;
+        if entry.size() > MAX_ENTRY_SIZE {
+            bail!("插件包文件 {} 超过 {} 字节", name, MAX_ENTRY_SIZE);
+        }
+        read_limited(entry, Path::new(&name), MAX_ENTRY_SIZE)
+    }
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let mut package = Self {
+            path: path.as_ref().to_path_buf(),
+            manifest: None,
+            icon: None,
+        };
+        if Self::library_kind(&package.path).is_some() && package.path.is_file() {
+            return Ok(package);
+        }
+        let manifest = toml::from_str::<PluginManifest>(&String::from_utf8(
+            package.read_entry(MANIFEST_FILE)?,
+        )?)
+        .context("插件清单格式错误")?;
+        if !PluginManifest::is_valid_id(&manifest.id) {
+            bail!("插件标识 {} 不合法", manifest.id);
+        }
+        package.icon = match &manifest.icon {
+            Some(icon) => package
+                .read_entry(icon)
+                .inspect_err(|err| log::warn!("读取插件 {} 图标失败: {}", manifest.name, err))
+                .ok()
+                .map(Arc::from),
+            None => None,
+        };
+        package.manifest = Some(manifest);
+        Ok(package)
+    }
+    pub fn discover(dir: impl AsRef<Path>) -> (Vec<Self>, Vec<(PathBuf, anyhow::Error)>) {
+        let mut packages = vec![];
+        let mut errors = vec![];
+        let entries = match std::fs::read_dir(dir.as_ref()) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return (packages, errors),
+            Err(err) => {
+                errors.push((dir.as_ref().to_path_buf(), err.into()));
+                return (packages, errors);
+            }
+        };
+        let mut paths = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| Self::is_package_path(path))
+            .collect::<Vec<_>>();
+        paths.sort();
+        for path in paths {
+            match Self::open(&path) {
+                Ok(package) => packages.push(package),
+                Err(err) => {
+                    log::error!("读取插件包 {} 失败: {}", path.display(), err);
+                    errors.push((path, err));
+                }
+            }
+        }
+        (packages, errors)
+    }
+    pub fn check(&self) -> Result<()> {
+        let Some(manifest) = &self.manifest else {
+            return Ok(());
+        };
+        if !manifest.is_compatible() {
+            bail!(
+                "插件 {} 需要宿主 API {}，当前宿主 API 为 {}",
+                manifest.name,
+                manifest.host_requirement,
+                API_VERSION
+            );
+        }
+        if manifest.library().is_none() {
+            bail!(
+                "插件 {} 不支持当前平台 {}",
+                manifest.name,
+                PluginManifest::current_platform()
+            );
+        }
+        Ok(())
+    }
+    pub fn check_info(&self, info: &PluginInfo) -> Result<()> {
+        if let Some(manifest) = &self.manifest
+            && (manifest.name != info.name || manifest.version != info.version)
+        {
+            bail!(
+                "插件清单 {} {} 与插件元数据 {} {} 不一致",
+                manifest.name,
+                manifest.version,
+                info.name,
+                info.version
+            );
+        }
+        Ok(())
+    }
+    pub fn library(&self) -> Result<PluginLibrary> {
+        self.check()?;
+        let Some(manifest) = &self.manifest else {
+            return Ok(PluginLibrary {
+                kind: Self::library_kind(&self.path).context("不支持的插件文件")?,
+                name: self.file_stem(),
+                bytes: std::fs::read(&self.path)?,
+            });
+        };
+        let library = manifest.library().context("插件不支持当前平台")?;
+        Ok(PluginLibrary {
+            kind: Self::library_kind(Path::new(library))
+                .with_context(|| format!("不支持的插件库 {}", library))?,
+            name: manifest.id.clone(),
+            bytes: self.read_entry(library)?,
+        })
+    }
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.manifest
+            .as_ref()
+            .is_some_and(|manifest| manifest.has_permission(permission))
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn namespace(&self) -> String {
+        match &self.manifest {
+            Some(manifest) => format!("id:{}", manifest.id),
+            None => {
+                let path = self
+                    .path
+                    .canonicalize()
+                    .unwrap_or_else(|_| self.path.clone());
+                format!("path:{}", path.display())
+            }
+        }
+    }
+    pub fn file_stem(&self) -> String {
+        self.path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+    pub fn manifest(&self) -> Option<&PluginManifest> {
+        self.manifest.as_ref()
+    }
+    pub fn icon(&self) -> Option<Arc<[u8]>> {
+        self.icon.clone()
+    }
+    pub fn title(&self) -> String {
+        match &self.manifest {
+            Some(manifest) => format!("{} {}", manifest.name, manifest.version),
+            None => self.file_stem(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    use super::*;
+
+    const MANIFEST: &str = r#"
+id = "starlink.test"
+name = "测试"
+version = "0.1.0"
+host-requirement = "*"
+permissions = ["network"]
+
+[libraries]
+wasm = "plugin.wasm"
+"#;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("starlink-package-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn bare_library_has_no_permissions() {
+        let dir = temp_dir();
+        let path = dir.join(format!("plugin.{}", std::env::consts::DLL_EXTENSION));
+        std::fs::write(&path, b"library").unwrap();
+        let package = PluginPackage::open(&path).unwrap();
+        assert!(package.manifest().is_none());
+        assert!(!package.has_permission(Permission::Network));
+        assert!(!package.has_permission(Permission::OpenUrl));
+        assert!(package.namespace().starts_with("path:"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn manifest_grants_only_declared_permissions() {
+        let dir = temp_dir();
+        std::fs::write(dir.join(MANIFEST_FILE), MANIFEST).unwrap();
+        let package = PluginPackage::open(&dir).unwrap();
+        assert!(package.has_permission(Permission::Network));
+        assert!(!package.has_permission(Permission::OpenUrl));
+        assert_eq!(package.namespace(), "id:starlink.test");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_manifests_are_rejected() {
+        let dir = temp_dir();
+        for manifest in [
+            MANIFEST.replace("starlink.test", "Starlink/Test"),
+            MANIFEST.replace("\"network\"", "\"filesystem\""),
+            MANIFEST.replace("version = \"0.1.0\"", "version = \"x\""),
+            "id = ".to_string(),
+        ] {
+            std::fs::write(dir.join(MANIFEST_FILE), manifest).unwrap();
+            assert!(PluginPackage::open(&dir).is_err());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entries_must_stay_inside_the_package() {
+        let dir = temp_dir();
+        let archive = dir.join(format!("plugin.{}", PACKAGE_EXTENSION));
+        let mut writer = ZipWriter::new(std::fs::File::create(&archive).unwrap());
+        writer
+            .start_file(MANIFEST_FILE, SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(MANIFEST.as_bytes()).unwrap();
+        writer
+            .start_file("lib/plugin.wasm", SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"wasm").unwrap();
+        writer.finish().unwrap();
+        let package = PluginPackage::open(&archive).unwrap();
+        assert_eq!(package.read_entry("lib/plugin.wasm").unwrap(), b"wasm");
+        for name in [
+            "../plugin.toml",
+            "/etc/passwd",
+            "lib/../plugin.toml",
+            "./plugin.toml",
+        ] {
+            assert!(package.read_entry(name).is_err(), "{}", name);
+        }
+        assert!(package.read_entry("missing.wasm").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn oversized_entries_are_rejected() {
+        let name = Path::new("plugin.wasm");
+        assert_eq!(read_limited(&b"1234"[..], name, 4).unwrap(), b"1234");
+        assert!(read_limited(&b"12345"[..], name, 4).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::Result;
+use eframe::egui;
+use plugin_interface::{UiEvent, Widget, WindowHandle};
+
+use crate::viewport::WindowViewport;
+
+pub trait PluginWindowBackend {
+    fn update(&mut self, window_id: u64, events: &[UiEvent]) -> Result<Vec<Widget>>;
+}
+impl PluginWindowBackend for WindowHandle {
+    fn update(&mut self, window_id: u64, events: &[UiEvent]) -> Result<Vec<Widget>> {
+        Ok(WindowHandle::update(self, window_id, events)?)
+    }
+}
+
+pub struct PluginWindowViewport {
+    handle: Box<dyn PluginWindowBackend>,
+    window_id: u64,
+    events: Vec<UiEvent>,
+}
+impl PluginWindowViewport {
+    pub fn new(handle: Box<dyn PluginWindowBackend>, window_id: u64) -> Self {
+        Self {
+            handle,
+            window_id,
+            events: vec![],
+        }
+    }
+}
+impl WindowViewport for PluginWindowViewport {
+    fn update(&mut self, ui: &mut egui::Ui) {
+        match self
+            .handle
+            .update(self.window_id, &std::mem::take(&mut self.events))
+        {
+            Ok(widgets) => {
+                for widget in &widgets {
+                    show_widget(ui, widget, &mut self.events);
+                }
+            }
+            Err(err) => {
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    format!("插件界面数据无效: {}", err),
+                );
+            }
+        }
+        if !self.events.is_empty() {
+            ui.ctx().request_repaint();
+        }
+    }
+}
+
+fn show_widget(ui: &mut egui::Ui, widget: &Widget, events: &mut Vec<UiEvent>) {
+    match widget {
+        Widget::Label(text) => {
+            ui.label(text);
+        }
+        Widget::Heading(text) => {
+            ui.heading(text);
+        }
+        Widget::Button { id, text } => {
+            if ui.button(text).clicked() {
+                events.push(UiEvent::Clicked(*id));
+            }
+        }
+        Widget::TextEdit {
+            id,
+            text,
+            multiline,
+        } => {
+            let mut text = text.clone();
+            let response = if *multiline {
+                ui.text_edit_multiline(&mut text)
+            } else {
+                ui.text_edit_singleline(&mut text)
+            };
+            if response.changed() {
+                events.push(UiEvent::TextChanged(*id, text));
+            }
+        }
+        Widget::Checkbox { id, text, checked } => {
+            let mut checked = *checked;
+            if ui.checkbox(&mut checked, text).changed() {
+                events.push(UiEvent::Toggled(*id, checked));
+            }
+        }
+        Widget::Separator => {
+            ui.separator();
+        }
+        Widget::Horizontal(widgets) => {
+            ui.horizontal(|ui| {
+                for widget in widgets {
+                    show_widget(ui, widget, events);
+                }
+            });
+        }
+    }
+}
@@ -1,11 +1,29 @@
+#[cfg(not(target_family = "wasm"))]
+use std::path::PathBuf;
+
 use anyhow::{Context, Result};
 use eframe::egui;
 use egui_notify::Toasts;
+#[cfg(not(target_family = "wasm"))]
+use notify_debouncer_mini::{Debouncer, notify::RecommendedWatcher};
+#[cfg(not(target_family = "wasm"))]
+use plugin_interface::{Permission, ToastLevel};
+#[cfg(not(target_family = "wasm"))]
+use tokio::sync::mpsc;
+#[cfg(not(target_family = "wasm"))]
 use uuid::Uuid;
 
 use crate::utils::{async_task, open_by_os};
+#[cfg(not(target_family = "wasm"))]
+use crate::{
+    host::{HostCommand, HostContext},
+    plugin_instance::{PLUGINS_DIR, PluginInstance, PluginState},
+    plugin_p2p::P2pNode,
+    plugin_package::PluginPackage,
+    plugin_window::PluginWindowViewport,
+};
 
-trait WindowViewport {
+pub trait WindowViewport {
     fn update(&mut self, ui: &mut egui::Ui);
 }
 
@@ -14,38 +32,318 @@ struct Window {
     title: String,
     is_open: bool,
     is_exit: bool,
+    #[cfg(not(target_family = "wasm"))]
+    plugin: Option<(PathBuf, String)>,
 }
 impl Window {
-    fn new(id: egui::Id, title: impl Into<String>, default_open: bool) -> Self {
+    #[cfg(not(target_family = "wasm"))]
+    fn new(
+        id: egui::Id,
+        title: impl Into<String>,
+        default_open: bool,
+        plugin: Option<(PathBuf, String)>,
+    ) -> Self {
         Self {
             id,
             title: title.into(),
             is_open: default_open,
             is_exit: false,
+            plugin,
         }
     }
 }
 
-struct Demo;
-impl WindowViewport for Demo {
-    fn update(&mut self, ui: &mut egui::Ui) {
-        if ui.button("qwdqwdqwd").clicked() {}
-    }
-}
-
 pub struct Viewport {
     toasts: Toasts,
     windows: Vec<(Window, Box<dyn WindowViewport>)>,
+    #[cfg(not(target_family = "wasm"))]
+    packages: Vec<PluginPackage>,
+    #[cfg(not(target_family = "wasm"))]
+    plugins: Vec<PluginInstance>,
+    #[cfg(not(target_family = "wasm"))]
+    plugin_errors: Vec<(PathBuf, String)>,
+    #[cfg(not(target_family = "wasm"))]
+    host_context: HostContext,
+    #[cfg(not(target_family = "wasm"))]
+    host_commands: mpsc::UnboundedReceiver<HostCommand>,
+    #[cfg(not(target_family = "wasm"))]
+    _plugin_watcher: Option<Debouncer<RecommendedWatcher>>,
 }
 impl Viewport {
-    pub fn new(cc: &eframe::CreationContext) -> Result<Self> {
+    pub fn new(
+        cc: &eframe::CreationContext,
+        #[cfg(not(target_family = "wasm"))] p2p_node: Option<P2pNode>,
+    ) -> Result<Self> {
         async_task(set_font(cc.egui_ctx.clone()));
-        Ok(Self {
-            toasts: Toasts::new()
-                .with_anchor(egui_notify::Anchor::BottomRight)
-                .with_margin(egui::vec2(1., 32.)),
+        egui_extras::install_image_loaders(&cc.egui_ctx);
+        #[allow(unused_mut)]
+        let mut toasts = Toasts::new()
+            .with_anchor(egui_notify::Anchor::BottomRight)
+            .with_margin(egui::vec2(1., 32.));
+        #[cfg(not(target_family = "wasm"))]
+        let (host_context, host_commands) = HostContext::new(cc.egui_ctx.clone(), p2p_node);
+        #[cfg(not(target_family = "wasm"))]
+        let (packages, errors) = PluginPackage::discover(PLUGINS_DIR);
+        #[cfg(not(target_family = "wasm"))]
+        let plugin_errors = errors
+            .into_iter()
+            .map(|(path, err)| {
+                toasts.error(format!("读取插件包 {} 失败: {}", path.display(), err));
+                (path, err.to_string())
+            })
+            .collect();
+        #[cfg(not(target_family = "wasm"))]
+        let plugin_watcher = PluginInstance::watch_dir(PLUGINS_DIR, &host_context)
+            .inspect_err(|err| log::error!("无法监视插件目录: {}", err))
+            .ok();
+        #[allow(unused_mut)]
+        let mut viewport = Self {
+            toasts,
             windows: vec![],
-        })
+            #[cfg(not(target_family = "wasm"))]
+            packages,
+            #[cfg(not(target_family = "wasm"))]
+            plugins: vec![],
+            #[cfg(not(target_family = "wasm"))]
+            plugin_errors,
+            #[cfg(not(target_family = "wasm"))]
+            host_context,
+            #[cfg(not(target_family = "wasm"))]
+            host_commands,
+            #[cfg(not(target_family = "wasm"))]
+            _plugin_watcher: plugin_watcher,
+        };
+        #[cfg(not(target_family = "wasm"))]
+        for index in 0..viewport.packages.len() {
+            match viewport.packages[index].check() {
+                Ok(()) => {
+                    viewport.load_plugin(index, None);
+                }
+                Err(err) => log::warn!(
+                    "跳过插件 {}: {}",
+                    viewport.packages[index].path().display(),
+                    err
+                ),
+            }
+        }
+        Ok(viewport)
+    }
+    #[cfg(not(target_family = "wasm"))]
+    fn load_plugin(&mut self, package_index: usize, state: Option<Vec<u8>>) -> Option<usize> {
+        let package = &self.packages[package_index];
+        let path = package.path().to_path_buf();
+        self.plugin_errors
+            .retain(|(error_path, _)| *error_path != path);
+        match PluginInstance::load(package, &self.host_context, state) {
+            Ok(plugin) => {
+                log::info!("已加载插件 {}: {}", plugin.info().name, path.display());
+                self.plugins.push(plugin);
+                Some(self.plugins.len() - 1)
+            }
+            Err(err) => {
+                log::error!("加载插件 {} 失败: {}", path.display(), err);
+                self.toasts
+                    .error(format!("加载插件 {} 失败: {}", path.display(), err));
+                self.plugin_errors.push((path, err.to_string()));
+                None
+            }
+        }
+    }
+    #[cfg(not(target_family = "wasm"))]
+    fn open_plugin_window(&mut self, index: usize, window_type_id: &str) {
+        let Some(window_type) = self.plugins[index]
+            .window_types()
+            .iter()
+            .find(|window_type| window_type.id == window_type_id)
+            .cloned()
+        else {
+            self.toasts
+                .error(format!("插件没有应用 {}", window_type_id));
+            return;
+        };
+        let id = egui::Id::new(Uuid::new_v4());
+        match self.plugins[index].create_window(&window_type.id) {
+            Ok(Some(handle)) => self.windows.push((
+                Window::new(
+                    id,
+                    window_type.title,
+                    true,
+                    Some((self.plugins[index].path().to_path_buf(), window_type.id)),
+                ),
+                Box::new(PluginWindowViewport::new(handle, id.value())),
+            )),
+            Ok(None) => {
+                self.toasts
+                    .error(format!("插件无法打开应用 {}", window_type.title));
+            }
+            Err(err) => {
+                self.toasts
+                    .error(format!("插件打开应用 {} 失败: {}", window_type.title, err));
+            }
+        }
+    }
+    #[cfg(not(target_family = "wasm"))]
+    fn close_plugin_windows(&mut self, index: usize) {
+        let path = self.plugins[index].path();
+        self.windows.retain(|(window, _)| {
+            window
+                .plugin
+                .as_ref()
+                .is_none_or(|(plugin, _)| plugin != path)
+        });
+    }
+    #[cfg(not(target_family = "wasm"))]
+    fn enable_plugin(&mut self, index: usize) {
+        if let Err(err) = self.plugins[index].enable() {
+            self.toasts.error(format!(
+                "启用插件 {} 失败: {}",
+                self.plugins[index].info().name,
+                err
+            ));
+        }
+    }
+    #[cfg(not(target_family = "wasm"))]
+    fn disable_plugin(&mut self, index: usize) {
+        self.close_plugin_windows(index);
+        if let Err(err) = self.plugins[index].disable() {
+            self.toasts.error(format!(
+                "停用插件 {} 失败: {}",
+                self.plugins[index].info().name,
+                err
+            ));
+        }
+    }
+    #[cfg(not(target_family = "wasm"))]
+    fn unload_plugin(&mut self, index: usize) {
+        self.close_plugin_windows(index);
+        let plugin = self.plugins.remove(index);
+        log::info!("已卸载插件 {}", plugin.info().name);
+    }
+    #[cfg(not(target_family = "wasm"))]
+    fn reload_plugin(&mut self, path: PathBuf) {
+        let mut state = None;
+        let mut was_enabled = true;
+        let mut window_types = vec![];
+        let was_loaded = match self.plugins.iter().position(|plugin| plugin.path() == path) {
+            Some(index) => {
+                match self.plugins[index].save_state() {
+                    Ok(saved) => state = saved,
+                    Err(err) => log::warn!(
+                        "保存插件 {} 状态失败: {}",
+                        self.plugins[index].info().name,
+                        err
+                    ),
+                }
+                was_enabled = self.plugins[index].state() == PluginState::Enabled;
+                window_types = self
+                    .windows
+                    .iter()
+                    .filter_map(|(window, _)| window.plugin.as_ref())
+                    .filter(|(plugin, _)| *plugin == path)
+                    .map(|(_, window_type)| window_type.clone())
+                    .collect();
+                self.unload_plugin(index);
+                true
+            }
+            None => false,
+        };
+        let was_known = self.packages.iter().any(|package| package.path() == path);
+        self.packages.retain(|package| package.path() != path);
+        self.plugin_errors
+            .retain(|(error_path, _)| *error_path != path);
+        if !PluginPackage::is_package_path(&path) {
+            return;
+        }
+        match PluginPackage::open(&path) {
+            Ok(package) => {
+                self.packages.push(package);
+                self.packages
+                    .sort_by(|left, right| left.path().cmp(right.path()));
+            }
+            Err(err) => {
+                self.toasts
+                    .error(format!("读取插件包 {} 失败: {}", path.display(), err));
+                self.plugin_errors.push((path, err.to_string()));
+                return;
+            }
+        }
+        let Some(package_index) = self
+            .packages
+            .iter()
+            .position(|package| package.path() == path)
+        else {
+            return;
+        };
+        if was_known && !was_loaded {
+            return;
+        }
+        if let Err(err) = self.packages[package_index].check() {
+            self.toasts.warning(err.to_string());
+            return;
+        }
+        let Some(index) = self.load_plugin(package_index, state) else {
+            return;
+        };
+        self.toasts.info(format!(
+            "已{}加载插件 {}",
+            if was_loaded { "重新" } else { "" },
+            self.plugins[index].info().name
+        ));
+        if !was_enabled {
+            self.disable_plugin(index);
+            return;
+        }
+        for window_type in window_types {
+            self.open_plugin_window(index, &window_type);
+        }
+    }
+    #[cfg(not(target_family = "wasm"))]
+    fn update_plugins(&mut self) {
+        for index in 0..self.plugins.len() {
+            if let Err(err) = self.plugins[index].update() {
+                self.toasts.error(format!(
+                    "插件 {} 运行失败，已停用: {}",
+                    self.plugins[index].info().name,
+                    err
+                ));
+                self.disable_plugin(index);
+            }
+        }
+    }
+    #[cfg(not(target_family = "wasm"))]
+    fn handle_host_commands(&mut self) {
+        while let Ok(command) = self.host_commands.try_recv() {
+            match command {
+                HostCommand::Toast(level, text) => {
+                    match level {
+                        ToastLevel::Info => self.toasts.info(text),
+                        ToastLevel::Success => self.toasts.success(text),
+                        ToastLevel::Warning => self.toasts.warning(text),
+                        ToastLevel::Error => self.toasts.error(text),
+                    };
+                }
+                HostCommand::OpenWindow {
+                    plugin,
+                    window_type,
+                } => {
+                    if let Some(index) = self
+                        .plugins
+                        .iter()
+                        .position(|instance| instance.path() == plugin)
+                    {
+                        self.open_plugin_window(index, &window_type);
+                    }
+                }
+                HostCommand::CloseWindow(window_id) => {
+                    for (window, _) in &mut self.windows {
+                        if window.id.value() == window_id {
+                            window.is_exit = true;
+                        }
+                    }
+                }
+                HostCommand::PluginChanged(path) => self.reload_plugin(path),
+            }
+        }
     }
     fn control_bar(&mut self, ui: &mut egui::Ui) {
         #[cfg(not(target_family = "wasm"))]
@@ -58,11 +356,11 @@ impl Viewport {
             if control_bar_response.drag_started_by(egui::PointerButton::Primary) {
                 ui.ctx().send_viewport_cmd(egui::ViewportCommand::StartDrag);
             }
-            if control_bar_response.double_clicked() {
-                if let Some(maximized) = ui.input(|state| state.viewport().maximized) {
-                    ui.ctx()
-                        .send_viewport_cmd(egui::ViewportCommand::Maximized(!maximized));
-                }
+            if control_bar_response.double_clicked()
+                && let Some(maximized) = ui.input(|state| state.viewport().maximized)
+            {
+                ui.ctx()
+                    .send_viewport_cmd(egui::ViewportCommand::Maximized(!maximized));
             }
         }
         ui.painter().line_segment(
@@ -74,15 +372,109 @@ impl Viewport {
 
             ui.add_space(4.);
             ui.menu_button("关于", |ui| {
-                if ui.link("by 张喜昌").clicked() {
-                    if let Err(err) = open_by_os("https://github.com/ZhangXiChang") {
-                        self.toasts.error(err.to_string());
+                if ui.link("by 张喜昌").clicked()
+                    && let Err(err) = open_by_os("https://github.com/ZhangXiChang")
+                {
+                    self.toasts.error(err.to_string());
+                }
+                if ui.link("GitHub").clicked()
+                    && let Err(err) = open_by_os("https://github.com/ZhangXiChang/starlink")
+                {
+                    self.toasts.error(err.to_string());
+                }
+            });
+            #[cfg(not(target_family = "wasm"))]
+            ui.menu_button("插件", |ui| {
+                if self.packages.is_empty() && self.plugin_errors.is_empty() {
+                    ui.label("没有已安装的插件");
+                }
+                let mut action = None;
+                for (package_index, package) in self.packages.iter().enumerate() {
+                    let plugin = self
+                        .plugins
+                        .iter()
+                        .enumerate()
+                        .find(|(_, plugin)| plugin.path() == package.path());
+                    ui.horizontal(|ui| {
+                        if let Some(icon) = package.icon() {
+                            ui.add(
+                                egui::Image::from_bytes(
+                                    format!("bytes://plugins/{}", package.path().display()),
+                                    icon,
+                                )
+                                .fit_to_exact_size(egui::vec2(16., 16.)),
+                            );
+                        }
+                        match plugin {
+                            Some((index, plugin)) => {
+                                let mut enabled = plugin.state() == PluginState::Enabled;
+                                if ui
+                                    .checkbox(
+                                        &mut enabled,
+                                        format!("{} {}", plugin.info().name, plugin.info().version),
+                                    )
+                                    .on_hover_text(package_details(package))
+                                    .changed()
+                                {
+                                    action = Some(PluginAction::SetEnabled(index, enabled));
+                                }
+                                if ui.small_button("卸载").clicked() {
+                                    action = Some(PluginAction::Unload(index));
+                                }
+                            }
+                            None => {
+                                let error =
+                                    package.check().err().map(|err| err.to_string()).or(self
+                                        .plugin_errors
+                                        .iter()
+                                        .find(|(path, _)| path == package.path())
+                                        .map(|(_, err)| err.clone()));
+                                let label = match &error {
+                                    Some(_) => ui.colored_label(
+                                        ui.visuals().error_fg_color,
+                                        package.title(),
+                                    ),
+                                    None => ui.weak(package.title()),
+                                };
+                                label.on_hover_text(match &error {
+                                    Some(err) => format!("{}\n{}", package_details(package), err),
+                                    None => package_details(package),
+                                });
+                                if ui
+                                    .add_enabled(
+                                        package.check().is_ok(),
+                                        egui::Button::new("加载").small(),
+                                    )
+                                    .clicked()
+                                {
+                                    action = Some(PluginAction::Load(package_index));
+                                }
+                            }
+                        }
+                    });
+                }
+                match action {
+                    Some(PluginAction::SetEnabled(index, true)) => self.enable_plugin(index),
+                    Some(PluginAction::SetEnabled(index, false)) => self.disable_plugin(index),
+                    Some(PluginAction::Unload(index)) => {
+                        self.unload_plugin(index);
+                        ui.close_menu();
+                    }
+                    Some(PluginAction::Load(package_index)) => {
+                        self.load_plugin(package_index, None);
+                        ui.close_menu();
                     }
+                    None => {}
                 }
-                if ui.link("GitHub").clicked() {
-                    if let Err(err) = open_by_os("https://github.com/ZhangXiChang/starlink") {
-                        self.toasts.error(err.to_string());
+                for (path, err) in &self.plugin_errors {
+                    if self.packages.iter().any(|package| package.path() == path) {
+                        continue;
                     }
+                    ui.colored_label(
+                        ui.visuals().error_fg_color,
+                        format!("{} 加载失败", path.display()),
+                    )
+                    .on_hover_text(err);
                 }
             });
 
@@ -101,11 +493,10 @@ impl Viewport {
                     if ui
                         .add(egui::Button::new("🗗").min_size(egui::vec2(24., 24.)))
                         .clicked()
+                        && let Some(maximized) = ui.input(|state| state.viewport().maximized)
                     {
-                        if let Some(maximized) = ui.input(|state| state.viewport().maximized) {
-                            ui.ctx()
-                                .send_viewport_cmd(egui::ViewportCommand::Maximized(!maximized));
-                        }
+                        ui.ctx()
+                            .send_viewport_cmd(egui::ViewportCommand::Maximized(!maximized));
                     }
                     if ui
                         .add(egui::Button::new("🗕").min_size(egui::vec2(24., 24.)))
@@ -125,13 +516,33 @@ impl Viewport {
             egui::Sense::click(),
         );
         content_area_response.context_menu(|ui| {
-            if ui.button("测试应用").clicked() {
-                self.windows.push((
-                    Window::new(egui::Id::new(Uuid::new_v4()), "测试应用", true),
-                    Box::new(Demo),
-                ));
-                ui.close_menu();
+            #[cfg(not(target_family = "wasm"))]
+            {
+                let mut selected = None;
+                let enabled_plugins = self
+                    .plugins
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, plugin)| plugin.state() == PluginState::Enabled);
+                for (index, plugin) in enabled_plugins {
+                    for window_type in plugin.window_types() {
+                        if ui.button(&window_type.title).clicked() {
+                            selected = Some((index, window_type.clone()));
+                        }
+                    }
+                }
+                if let Some((index, window_type)) = selected {
+                    self.open_plugin_window(index, &window_type.id);
+                    ui.close_menu();
+                }
+                if self.plugins.iter().all(|plugin| {
+                    plugin.state() != PluginState::Enabled || plugin.window_types().is_empty()
+                }) {
+                    ui.label("没有可打开的应用");
+                }
             }
+            #[cfg(target_family = "wasm")]
+            ui.label("没有可打开的应用");
         });
         ui.painter().text(
             ui.max_rect().center(),
@@ -176,6 +587,11 @@ impl Viewport {
 }
 impl eframe::App for Viewport {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        #[cfg(not(target_family = "wasm"))]
+        {
+            self.handle_host_commands();
+            self.update_plugins();
+        }
         ctx.style_mut(|style| {
             style.spacing.item_spacing = egui::Vec2::ZERO;
         });
@@ -221,6 +637,43 @@ impl eframe::App for Viewport {
     }
 }
 
+#[cfg(not(target_family = "wasm"))]
+enum PluginAction {
+    SetEnabled(usize, bool),
+    Unload(usize),
+    Load(usize),
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn package_details(package: &PluginPackage) -> String {
+    let mut details = package.path().display().to_string();
+    if let Some(manifest) = package.manifest() {
+        if !manifest.description.is_empty() {
+            details = format!("{}\n{}", manifest.description, details);
+        }
+        if !manifest.authors.is_empty() {
+            details.push_str(&format!("\n作者: {}", manifest.authors.join(", ")));
+        }
+        let permissions = manifest
+            .permissions
+            .iter()
+            .map(|permission| match permission {
+                Permission::OpenUrl => "打开链接",
+                Permission::Network => "网络",
+            })
+            .collect::<Vec<_>>();
+        details.push_str(&format!(
+            "\n权限: {}",
+            if permissions.is_empty() {
+                "无".to_string()
+            } else {
+                permissions.join(", ")
+            }
+        ));
+    }
+    details
+}
+
 async fn set_font(ctx: egui::Context) {
     if let Err(err) = async move {
         let font;
@@ -0,0 +1,226 @@
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow};
+use parking_lot::Mutex;
+use plugin_interface::{
+    HostServices, Lifecycle, LogLevel, PluginInfo, ToastLevel, UiEvent, VersionReq, Widget,
+    WindowType,
+};
+use wasmtime::{
+    Config, Engine, Store, StoreLimits, StoreLimitsBuilder,
+    component::{Component, Linker},
+};
+
+use crate::{
+    host::{HostContext, PluginHost},
+    plugin_package::PluginPackage,
+    plugin_window::PluginWindowBackend,
+};
+
+const MAX_MEMORY_SIZE: usize = 64 * 1024 * 1024;
+const MAX_INSTANCES: usize = 16;
+const FUEL_PER_CALL: u64 = 1_000_000_000;
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "../plugin-interface/wit",
+        world: "plugin",
+    });
+}
+
+use bindings::starlink::plugin::{host as wit_host, types as wit_types};
+
+struct WasmState {
+    host: Option<Arc<PluginHost>>,
+    limits: StoreLimits,
+}
+impl wit_types::Host for WasmState {}
+impl wit_host::Host for WasmState {
+    fn toast(&mut self, level: wit_host::ToastLevel, text: String) {
+        let level = match level {
+            wit_host::ToastLevel::Info => ToastLevel::Info,
+            wit_host::ToastLevel::Success => ToastLevel::Success,
+            wit_host::ToastLevel::Warning => ToastLevel::Warning,
+            wit_host::ToastLevel::Error => ToastLevel::Error,
+        };
+        if let Some(host) = &self.host {
+            host.toast(level, &text);
+        }
+    }
+    fn open_url(&mut self, url: String) -> bool {
+        self.host.as_ref().is_some_and(|host| host.open_url(&url))
+    }
+    fn log(&mut self, level: wit_host::LogLevel, message: String) {
+        let level = match level {
+            wit_host::LogLevel::Error => LogLevel::Error,
+            wit_host::LogLevel::Warn => LogLevel::Warn,
+            wit_host::LogLevel::Info => LogLevel::Info,
+            wit_host::LogLevel::Debug => LogLevel::Debug,
+            wit_host::LogLevel::Trace => LogLevel::Trace,
+        };
+        if let Some(host) = &self.host {
+            host.log(level, &message);
+        }
+    }
+    fn setting(&mut self, key: String) -> Option<String> {
+        self.host.as_ref()?.setting(&key)
+    }
+    fn set_setting(&mut self, key: String, value: String) {
+        if let Some(host) = &self.host {
+            host.set_setting(&key, &value);
+        }
+    }
+    fn remove_setting(&mut self, key: String) {
+        if let Some(host) = &self.host {
+            host.remove_setting(&key);
+        }
+    }
+    fn open_window(&mut self, window_type: String) {
+        if let Some(host) = &self.host {
+            host.open_window(&window_type);
+        }
+    }
+    fn close_window(&mut self, window_id: u64) {
+        if let Some(host) = &self.host {
+            host.close_window(window_id);
+        }
+    }
+    fn p2p(&mut self, call: Vec<u8>) -> Result<Vec<u8>, String> {
+        let host = self.host.as_ref().ok_or("插件尚未初始化")?;
+        let call =
+            postcard::from_bytes(&call).map_err(|err| format!("无法解析 P2P 调用: {}", err))?;
+        postcard::to_stdvec(&host.p2p(call)?).map_err(|err| err.to_string())
+    }
+}
+
+pub struct WasmPlugin {
+    store: Store<WasmState>,
+    bindings: bindings::Plugin,
+}
+impl WasmPlugin {
+    fn engine() -> Result<Engine> {
+        let mut config = Config::new();
+        config.wasm_component_model(true).consume_fuel(true);
+        Engine::new(&config)
+    }
+    fn component(engine: &Engine, bytes: &[u8]) -> Result<Component> {
+        if wasmparser::Parser::is_core_wasm(bytes) {
+            let bytes = wit_component::ComponentEncoder::default()
+                .module(bytes)?
+                .validate(true)
+                .encode()?;
+            return Component::new(engine, bytes);
+        }
+        Component::new(engine, bytes)
+    }
+    pub fn load(
+        package: &PluginPackage,
+        bytes: &[u8],
+        host_context: &HostContext,
+    ) -> Result<(PluginInfo, Self)> {
+        let engine = Self::engine()?;
+        let component = Self::component(&engine, bytes)?;
+        let mut linker = Linker::new(&engine);
+        bindings::Plugin::add_to_linker(&mut linker, |state: &mut WasmState| state)?;
+        let mut store = Store::new(
+            &engine,
+            WasmState {
+                host: None,
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(MAX_MEMORY_SIZE)
+                    .instances(MAX_INSTANCES)
+                    .build(),
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(FUEL_PER_CALL)?;
+        let bindings = bindings::Plugin::instantiate(&mut store, &component, &linker)?;
+        let mut plugin = Self { store, bindings };
+        let metadata = plugin.call(|bindings, store| bindings.call_metadata(store))?;
+        let info = PluginInfo {
+            name: metadata.name,
+            version: metadata.version.parse()?,
+            host_requirement: VersionReq::parse(&metadata.host_requirement)?,
+        };
+        if info.is_compatible() {
+            plugin.store.data_mut().host = Some(host_context.plugin_services(package, &info)?);
+            plugin.call(|bindings, store| bindings.call_instantiate(store))?;
+        }
+        Ok((info, plugin))
+    }
+    pub fn host(&self) -> Option<Arc<PluginHost>> {
+        self.store.data().host.clone()
+    }
+    fn call<T>(
+        &mut self,
+        f: impl FnOnce(&bindings::Plugin, &mut Store<WasmState>) -> wasmtime::Result<T>,
+    ) -> Result<T> {
+        self.store.set_fuel(FUEL_PER_CALL)?;
+        f(&self.bindings, &mut self.store).map_err(|err| anyhow!("插件执行失败: {:?}", err))
+    }
+    pub fn lifecycle(&mut self, lifecycle: Lifecycle) -> Result<()> {
+        let lifecycle = match lifecycle {
+            Lifecycle::Load => wit_types::Lifecycle::Load,
+            Lifecycle::Enable => wit_types::Lifecycle::Enable,
+            Lifecycle::Update => wit_types::Lifecycle::Update,
+            Lifecycle::Disable => wit_types::Lifecycle::Disable,
+            Lifecycle::Unload => wit_types::Lifecycle::Unload,
+        };
+        self.call(|bindings, store| bindings.call_lifecycle(store, lifecycle))
+    }
+    pub fn save_state(&mut self) -> Result<Option<Vec<u8>>> {
+        self.call(|bindings, store| bindings.call_save_state(store))
+    }
+    pub fn restore_state(&mut self, state: Vec<u8>) -> Result<()> {
+        self.call(|bindings, store| bindings.call_restore_state(store, &state))
+    }
+    pub fn window_types(&mut self) -> Result<Vec<WindowType>> {
+        Ok(self
+            .call(|bindings, store| bindings.call_window_types(store))?
+            .into_iter()
+            .map(|window_type| WindowType::new(window_type.id, window_type.title))
+            .collect())
+    }
+    pub fn create_window(&mut self, id: &str) -> Result<Option<u64>> {
+        self.call(|bindings, store| bindings.call_create_window(store, id))
+    }
+    fn update_window(
+        &mut self,
+        handle: u64,
+        window_id: u64,
+        events: &[UiEvent],
+    ) -> Result<Vec<Widget>> {
+        let events = postcard::to_stdvec(events)?;
+        let widgets = self.call(|bindings, store| {
+            bindings.call_update_window(store, handle, window_id, &events)
+        })?;
+        Ok(postcard::from_bytes(&widgets)?)
+    }
+    fn drop_window(&mut self, handle: u64) -> Result<()> {
+        self.call(|bindings, store| bindings.call_drop_window(store, handle))
+    }
+}
+
+pub struct WasmWindow {
+    plugin: Arc<Mutex<WasmPlugin>>,
+    handle: u64,
+}
+impl WasmWindow {
+    pub fn new(plugin: Arc<Mutex<WasmPlugin>>, handle: u64) -> Self {
+        Self { plugin, handle }
+    }
+}
+impl PluginWindowBackend for WasmWindow {
+    fn update(&mut self, window_id: u64, events: &[UiEvent]) -> Result<Vec<Widget>> {
+        self.plugin
+            .lock()
+            .update_window(self.handle, window_id, events)
+    }
+}
+impl Drop for WasmWindow {
+    fn drop(&mut self) {
+        if let Err(err) = self.plugin.lock().drop_window(self.handle) {
+            log::warn!("关闭插件窗口失败: {}", err);
+        }
+    }
+}
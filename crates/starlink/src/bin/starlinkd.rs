@@ -0,0 +1,63 @@
+fn main() {
+    cei::main();
+}
+
+#[cfg(not(target_family = "wasm"))]
+mod cei {
+    use std::path::PathBuf;
+
+    use anyhow::Result;
+    use clap::Parser;
+    use starlink::{DAEMON_ADDR, DaemonOptions, Starlink, default_download_dir};
+
+    #[derive(Parser)]
+    #[command(name = "starlinkd", about = "Starlink 守护进程")]
+    struct Cli {
+        #[arg(long, default_value = DAEMON_ADDR, help = "控制地址")]
+        addr: String,
+        #[arg(long, help = "下载目录，默认为数据目录下的 downloads")]
+        download_dir: Option<PathBuf>,
+        #[arg(
+            long = "share-dir",
+            help = "允许分享的目录，可重复指定，默认为当前目录"
+        )]
+        share_dirs: Vec<PathBuf>,
+    }
+
+    #[tokio::main]
+    pub async fn main() {
+        env_logger::builder()
+            .filter_level(log::LevelFilter::Off)
+            .filter_module("starlink", log::LevelFilter::Info)
+            .parse_default_env()
+            .init();
+        if let Err(err) = run(Cli::parse()).await {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+    async fn run(cli: Cli) -> Result<()> {
+        let starlink = Starlink::new().await?;
+        log::info!(
+            "节点 {} 已启动，控制地址 {}",
+            starlink.node_addr().await?.node_id,
+            cli.addr
+        );
+        let mut options = DaemonOptions {
+            download_dir: cli.download_dir.unwrap_or_else(default_download_dir),
+            ..Default::default()
+        };
+        if !cli.share_dirs.is_empty() {
+            options.share_dirs = cli.share_dirs;
+        }
+        tokio::select! {
+            result = starlink.serve_daemon(cli.addr, options) => result,
+            result = tokio::signal::ctrl_c() => Ok(result?),
+        }
+    }
+}
+
+#[cfg(target_family = "wasm")]
+mod cei {
+    pub fn main() {}
+}
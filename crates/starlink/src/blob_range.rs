@@ -0,0 +1,264 @@
+use std::{
+    io::SeekFrom,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use anyhow::{Result, bail};
+use bao_tree::{ChunkNum, ChunkRanges, io::BaoContentItem};
+use bytes::Bytes;
+use iroh::endpoint::Connection;
+use iroh_blobs::{
+    get::{
+        fsm::{self, BlobContentNext, ConnectedNext, EndBlobNext},
+        request::get_verified_size,
+    },
+    protocol::{GetRequest, RangeSpecSeq},
+    rpc::client::blobs::ReadAtLen,
+    ticket::BlobTicket,
+};
+use n0_future::boxed::BoxFuture;
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+
+use crate::Starlink;
+
+const READ_AHEAD_SIZE: u64 = 256 * 1024;
+
+struct RangeAssembler {
+    next: u64,
+    end: u64,
+    bytes: Vec<u8>,
+}
+impl RangeAssembler {
+    fn new(offset: u64, end: u64) -> Self {
+        Self {
+            next: offset,
+            end,
+            bytes: vec![],
+        }
+    }
+    fn push(&mut self, leaf_offset: u64, data: &[u8]) -> Result<()> {
+        let leaf_end = leaf_offset + data.len() as u64;
+        if leaf_end <= self.next || self.next >= self.end {
+            return Ok(());
+        }
+        if leaf_offset > self.next {
+            bail!("数据块缺失: {}..{}", self.next, leaf_offset);
+        }
+        let stop = leaf_end.min(self.end);
+        self.bytes.extend_from_slice(
+            &data[(self.next - leaf_offset) as usize..(stop - leaf_offset) as usize],
+        );
+        self.next = stop;
+        Ok(())
+    }
+    fn finish(self) -> Result<Bytes> {
+        if self.next < self.end {
+            bail!("数据块缺失: {}..{}", self.next, self.end);
+        }
+        Ok(self.bytes.into())
+    }
+}
+
+pub struct BlobRangeReader {
+    starlink: Starlink,
+    ticket: BlobTicket,
+    connection: Option<Connection>,
+    size: u64,
+    position: u64,
+    buffer: Bytes,
+    buffer_offset: u64,
+    fetching: Option<BoxFuture<Result<Bytes>>>,
+}
+impl BlobRangeReader {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+impl AsyncRead for BlobRangeReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        loop {
+            if self.position >= self.size || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            let buffer_end = self.buffer_offset + self.buffer.len() as u64;
+            if self.position >= self.buffer_offset && self.position < buffer_end {
+                let start = (self.position - self.buffer_offset) as usize;
+                let len = buf.remaining().min(self.buffer.len() - start);
+                buf.put_slice(&self.buffer[start..start + len]);
+                self.position += len as u64;
+                return Poll::Ready(Ok(()));
+            }
+            let position = self.position;
+            let fetching = match &mut self.fetching {
+                Some(fetching) => fetching,
+                None => {
+                    let starlink = self.starlink.clone();
+                    let ticket = self.ticket.clone();
+                    let connection = self.connection.clone();
+                    self.fetching.insert(Box::pin(async move {
+                        starlink
+                            .fetch_range(&ticket, connection.as_ref(), position, READ_AHEAD_SIZE)
+                            .await
+                    }))
+                }
+            };
+            let bytes = match fetching.as_mut().poll(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => return Poll::Pending,
+            };
+            self.fetching = None;
+            let bytes = bytes.map_err(std::io::Error::other)?;
+            if bytes.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            self.buffer_offset = position;
+            self.buffer = bytes;
+        }
+    }
+}
+impl AsyncSeek for BlobRangeReader {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        }
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "无效的偏移"))?;
+        self.position = position;
+        self.fetching = None;
+        Ok(())
+    }
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+impl Starlink {
+    pub async fn read_range(&self, ticket: BlobTicket, offset: u64, len: u64) -> Result<Bytes> {
+        let connection = self.range_connection(&ticket).await?;
+        self.fetch_range(&ticket, connection.as_ref(), offset, len)
+            .await
+    }
+    pub async fn range_reader(&self, ticket: BlobTicket) -> Result<BlobRangeReader> {
+        let connection = self.range_connection(&ticket).await?;
+        let size = match &connection {
+            Some(connection) => get_verified_size(connection, &ticket.hash()).await?.0,
+            None => self.blobs.client().read(ticket.hash()).await?.size(),
+        };
+        Ok(BlobRangeReader {
+            starlink: self.clone(),
+            ticket,
+            connection,
+            size,
+            position: 0,
+            buffer: Bytes::new(),
+            buffer_offset: 0,
+            fetching: None,
+        })
+    }
+    async fn range_connection(&self, ticket: &BlobTicket) -> Result<Option<Connection>> {
+        if self.blobs.client().has(ticket.hash()).await? {
+            return Ok(None);
+        }
+        Ok(Some(
+            self.router
+                .endpoint()
+                .connect(ticket.node_addr().clone(), iroh_blobs::ALPN)
+                .await?,
+        ))
+    }
+    async fn fetch_range(
+        &self,
+        ticket: &BlobTicket,
+        connection: Option<&Connection>,
+        offset: u64,
+        len: u64,
+    ) -> Result<Bytes> {
+        let Some(connection) = connection else {
+            let size = self.blobs.client().read(ticket.hash()).await?.size();
+            if offset >= size {
+                return Ok(Bytes::new());
+            }
+            return self
+                .blobs
+                .client()
+                .read_at_to_bytes(ticket.hash(), offset, ReadAtLen::AtMost(len))
+                .await;
+        };
+        let end = offset.saturating_add(len);
+        let request = GetRequest::new(
+            ticket.hash(),
+            RangeSpecSeq::from_ranges([ChunkRanges::from(
+                ChunkNum::full_chunks(offset)..ChunkNum::chunks(end),
+            )]),
+        );
+        let ConnectedNext::StartRoot(start_root) = fsm::start(connection.clone(), request)
+            .next()
+            .await?
+            .next()
+            .await?
+        else {
+            bail!("意外的响应");
+        };
+        let (mut content, size) = start_root.next().next().await?;
+        let mut assembler = RangeAssembler::new(offset, end.min(size));
+        let end_blob = loop {
+            match content.next().await {
+                BlobContentNext::More((next, item)) => {
+                    if let BaoContentItem::Leaf(leaf) = item? {
+                        self.throttle_download(ticket.node_addr().node_id, leaf.data.len() as u64)
+                            .await;
+                        assembler.push(leaf.offset, &leaf.data)?;
+                    }
+                    content = next;
+                }
+                BlobContentNext::Done(end_blob) => break end_blob,
+            }
+        };
+        if let EndBlobNext::Closing(closing) = end_blob.next() {
+            closing.next().await?;
+        }
+        assembler.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assembles_requested_window_from_leaves() {
+        let mut assembler = RangeAssembler::new(3, 10);
+        assembler.push(0, b"abcdef").unwrap();
+        assembler.push(6, b"ghijkl").unwrap();
+        assert_eq!(assembler.finish().unwrap(), Bytes::from_static(b"defghij"));
+    }
+
+    #[test]
+    fn rejects_gaps_between_leaves() {
+        let mut assembler = RangeAssembler::new(0, 12);
+        assembler.push(0, b"abcd").unwrap();
+        assert!(assembler.push(8, b"ijkl").is_err());
+    }
+
+    #[test]
+    fn rejects_missing_tail() {
+        let mut assembler = RangeAssembler::new(0, 12);
+        assembler.push(0, b"abcdef").unwrap();
+        assert!(assembler.finish().is_err());
+    }
+
+    #[test]
+    fn empty_range_is_empty() {
+        let assembler = RangeAssembler::new(5, 5);
+        assert!(assembler.finish().unwrap().is_empty());
+    }
+}
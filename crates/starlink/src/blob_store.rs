@@ -0,0 +1,434 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use iroh::NodeAddr;
+use iroh_blobs::{
+    BlobFormat, Hash, HashAndFormat, hashseq::HashSeq, rpc::client::blobs::MemClient,
+};
+use n0_future::StreamExt;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{Starlink, write_atomic};
+
+const PIN_TAG_PREFIX: &str = "pin-";
+const AUTO_TAG_PREFIX: &str = "auto-";
+const MAX_PROVIDERS: usize = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlobEntry {
+    pub hash: Hash,
+    pub size: u64,
+    pub tags: Vec<String>,
+    pub pinned: bool,
+    pub added_at: SystemTime,
+    pub last_access: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BlobStoreUsage {
+    pub blob_count: usize,
+    pub total_size: u64,
+    pub quota: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct BlobMeta {
+    added_at: SystemTime,
+    last_access: SystemTime,
+    #[serde(default)]
+    providers: Vec<NodeAddr>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct BlobMetaFile {
+    quota: Option<u64>,
+    blobs: HashMap<Hash, BlobMeta>,
+}
+
+#[derive(Clone)]
+pub(crate) struct BlobMetaStore {
+    path: PathBuf,
+    file: Arc<Mutex<BlobMetaFile>>,
+}
+impl BlobMetaStore {
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = match std::fs::read(&path) {
+            Ok(bytes) => match serde_json::from_slice(&bytes) {
+                Ok(file) => file,
+                Err(err) => {
+                    log::error!(
+                        "区块元数据文件 {} 已损坏，将从空元数据开始: {}",
+                        path.display(),
+                        err
+                    );
+                    BlobMetaFile::default()
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BlobMetaFile::default(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            path,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+    fn save(&self, file: &BlobMetaFile) -> Result<()> {
+        write_atomic(&self.path, &serde_json::to_vec(file)?)
+    }
+    pub(crate) fn touch(&self, hash: Hash) -> Result<()> {
+        let mut file = self.file.lock();
+        let now = SystemTime::now();
+        file.blobs
+            .entry(hash)
+            .and_modify(|meta| meta.last_access = now)
+            .or_insert(BlobMeta {
+                added_at: now,
+                last_access: now,
+                providers: vec![],
+            });
+        self.save(&file)
+    }
+    pub(crate) fn add_provider(&self, hash: Hash, node_addr: NodeAddr) -> Result<()> {
+        let mut file = self.file.lock();
+        let Some(meta) = file.blobs.get_mut(&hash) else {
+            return Ok(());
+        };
+        meta.providers
+            .retain(|provider| provider.node_id != node_addr.node_id);
+        meta.providers.insert(0, node_addr);
+        meta.providers.truncate(MAX_PROVIDERS);
+        self.save(&file)
+    }
+    pub(crate) fn providers(&self, hash: Hash) -> Vec<NodeAddr> {
+        self.file
+            .lock()
+            .blobs
+            .get(&hash)
+            .map(|meta| meta.providers.clone())
+            .unwrap_or_default()
+    }
+    fn remove(&self, hash: Hash) -> Result<()> {
+        let mut file = self.file.lock();
+        if file.blobs.remove(&hash).is_some() {
+            self.save(&file)?;
+        }
+        Ok(())
+    }
+    fn get(&self, hash: Hash) -> Option<BlobMeta> {
+        self.file.lock().blobs.get(&hash).cloned()
+    }
+    fn quota(&self) -> Option<u64> {
+        self.file.lock().quota
+    }
+    fn set_quota(&self, quota: Option<u64>) -> Result<()> {
+        let mut file = self.file.lock();
+        file.quota = quota;
+        self.save(&file)
+    }
+}
+
+fn pin_tag(hash: Hash) -> String {
+    format!("{}{}", PIN_TAG_PREFIX, hash)
+}
+
+#[derive(Default)]
+struct BlobRoots {
+    reachable: HashSet<Hash>,
+    protected: HashSet<Hash>,
+}
+
+fn eviction_candidates(
+    mut entries: Vec<BlobEntry>,
+    protected: &HashSet<Hash>,
+    quota: u64,
+) -> Vec<BlobEntry> {
+    let mut total_size = entries.iter().map(|entry| entry.size).sum::<u64>();
+    entries.retain(|entry| !entry.pinned && !protected.contains(&entry.hash));
+    entries.sort_by_key(|entry| entry.last_access);
+    let mut candidates = vec![];
+    for entry in entries {
+        if total_size <= quota {
+            break;
+        }
+        total_size -= entry.size;
+        candidates.push(entry);
+    }
+    candidates
+}
+
+async fn tagged_roots(client: &MemClient, protected: HashSet<Hash>) -> Result<BlobRoots> {
+    let mut roots = BlobRoots {
+        protected,
+        ..Default::default()
+    };
+    let mut hash_seqs = vec![];
+    let mut tag_stream = client.tags().list().await?;
+    while let Some(tag) = tag_stream.next().await {
+        let tag = tag?;
+        roots.reachable.insert(tag.hash);
+        if !tag.name.0.starts_with(AUTO_TAG_PREFIX.as_bytes()) {
+            roots.protected.insert(tag.hash);
+        }
+        if tag.format == BlobFormat::HashSeq {
+            hash_seqs.push(tag.hash);
+        }
+    }
+    for hash_seq in hash_seqs {
+        let children = match client.read_to_bytes(hash_seq).await {
+            Ok(bytes) => HashSeq::try_from(bytes),
+            Err(err) => Err(err),
+        };
+        match children {
+            Ok(children) => {
+                roots.reachable.extend(children.iter());
+                roots.protected.extend(children.iter());
+            }
+            Err(err) => log::warn!("跳过不完整的哈希序列 {}: {}", hash_seq, err),
+        }
+    }
+    Ok(roots)
+}
+
+impl Starlink {
+    pub async fn list_blobs(&self) -> Result<Vec<BlobEntry>> {
+        let mut tags = HashMap::<Hash, Vec<String>>::new();
+        let mut tag_stream = self.blobs.client().tags().list().await?;
+        while let Some(tag) = tag_stream.next().await {
+            let tag = tag?;
+            tags.entry(tag.hash)
+                .or_default()
+                .push(String::from_utf8_lossy(&tag.name.0).into_owned());
+        }
+        let mut entries = vec![];
+        let mut blob_stream = self.blobs.client().list().await?;
+        while let Some(blob) = blob_stream.next().await {
+            let blob = blob?;
+            let tags = tags.remove(&blob.hash).unwrap_or_default();
+            let pinned = tags.contains(&pin_tag(blob.hash));
+            let meta = self.blob_meta.get(blob.hash).unwrap_or(BlobMeta {
+                added_at: SystemTime::UNIX_EPOCH,
+                last_access: SystemTime::UNIX_EPOCH,
+                providers: vec![],
+            });
+            entries.push(BlobEntry {
+                hash: blob.hash,
+                size: blob.size,
+                tags,
+                pinned,
+                added_at: meta.added_at,
+                last_access: meta.last_access,
+            });
+        }
+        Ok(entries)
+    }
+    pub async fn blob_store_usage(&self) -> Result<BlobStoreUsage> {
+        let mut blob_count = 0;
+        let mut total_size = 0;
+        let mut blob_stream = self.blobs.client().list().await?;
+        while let Some(blob) = blob_stream.next().await {
+            blob_count += 1;
+            total_size += blob?.size;
+        }
+        let mut incomplete_stream = self.blobs.client().list_incomplete().await?;
+        while let Some(blob) = incomplete_stream.next().await {
+            total_size += blob?.size;
+        }
+        Ok(BlobStoreUsage {
+            blob_count,
+            total_size,
+            quota: self.blob_meta.quota(),
+        })
+    }
+    pub async fn tag_blob(&self, hash: Hash, name: impl AsRef<str>) -> Result<()> {
+        self.blobs
+            .client()
+            .tags()
+            .set(name.as_ref(), HashAndFormat::raw(hash))
+            .await
+    }
+    pub async fn untag_blob(&self, name: impl AsRef<str>) -> Result<()> {
+        self.blobs.client().tags().delete(name.as_ref()).await
+    }
+    pub async fn pin_blob(&self, hash: Hash) -> Result<()> {
+        self.tag_blob(hash, pin_tag(hash)).await
+    }
+    pub async fn unpin_blob(&self, hash: Hash) -> Result<()> {
+        self.untag_blob(pin_tag(hash)).await
+    }
+    pub async fn delete_blob(&self, hash: Hash) -> Result<()> {
+        let mut tag_stream = self.blobs.client().tags().list().await?;
+        let mut names = vec![];
+        while let Some(tag) = tag_stream.next().await {
+            let tag = tag?;
+            if tag.hash == hash {
+                names.push(tag.name);
+            }
+        }
+        for name in names {
+            self.blobs.client().tags().delete(name).await?;
+        }
+        self.blobs.client().delete_blob(hash).await?;
+        self.blob_meta.remove(hash)
+    }
+    async fn blob_roots(&self, keep: &[Hash]) -> Result<BlobRoots> {
+        let mut protected = keep.iter().copied().collect::<HashSet<_>>();
+        protected.extend(self.shares.shared_hashes());
+        tagged_roots(self.blobs.client(), protected).await
+    }
+    pub async fn gc_blobs(&self) -> Result<u64> {
+        let mut freed_size = self.enforce_blob_quota(&[]).await?;
+        let roots = self.blob_roots(&[]).await?;
+        for entry in self.list_blobs().await? {
+            if !roots.reachable.contains(&entry.hash) {
+                self.blobs.client().delete_blob(entry.hash).await?;
+                self.blob_meta.remove(entry.hash)?;
+                freed_size += entry.size;
+            }
+        }
+        Ok(freed_size)
+    }
+    pub async fn set_blob_quota(&self, quota: Option<u64>) -> Result<()> {
+        self.blob_meta.set_quota(quota)?;
+        self.enforce_blob_quota(&[]).await?;
+        Ok(())
+    }
+    pub(crate) async fn enforce_blob_quota(&self, keep: &[Hash]) -> Result<u64> {
+        let Some(quota) = self.blob_meta.quota() else {
+            return Ok(0);
+        };
+        let roots = self.blob_roots(keep).await?;
+        let mut freed_size = 0;
+        for entry in eviction_candidates(self.list_blobs().await?, &roots.protected, quota) {
+            self.delete_blob(entry.hash).await?;
+            freed_size += entry.size;
+        }
+        Ok(freed_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn entry(data: &[u8], size: u64, age: u64, pinned: bool) -> BlobEntry {
+        let last_access = SystemTime::UNIX_EPOCH + Duration::from_secs(age);
+        BlobEntry {
+            hash: Hash::new(data),
+            size,
+            tags: vec![],
+            pinned,
+            added_at: last_access,
+            last_access,
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used_until_under_quota() {
+        let entries = vec![
+            entry(b"a", 10, 3, false),
+            entry(b"b", 10, 1, false),
+            entry(b"c", 10, 2, false),
+        ];
+        let candidates = eviction_candidates(entries, &HashSet::new(), 15);
+        let hashes = candidates
+            .iter()
+            .map(|entry| entry.hash)
+            .collect::<Vec<_>>();
+        assert_eq!(hashes, vec![Hash::new(b"b"), Hash::new(b"c")]);
+    }
+
+    #[test]
+    fn never_evicts_pinned_or_protected_blobs() {
+        let entries = vec![
+            entry(b"pinned", 10, 1, true),
+            entry(b"shared", 10, 2, false),
+            entry(b"old", 10, 3, false),
+        ];
+        let protected = HashSet::from([Hash::new(b"shared")]);
+        let candidates = eviction_candidates(entries, &protected, 0);
+        let hashes = candidates
+            .iter()
+            .map(|entry| entry.hash)
+            .collect::<Vec<_>>();
+        assert_eq!(hashes, vec![Hash::new(b"old")]);
+    }
+
+    #[test]
+    fn nothing_evicted_within_quota() {
+        let entries = vec![entry(b"a", 10, 1, false), entry(b"b", 10, 2, false)];
+        assert!(eviction_candidates(entries, &HashSet::new(), 20).is_empty());
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("starlink-blob-meta-{}", rand::random::<u64>()))
+    }
+
+    #[test]
+    fn corrupt_metadata_starts_empty() {
+        let dir = temp_dir();
+        let path = dir.join("blob_meta.json");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, b"{\"quota\": 1, \"blo").unwrap();
+        let store = BlobMetaStore::load(&path).unwrap();
+        assert_eq!(store.quota(), None);
+        assert!(store.get(Hash::new(b"a")).is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn save_replaces_metadata_without_leftovers() {
+        let dir = temp_dir();
+        let path = dir.join("blob_meta.json");
+        let store = BlobMetaStore::load(&path).unwrap();
+        store.touch(Hash::new(b"a")).unwrap();
+        store.set_quota(Some(42)).unwrap();
+        let reloaded = BlobMetaStore::load(&path).unwrap();
+        assert_eq!(reloaded.quota(), Some(42));
+        assert!(reloaded.get(Hash::new(b"a")).is_some());
+        let names = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["blob_meta.json"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn incomplete_hash_seq_is_skipped() {
+        let endpoint = iroh::Endpoint::builder()
+            .relay_mode(iroh::RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap();
+        let blobs = iroh_blobs::net_protocol::Blobs::memory().build(&endpoint);
+        let client = blobs.client();
+        let child = client.add_bytes(&b"child"[..]).await.unwrap().hash;
+        let hash_seq = [child].into_iter().collect::<HashSeq>();
+        let complete = client.add_bytes(hash_seq.into_inner()).await.unwrap().hash;
+        client
+            .tags()
+            .set("complete", HashAndFormat::hash_seq(complete))
+            .await
+            .unwrap();
+        let missing = Hash::new(b"missing");
+        client
+            .tags()
+            .set("missing", HashAndFormat::hash_seq(missing))
+            .await
+            .unwrap();
+        let roots = tagged_roots(client, HashSet::new()).await.unwrap();
+        assert!(roots.reachable.contains(&child));
+        assert!(roots.protected.contains(&child));
+        assert!(roots.reachable.contains(&missing));
+        endpoint.close().await;
+    }
+}
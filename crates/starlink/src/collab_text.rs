@@ -0,0 +1,674 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Result, bail};
+use iroh::{NodeAddr, NodeId, SecretKey};
+use iroh_base::Signature;
+use iroh_gossip::{
+    net::{Event, GossipEvent, GossipReceiver, GossipSender},
+    proto::TopicId,
+};
+use n0_future::{
+    StreamExt,
+    task::AbortOnDropHandle,
+    time::{Instant, sleep_until},
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::Starlink;
+
+const OPS_PER_MESSAGE: usize = 32;
+const MAX_PENDING_OPS: usize = 4096;
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub enum TextChange {
+    Edit { author: NodeId, local: bool },
+    Selection { author: NodeId },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextSelection {
+    pub author: NodeId,
+    pub anchor: usize,
+    pub head: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct CharId {
+    clock: u64,
+    author: NodeId,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct TextElement {
+    id: CharId,
+    origin: Option<CharId>,
+    ch: char,
+    deleted: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+enum TextOp {
+    Insert {
+        id: CharId,
+        origin: Option<CharId>,
+        ch: char,
+    },
+    Delete {
+        id: CharId,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct Cursor {
+    anchor: Option<CharId>,
+    head: Option<CharId>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum TextMessage {
+    Ops(Vec<TextOp>),
+    Sync(Vec<TextOp>),
+    Awareness { cursor: Option<Cursor> },
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedTextMessage {
+    author: NodeId,
+    message: Vec<u8>,
+    signature: Signature,
+}
+impl SignedTextMessage {
+    fn sign(secret_key: &SecretKey, message: &TextMessage) -> Result<Vec<u8>> {
+        let message = postcard::to_stdvec(message)?;
+        Ok(postcard::to_stdvec(&SignedTextMessage {
+            author: secret_key.public(),
+            signature: secret_key.sign(&message),
+            message,
+        })?)
+    }
+    fn verify(bytes: &[u8]) -> Result<(NodeId, TextMessage)> {
+        let signed = postcard::from_bytes::<SignedTextMessage>(bytes)?;
+        signed.author.verify(&signed.message, &signed.signature)?;
+        let message = postcard::from_bytes::<TextMessage>(&signed.message)?;
+        if let TextMessage::Ops(ops) = &message
+            && ops.iter().any(|op| match op {
+                TextOp::Insert { id, .. } => id.author != signed.author,
+                TextOp::Delete { .. } => false,
+            })
+        {
+            bail!("文本操作的作者与签名不一致");
+        }
+        Ok((signed.author, message))
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct TextState {
+    clock: u64,
+    elements: Vec<TextElement>,
+}
+impl TextState {
+    fn position(&self, id: CharId) -> Option<usize> {
+        self.elements.iter().position(|element| element.id == id)
+    }
+    fn visible_position(&self, index: usize) -> Option<usize> {
+        self.elements
+            .iter()
+            .enumerate()
+            .filter(|(_, element)| !element.deleted)
+            .nth(index)
+            .map(|(position, _)| position)
+    }
+    fn char_id_before(&self, index: usize) -> Option<CharId> {
+        index
+            .checked_sub(1)
+            .and_then(|index| self.visible_position(index))
+            .map(|position| self.elements[position].id)
+    }
+    fn index_after(&self, id: Option<CharId>) -> usize {
+        let Some(position) = id.and_then(|id| self.position(id)) else {
+            return 0;
+        };
+        self.elements[..=position]
+            .iter()
+            .filter(|element| !element.deleted)
+            .count()
+    }
+    fn apply(&mut self, op: &TextOp) -> Option<bool> {
+        match op {
+            TextOp::Insert { id, origin, ch } => {
+                if self.position(*id).is_some() {
+                    return Some(false);
+                }
+                let mut position = match origin {
+                    Some(origin) => self.position(*origin)? + 1,
+                    None => 0,
+                };
+                while self
+                    .elements
+                    .get(position)
+                    .is_some_and(|element| element.id > *id)
+                {
+                    position += 1;
+                }
+                self.clock = self.clock.max(id.clock);
+                self.elements.insert(
+                    position,
+                    TextElement {
+                        id: *id,
+                        origin: *origin,
+                        ch: *ch,
+                        deleted: false,
+                    },
+                );
+                Some(true)
+            }
+            TextOp::Delete { id } => {
+                let position = self.position(*id)?;
+                let element = &mut self.elements[position];
+                Some(!std::mem::replace(&mut element.deleted, true))
+            }
+        }
+    }
+    fn merge(&mut self, pending: &mut Vec<TextOp>, ops: Vec<TextOp>) -> bool {
+        let mut changed = false;
+        pending.extend(ops);
+        loop {
+            let before = pending.len();
+            pending.retain(|op| match self.apply(op) {
+                Some(applied) => {
+                    changed |= applied;
+                    false
+                }
+                None => true,
+            });
+            if pending.len() == before {
+                break;
+            }
+        }
+        if pending.len() > MAX_PENDING_OPS {
+            let dropped = pending.len() - MAX_PENDING_OPS;
+            pending.drain(..dropped);
+            log::warn!("待合并的文本操作过多，丢弃 {} 个", dropped);
+        }
+        changed
+    }
+    fn ops(&self) -> Vec<TextOp> {
+        let mut elements = self.elements.iter().collect::<Vec<_>>();
+        elements.sort_by_key(|element| element.id);
+        let mut ops = elements
+            .into_iter()
+            .map(|element| TextOp::Insert {
+                id: element.id,
+                origin: element.origin,
+                ch: element.ch,
+            })
+            .collect::<Vec<_>>();
+        ops.extend(
+            self.elements
+                .iter()
+                .filter(|element| element.deleted)
+                .map(|element| TextOp::Delete { id: element.id }),
+        );
+        ops
+    }
+}
+
+struct TextInner {
+    topic: TopicId,
+    secret_key: SecretKey,
+    path: Option<PathBuf>,
+    state: Mutex<TextState>,
+    dirty: AtomicBool,
+    pending: Mutex<Vec<TextOp>>,
+    cursor: Mutex<Option<Cursor>>,
+    peer_cursors: Mutex<HashMap<NodeId, Cursor>>,
+    sender: GossipSender,
+    changes: broadcast::Sender<TextChange>,
+}
+impl TextInner {
+    fn save(&self, state: &TextState) -> Result<()> {
+        if let Some(path) = &self.path {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, postcard::to_stdvec(state)?)?;
+        }
+        Ok(())
+    }
+    fn author(&self) -> NodeId {
+        self.secret_key.public()
+    }
+    fn save_if_dirty(&self) {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        if let Err(err) = self.save(&self.state.lock()) {
+            self.dirty.store(true, Ordering::Relaxed);
+            log::warn!("保存文本失败: {}", err);
+        }
+    }
+    fn merge(&self, author: NodeId, ops: Vec<TextOp>) {
+        let changed = self.state.lock().merge(&mut self.pending.lock(), ops);
+        if changed {
+            self.dirty.store(true, Ordering::Relaxed);
+            _ = self.changes.send(TextChange::Edit {
+                author,
+                local: false,
+            });
+        }
+    }
+    fn receive(&self, content: &[u8]) -> Result<()> {
+        let (author, message) = SignedTextMessage::verify(content)?;
+        match message {
+            TextMessage::Ops(ops) | TextMessage::Sync(ops) => self.merge(author, ops),
+            TextMessage::Awareness { cursor } => {
+                match cursor {
+                    Some(cursor) => self.peer_cursors.lock().insert(author, cursor),
+                    None => self.peer_cursors.lock().remove(&author),
+                };
+                _ = self.changes.send(TextChange::Selection { author });
+            }
+        }
+        Ok(())
+    }
+    async fn run(self: Arc<Self>, mut receiver: GossipReceiver) {
+        let mut next_save = Instant::now() + SAVE_INTERVAL;
+        loop {
+            let event = n0_future::future::or(
+                async {
+                    sleep_until(next_save).await;
+                    None
+                },
+                async { Some(receiver.next().await) },
+            )
+            .await;
+            let result = match event {
+                None => {
+                    next_save = Instant::now() + SAVE_INTERVAL;
+                    self.save_if_dirty();
+                    Ok(())
+                }
+                Some(Some(Ok(Event::Gossip(GossipEvent::Received(message))))) => {
+                    self.receive(&message.content)
+                }
+                Some(Some(Ok(Event::Gossip(GossipEvent::NeighborUp(_))))) => {
+                    self.announce_all().await
+                }
+                Some(Some(Ok(Event::Gossip(GossipEvent::NeighborDown(node_id))))) => {
+                    if self.peer_cursors.lock().remove(&node_id).is_some() {
+                        _ = self.changes.send(TextChange::Selection { author: node_id });
+                    }
+                    Ok(())
+                }
+                Some(Some(Ok(_))) => Ok(()),
+                Some(Some(Err(err))) => Err(err.into()),
+                Some(None) => return,
+            };
+            if let Err(err) = result {
+                log::warn!("同步文本失败: {}", err);
+            }
+        }
+    }
+    async fn broadcast_ops(&self, ops: Vec<TextOp>, sync: bool) -> Result<()> {
+        for chunk in ops.chunks(OPS_PER_MESSAGE) {
+            if sync {
+                let message = TextMessage::Sync(chunk.to_vec());
+                self.sender
+                    .broadcast_neighbors(
+                        SignedTextMessage::sign(&self.secret_key, &message)?.into(),
+                    )
+                    .await?;
+            } else {
+                let message = TextMessage::Ops(chunk.to_vec());
+                self.sender
+                    .broadcast(SignedTextMessage::sign(&self.secret_key, &message)?.into())
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+    async fn announce_all(&self) -> Result<()> {
+        let ops = self.state.lock().ops();
+        self.broadcast_ops(ops, true).await?;
+        let cursor = *self.cursor.lock();
+        if cursor.is_some() {
+            self.sender
+                .broadcast_neighbors(
+                    SignedTextMessage::sign(&self.secret_key, &TextMessage::Awareness { cursor })?
+                        .into(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+impl Drop for TextInner {
+    fn drop(&mut self) {
+        self.save_if_dirty();
+    }
+}
+
+#[derive(Clone)]
+pub struct CollabText {
+    inner: Arc<TextInner>,
+    _task: Arc<AbortOnDropHandle<()>>,
+}
+impl CollabText {
+    pub fn topic(&self) -> TopicId {
+        self.inner.topic
+    }
+    pub fn text(&self) -> String {
+        self.inner
+            .state
+            .lock()
+            .elements
+            .iter()
+            .filter(|element| !element.deleted)
+            .map(|element| element.ch)
+            .collect()
+    }
+    pub fn len(&self) -> usize {
+        self.inner
+            .state
+            .lock()
+            .elements
+            .iter()
+            .filter(|element| !element.deleted)
+            .count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub async fn insert(&self, index: usize, text: impl AsRef<str>) -> Result<()> {
+        let ops = {
+            let mut state = self.inner.state.lock();
+            let len = state
+                .elements
+                .iter()
+                .filter(|element| !element.deleted)
+                .count();
+            let mut origin = state.char_id_before(index.min(len));
+            let mut ops = vec![];
+            for ch in text.as_ref().chars() {
+                state.clock = state.clock.saturating_add(1);
+                let id = CharId {
+                    clock: state.clock,
+                    author: self.inner.author(),
+                };
+                let op = TextOp::Insert { id, origin, ch };
+                state.apply(&op);
+                ops.push(op);
+                origin = Some(id);
+            }
+            ops
+        };
+        self.edited(ops).await
+    }
+    pub async fn delete(&self, index: usize, len: usize) -> Result<()> {
+        let ops = {
+            let mut state = self.inner.state.lock();
+            let ops = state
+                .elements
+                .iter()
+                .filter(|element| !element.deleted)
+                .skip(index)
+                .take(len)
+                .map(|element| TextOp::Delete { id: element.id })
+                .collect::<Vec<_>>();
+            for op in &ops {
+                state.apply(op);
+            }
+            ops
+        };
+        self.edited(ops).await
+    }
+    pub async fn replace(&self, text: impl AsRef<str>) -> Result<()> {
+        let old = self.text().chars().collect::<Vec<_>>();
+        let new = text.as_ref().chars().collect::<Vec<_>>();
+        let prefix = old
+            .iter()
+            .zip(&new)
+            .take_while(|(old, new)| old == new)
+            .count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(old, new)| old == new)
+            .count();
+        self.delete(prefix, old.len() - prefix - suffix).await?;
+        self.insert(
+            prefix,
+            new[prefix..new.len() - suffix].iter().collect::<String>(),
+        )
+        .await
+    }
+    pub async fn set_selection(&self, selection: Option<(usize, usize)>) -> Result<()> {
+        let cursor = selection.map(|(anchor, head)| {
+            let state = self.inner.state.lock();
+            Cursor {
+                anchor: state.char_id_before(anchor),
+                head: state.char_id_before(head),
+            }
+        });
+        *self.inner.cursor.lock() = cursor;
+        self.inner
+            .sender
+            .broadcast(
+                SignedTextMessage::sign(
+                    &self.inner.secret_key,
+                    &TextMessage::Awareness { cursor },
+                )?
+                .into(),
+            )
+            .await?;
+        Ok(())
+    }
+    pub fn selections(&self) -> Vec<TextSelection> {
+        let state = self.inner.state.lock();
+        self.inner
+            .peer_cursors
+            .lock()
+            .iter()
+            .map(|(author, cursor)| TextSelection {
+                author: *author,
+                anchor: state.index_after(cursor.anchor),
+                head: state.index_after(cursor.head),
+            })
+            .collect()
+    }
+    pub fn subscribe(&self) -> broadcast::Receiver<TextChange> {
+        self.inner.changes.subscribe()
+    }
+    async fn edited(&self, ops: Vec<TextOp>) -> Result<()> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+        self.inner.dirty.store(true, Ordering::Relaxed);
+        _ = self.inner.changes.send(TextChange::Edit {
+            author: self.inner.author(),
+            local: true,
+        });
+        self.inner.broadcast_ops(ops, false).await
+    }
+}
+
+impl Starlink {
+    pub async fn open_text(
+        &self,
+        topic: TopicId,
+        peer_node_addrs: Vec<NodeAddr>,
+    ) -> Result<CollabText> {
+        let peer_node_ids = self.add_peer_node_addrs(peer_node_addrs)?;
+        #[cfg(not(target_family = "wasm"))]
+        let path = Some(
+            PathBuf::from(crate::CACHE_DIR)
+                .join("texts")
+                .join(format!("{}.bin", topic)),
+        );
+        #[cfg(target_family = "wasm")]
+        let path: Option<PathBuf> = None;
+        let state = match &path {
+            Some(path) if path.exists() => postcard::from_bytes(&std::fs::read(path)?)?,
+            _ => TextState::default(),
+        };
+        let (sender, receiver) = self.gossip.subscribe(topic, peer_node_ids)?.split();
+        let inner = Arc::new(TextInner {
+            topic,
+            secret_key: self.router.endpoint().secret_key().clone(),
+            path,
+            state: Mutex::new(state),
+            dirty: AtomicBool::new(false),
+            pending: Mutex::new(vec![]),
+            cursor: Mutex::new(None),
+            peer_cursors: Mutex::new(HashMap::new()),
+            sender,
+            changes: broadcast::channel(64).0,
+        });
+        let task = n0_future::task::spawn(inner.clone().run(receiver));
+        Ok(CollabText {
+            inner,
+            _task: Arc::new(AbortOnDropHandle::new(task)),
+        })
+    }
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use super::*;
+
+    fn secret_key(seed: u8) -> SecretKey {
+        SecretKey::from_bytes(&[seed; 32])
+    }
+
+    fn insert(state: &mut TextState, author: NodeId, index: usize, text: &str) -> Vec<TextOp> {
+        let mut origin = state.char_id_before(index);
+        let mut ops = vec![];
+        for ch in text.chars() {
+            state.clock += 1;
+            let id = CharId {
+                clock: state.clock,
+                author,
+            };
+            let op = TextOp::Insert { id, origin, ch };
+            state.apply(&op);
+            ops.push(op);
+            origin = Some(id);
+        }
+        ops
+    }
+
+    fn text(state: &TextState) -> String {
+        state
+            .elements
+            .iter()
+            .filter(|element| !element.deleted)
+            .map(|element| element.ch)
+            .collect()
+    }
+
+    #[test]
+    fn concurrent_inserts_converge_in_any_order() {
+        let (alice, bob) = (secret_key(1).public(), secret_key(2).public());
+        let mut base = TextState::default();
+        let base_ops = insert(&mut base, alice, 0, "ac");
+        let mut left = TextState::default();
+        let mut right = TextState::default();
+        left.merge(&mut vec![], base_ops.clone());
+        right.merge(&mut vec![], base_ops);
+        let left_ops = insert(&mut left, alice, 1, "X");
+        let right_ops = insert(&mut right, bob, 1, "Y");
+        left.merge(&mut vec![], right_ops.clone());
+        right.merge(&mut vec![], left_ops.clone());
+        assert_eq!(text(&left), text(&right));
+        assert_eq!(text(&left).len(), 4);
+        assert!(text(&left).starts_with('a') && text(&left).ends_with('c'));
+    }
+
+    #[test]
+    fn deletes_are_idempotent_and_survive_replay() {
+        let author = secret_key(1).public();
+        let mut state = TextState::default();
+        let ops = insert(&mut state, author, 0, "abc");
+        let delete = TextOp::Delete {
+            id: match ops[1] {
+                TextOp::Insert { id, .. } => id,
+                TextOp::Delete { id } => id,
+            },
+        };
+        assert!(state.merge(&mut vec![], vec![delete.clone()]));
+        assert!(!state.merge(&mut vec![], vec![delete]));
+        assert!(!state.merge(&mut vec![], ops));
+        assert_eq!(text(&state), "ac");
+    }
+
+    #[test]
+    fn out_of_order_ops_wait_for_their_origin() {
+        let author = secret_key(1).public();
+        let mut source = TextState::default();
+        let ops = insert(&mut source, author, 0, "abc");
+        let mut state = TextState::default();
+        let mut pending = vec![];
+        let reversed = ops.into_iter().rev().collect::<Vec<_>>();
+        assert!(!state.merge(&mut pending, reversed[..2].to_vec()));
+        assert_eq!(pending.len(), 2);
+        assert!(state.merge(&mut pending, reversed[2..].to_vec()));
+        assert!(pending.is_empty());
+        assert_eq!(text(&state), text(&source));
+    }
+
+    #[test]
+    fn pending_queue_is_bounded() {
+        let author = secret_key(1).public();
+        let orphans = (0..MAX_PENDING_OPS as u64 + 10)
+            .map(|clock| TextOp::Insert {
+                id: CharId {
+                    clock: clock + 1,
+                    author,
+                },
+                origin: Some(CharId { clock: 0, author }),
+                ch: 'x',
+            })
+            .collect();
+        let mut state = TextState::default();
+        let mut pending = vec![];
+        assert!(!state.merge(&mut pending, orphans));
+        assert_eq!(pending.len(), MAX_PENDING_OPS);
+    }
+
+    #[test]
+    fn signed_messages_carry_their_author() {
+        let key = secret_key(1);
+        let mut state = TextState::default();
+        let ops = insert(&mut state, key.public(), 0, "hi");
+        let bytes = SignedTextMessage::sign(&key, &TextMessage::Ops(ops.clone())).unwrap();
+        let (author, message) = SignedTextMessage::verify(&bytes).unwrap();
+        assert_eq!(author, key.public());
+        assert!(matches!(message, TextMessage::Ops(received) if received.len() == 2));
+
+        let mut tampered = postcard::from_bytes::<SignedTextMessage>(&bytes).unwrap();
+        tampered.author = secret_key(2).public();
+        assert!(SignedTextMessage::verify(&postcard::to_stdvec(&tampered).unwrap()).is_err());
+
+        let forged =
+            SignedTextMessage::sign(&secret_key(2), &TextMessage::Ops(ops.clone())).unwrap();
+        assert!(SignedTextMessage::verify(&forged).is_err());
+        let relayed = SignedTextMessage::sign(&secret_key(2), &TextMessage::Sync(ops)).unwrap();
+        assert_eq!(
+            SignedTextMessage::verify(&relayed).unwrap().0,
+            secret_key(2).public()
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use iroh::{
+    NodeAddr, NodeId,
+    endpoint::{Connection, VarInt},
+    protocol::ProtocolHandler,
+};
+use n0_future::boxed::BoxFuture;
+use parking_lot::Mutex;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::Starlink;
+
+pub(crate) const CUSTOM_PROTOCOLS_ALPN: &[u8] = b"starlink/custom/0";
+const MAX_NAME_SIZE: usize = 256;
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+const REQUEST_QUEUE_SIZE: usize = 64;
+
+#[derive(Debug)]
+pub struct ProtocolRequest {
+    peer: NodeId,
+    data: Bytes,
+    responder: oneshot::Sender<Bytes>,
+}
+impl ProtocolRequest {
+    pub fn peer(&self) -> NodeId {
+        self.peer
+    }
+    pub fn data(&self) -> &Bytes {
+        &self.data
+    }
+    pub fn respond(self, data: impl Into<Bytes>) {
+        _ = self.responder.send(data.into());
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CustomProtocols {
+    handlers: Arc<Mutex<HashMap<String, mpsc::Sender<ProtocolRequest>>>>,
+}
+impl CustomProtocols {
+    async fn handle_stream(
+        &self,
+        peer: NodeId,
+        mut send: iroh::endpoint::SendStream,
+        mut recv: iroh::endpoint::RecvStream,
+    ) -> Result<()> {
+        let mut name_len = [0; 2];
+        recv.read_exact(&mut name_len).await?;
+        let name_len = u16::from_be_bytes(name_len) as usize;
+        if name_len > MAX_NAME_SIZE {
+            bail!("协议名称过长");
+        }
+        let mut name = vec![0; name_len];
+        recv.read_exact(&mut name).await?;
+        let name = String::from_utf8(name)?;
+        let handler = self.handlers.lock().get(&name).cloned();
+        let Some(handler) = handler else {
+            send.reset(VarInt::from_u32(1))?;
+            return Ok(());
+        };
+        let data = recv.read_to_end(MAX_MESSAGE_SIZE).await?;
+        let (responder, response) = oneshot::channel();
+        handler
+            .send(ProtocolRequest {
+                peer,
+                data: data.into(),
+                responder,
+            })
+            .await?;
+        match response.await {
+            Ok(response) => {
+                send.write_all(&response).await?;
+                send.finish()?;
+            }
+            Err(_) => {
+                send.reset(VarInt::from_u32(2))?;
+            }
+        }
+        Ok(())
+    }
+}
+impl ProtocolHandler for CustomProtocols {
+    fn accept(&self, connection: Connection) -> BoxFuture<Result<()>> {
+        let custom_protocols = self.clone();
+        Box::pin(async move {
+            let peer = connection.remote_node_id()?;
+            while let Ok((send, recv)) = connection.accept_bi().await {
+                n0_future::task::spawn({
+                    let custom_protocols = custom_protocols.clone();
+                    async move {
+                        if let Err(err) = custom_protocols.handle_stream(peer, send, recv).await {
+                            log::warn!("处理自定义协议请求失败: {}", err);
+                        }
+                    }
+                });
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Starlink {
+    pub fn register_protocol(
+        &self,
+        name: impl Into<String>,
+    ) -> Result<mpsc::Receiver<ProtocolRequest>> {
+        let name = name.into();
+        if name.len() > MAX_NAME_SIZE {
+            bail!("协议名称过长");
+        }
+        let mut handlers = self.custom_protocols.handlers.lock();
+        if handlers.contains_key(&name) {
+            bail!("协议 {} 已注册", name);
+        }
+        let (sender, receiver) = mpsc::channel(REQUEST_QUEUE_SIZE);
+        handlers.insert(name, sender);
+        Ok(receiver)
+    }
+    pub fn unregister_protocol(&self, name: &str) -> bool {
+        self.custom_protocols.handlers.lock().remove(name).is_some()
+    }
+    pub async fn protocol_request(
+        &self,
+        node_addr: NodeAddr,
+        name: &str,
+        data: impl Into<Bytes>,
+    ) -> Result<Bytes> {
+        if name.len() > MAX_NAME_SIZE {
+            bail!("协议名称过长");
+        }
+        let connection = self
+            .router
+            .endpoint()
+            .connect(node_addr, CUSTOM_PROTOCOLS_ALPN)
+            .await?;
+        let (mut send, mut recv) = connection.open_bi().await?;
+        send.write_all(&(name.len() as u16).to_be_bytes()).await?;
+        send.write_all(name.as_bytes()).await?;
+        send.write_all(&data.into()).await?;
+        send.finish()?;
+        let response = recv.read_to_end(MAX_MESSAGE_SIZE).await;
+        connection.close(VarInt::from_u32(0), b"done");
+        match response {
+            Ok(response) => Ok(response.into()),
+            Err(err) => bail!("请求协议 {} 失败: {}", name, err),
+        }
+    }
+}
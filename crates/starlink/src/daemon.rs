@@ -0,0 +1,988 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Component, Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use iroh::{NodeAddr, NodeId};
+use iroh_blobs::Hash;
+use iroh_gossip::{
+    net::{Event, GossipEvent},
+    proto::TopicId,
+};
+use n0_future::{StreamExt, task::AbortOnDropHandle};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, Lines,
+    },
+    net::{
+        TcpListener, TcpStream, ToSocketAddrs,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    sync::{broadcast, mpsc},
+};
+
+use crate::{CACHE_DIR, ProtocolRequest, Starlink, file_ticket::parse_download_ticket};
+
+pub const DAEMON_ADDR: &str = "127.0.0.1:47800";
+const DAEMON_ENDPOINT_FILE: &str = "daemon.json";
+const DOWNLOADS_DIR: &str = "downloads";
+const TOPIC_EVENT_CAPACITY: usize = 256;
+const MAX_HELLO_LEN: u64 = 1024;
+const MAX_REQUEST_LEN: u64 = 24 * 1024 * 1024;
+
+mod base64_bytes {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&data_encoding::BASE64.encode(bytes))
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        data_encoding::BASE64
+            .decode(String::deserialize(deserializer)?.as_bytes())
+            .map_err(D::Error::custom)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Base64Bytes(#[serde(with = "base64_bytes")] Vec<u8>);
+
+pub fn default_download_dir() -> PathBuf {
+    let dir = PathBuf::from(CACHE_DIR).join(DOWNLOADS_DIR);
+    std::path::absolute(&dir).unwrap_or(dir)
+}
+
+#[derive(Debug, Clone)]
+pub struct DaemonOptions {
+    pub download_dir: PathBuf,
+    pub share_dirs: Vec<PathBuf>,
+}
+impl Default for DaemonOptions {
+    fn default() -> Self {
+        Self {
+            download_dir: default_download_dir(),
+            share_dirs: vec![PathBuf::from(".")],
+        }
+    }
+}
+impl DaemonOptions {
+    fn canonicalize(self) -> Result<Self> {
+        std::fs::create_dir_all(&self.download_dir)?;
+        let download_dir = std::fs::canonicalize(&self.download_dir)?;
+        let share_dirs = self
+            .share_dirs
+            .iter()
+            .filter_map(|dir| {
+                std::fs::canonicalize(dir)
+                    .inspect_err(|err| log::warn!("忽略分享目录 {}: {}", dir.display(), err))
+                    .ok()
+            })
+            .collect();
+        Ok(Self {
+            download_dir,
+            share_dirs,
+        })
+    }
+    fn share_path(&self, path: &Path) -> Result<PathBuf> {
+        let path = std::fs::canonicalize(path)?;
+        if !self.share_dirs.iter().any(|dir| path.starts_with(dir)) {
+            bail!("{} 不在允许分享的目录中", path.display());
+        }
+        Ok(path)
+    }
+    fn download_path(&self, name: &str) -> Result<PathBuf> {
+        if !matches!(
+            Path::new(name).components().collect::<Vec<_>>()[..],
+            [Component::Normal(_)]
+        ) {
+            bail!("文件名 {} 不合法，只能指定下载目录中的文件名", name);
+        }
+        Ok(self.download_dir.join(name))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DaemonHello {
+    token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DaemonEndpoint {
+    addr: SocketAddr,
+    token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum DaemonRequest {
+    NodeAddr,
+    Peers,
+    Share {
+        path: PathBuf,
+    },
+    ShareBytes {
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    Download {
+        ticket: String,
+        name: Option<String>,
+    },
+    ReadBytes {
+        ticket: String,
+    },
+    ListBlobs,
+    DeleteBlob {
+        hash: Hash,
+    },
+    GcBlobs,
+    JoinTopic {
+        topic: TopicId,
+        nodes: Vec<NodeAddr>,
+    },
+    LeaveTopic {
+        topic: TopicId,
+    },
+    Topics,
+    Broadcast {
+        topic: TopicId,
+        message: String,
+    },
+    BroadcastBytes {
+        topic: TopicId,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    Listen {
+        topic: TopicId,
+        #[serde(default)]
+        binary: bool,
+    },
+    ProtocolRequest {
+        node_addr: NodeAddr,
+        name: String,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    RegisterProtocol {
+        name: String,
+    },
+    Respond {
+        id: u64,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DaemonResponse {
+    Ok(serde_json::Value),
+    Error(String),
+    Event { topic: TopicId, event: TopicEvent },
+    Request(RemoteRequest),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TopicEvent {
+    Message {
+        from: NodeId,
+        content: String,
+    },
+    Data {
+        from: NodeId,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    NeighborUp(NodeId),
+    NeighborDown(NodeId),
+}
+impl TopicEvent {
+    fn into_text(self) -> Self {
+        match self {
+            Self::Data { from, data } => Self::Message {
+                from,
+                content: String::from_utf8_lossy(&data).into_owned(),
+            },
+            event => event,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteRequest {
+    pub id: u64,
+    pub peer: NodeId,
+    #[serde(with = "base64_bytes")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub node_id: NodeId,
+    pub conn_type: String,
+    pub latency: Option<Duration>,
+}
+
+struct DaemonTopic {
+    sender: iroh_gossip::net::GossipSender,
+    events: broadcast::Sender<TopicEvent>,
+    _task: AbortOnDropHandle<()>,
+}
+
+#[derive(Clone)]
+struct Daemon {
+    starlink: Starlink,
+    options: Arc<DaemonOptions>,
+    token: Arc<str>,
+    topics: Arc<Mutex<HashMap<TopicId, DaemonTopic>>>,
+    requests: Arc<Mutex<HashMap<u64, ProtocolRequest>>>,
+    next_request_id: Arc<AtomicU64>,
+}
+impl Daemon {
+    async fn handle_connection(self, stream: TcpStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        authenticate(&mut reader, &mut writer, &self.token).await?;
+        while let Some(line) = read_request_line(&mut reader, MAX_REQUEST_LEN).await? {
+            let request = match serde_json::from_str::<DaemonRequest>(&line) {
+                Ok(request) => request,
+                Err(err) => {
+                    write_response(&mut writer, &DaemonResponse::Error(err.to_string())).await?;
+                    bail!("控制请求格式错误: {}", err);
+                }
+            };
+            match request {
+                DaemonRequest::Listen { topic, binary } => {
+                    let events = self
+                        .topics
+                        .lock()
+                        .get(&topic)
+                        .map(|daemon_topic| daemon_topic.events.subscribe());
+                    let Some(events) = events else {
+                        write_response(&mut writer, &DaemonResponse::Error("未加入该话题".into()))
+                            .await?;
+                        continue;
+                    };
+                    write_response(&mut writer, &DaemonResponse::Ok(serde_json::Value::Null))
+                        .await?;
+                    return listen(&mut writer, topic, binary, events).await;
+                }
+                DaemonRequest::RegisterProtocol { name } => {
+                    let receiver = match self.starlink.register_protocol(name.clone()) {
+                        Ok(receiver) => receiver,
+                        Err(err) => {
+                            write_response(&mut writer, &DaemonResponse::Error(err.to_string()))
+                                .await?;
+                            continue;
+                        }
+                    };
+                    write_response(&mut writer, &DaemonResponse::Ok(serde_json::Value::Null))
+                        .await?;
+                    let result = self
+                        .serve_protocol(&mut reader, &mut writer, receiver)
+                        .await;
+                    self.starlink.unregister_protocol(&name);
+                    return result;
+                }
+                _ => {}
+            }
+            let response = match self.handle_request(request).await {
+                Ok(value) => DaemonResponse::Ok(value),
+                Err(err) => DaemonResponse::Error(err.to_string()),
+            };
+            write_response(&mut writer, &response).await?;
+        }
+        Ok(())
+    }
+    async fn handle_request(&self, request: DaemonRequest) -> Result<serde_json::Value> {
+        let value = match request {
+            DaemonRequest::NodeAddr => serde_json::to_value(self.starlink.node_addr().await?)?,
+            DaemonRequest::Peers => serde_json::to_value(
+                self.starlink
+                    .peers()
+                    .into_iter()
+                    .map(|remote_info| PeerInfo {
+                        node_id: remote_info.node_id,
+                        conn_type: remote_info.conn_type.to_string(),
+                        latency: remote_info.latency,
+                    })
+                    .collect::<Vec<_>>(),
+            )?,
+            DaemonRequest::Share { path } => {
+                let path = self.options.share_path(&path)?;
+                serde_json::to_value(self.starlink.share_file(path, None).await?.to_string())?
+            }
+            DaemonRequest::ShareBytes { data } => {
+                serde_json::to_value(self.starlink.share_bytes(data).await?.to_string())?
+            }
+            DaemonRequest::Download { ticket, name } => {
+                let (blob, file_name) = parse_download_ticket(&ticket)?;
+                let dest = self.options.download_path(&name.unwrap_or(file_name))?;
+                self.starlink
+                    .download_file(blob.clone())
+                    .await?
+                    .finish()
+                    .await?;
+                self.starlink
+                    .save_file(blob, dest.to_string_lossy().into_owned())
+                    .await?;
+                serde_json::to_value(dest)?
+            }
+            DaemonRequest::ReadBytes { ticket } => serde_json::to_value(Base64Bytes(
+                self.starlink.read_to_bytes(ticket.parse()?).await?.to_vec(),
+            ))?,
+            DaemonRequest::ListBlobs => serde_json::to_value(self.starlink.list_blobs().await?)?,
+            DaemonRequest::DeleteBlob { hash } => {
+                self.starlink.delete_blob(hash).await?;
+                serde_json::Value::Null
+            }
+            DaemonRequest::GcBlobs => serde_json::to_value(self.starlink.gc_blobs().await?)?,
+            DaemonRequest::JoinTopic { topic, nodes } => {
+                self.join_topic(topic, nodes).await?;
+                serde_json::Value::Null
+            }
+            DaemonRequest::LeaveTopic { topic } => {
+                serde_json::to_value(self.topics.lock().remove(&topic).is_some())?
+            }
+            DaemonRequest::Topics => {
+                serde_json::to_value(self.topics.lock().keys().copied().collect::<Vec<_>>())?
+            }
+            DaemonRequest::Broadcast { topic, message } => {
+                let sender = self
+                    .topics
+                    .lock()
+                    .get(&topic)
+                    .map(|daemon_topic| daemon_topic.sender.clone());
+                let Some(sender) = sender else {
+                    bail!("未加入该话题");
+                };
+                sender.broadcast(message.into()).await?;
+                serde_json::Value::Null
+            }
+            DaemonRequest::BroadcastBytes { topic, data } => {
+                let sender = self
+                    .topics
+                    .lock()
+                    .get(&topic)
+                    .map(|daemon_topic| daemon_topic.sender.clone());
+                let Some(sender) = sender else {
+                    bail!("未加入该话题");
+                };
+                sender.broadcast(data.into()).await?;
+                serde_json::Value::Null
+            }
+            DaemonRequest::ProtocolRequest {
+                node_addr,
+                name,
+                data,
+            } => serde_json::to_value(Base64Bytes(
+                self.starlink
+                    .protocol_request(node_addr, &name, data)
+                    .await?
+                    .to_vec(),
+            ))?,
+            DaemonRequest::Respond { id, data } => {
+                self.requests
+                    .lock()
+                    .remove(&id)
+                    .context("请求不存在或已响应")?
+                    .respond(data);
+                serde_json::Value::Null
+            }
+            DaemonRequest::Listen { .. } | DaemonRequest::RegisterProtocol { .. } => {
+                bail!("流式请求不能在此处理")
+            }
+        };
+        Ok(value)
+    }
+    async fn serve_protocol(
+        &self,
+        reader: &mut (impl AsyncBufRead + Unpin),
+        writer: &mut (impl AsyncWrite + Unpin),
+        mut receiver: mpsc::Receiver<ProtocolRequest>,
+    ) -> Result<()> {
+        let mut ids = vec![];
+        let result = async {
+            loop {
+                tokio::select! {
+                    request = receiver.recv() => {
+                        let Some(request) = request else {
+                            return Ok(());
+                        };
+                        let id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+                        let remote_request = RemoteRequest {
+                            id,
+                            peer: request.peer(),
+                            data: request.data().to_vec(),
+                        };
+                        self.requests.lock().insert(id, request);
+                        ids.push(id);
+                        write_response(writer, &DaemonResponse::Request(remote_request)).await?;
+                    }
+                    line = read_request_line(reader, MAX_REQUEST_LEN) => {
+                        if line?.is_some() {
+                            bail!("协议连接不接受其他请求");
+                        }
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        .await;
+        let mut requests = self.requests.lock();
+        for id in ids {
+            requests.remove(&id);
+        }
+        result
+    }
+    async fn join_topic(&self, topic: TopicId, nodes: Vec<NodeAddr>) -> Result<()> {
+        let node_ids = self.starlink.add_peer_node_addrs(nodes)?;
+        let sender = self
+            .topics
+            .lock()
+            .get(&topic)
+            .map(|daemon_topic| daemon_topic.sender.clone());
+        if let Some(sender) = sender {
+            sender.join_peers(node_ids).await?;
+            return Ok(());
+        }
+        let (sender, mut receiver) = self.starlink.gossip.subscribe(topic, node_ids)?.split();
+        let (events, _) = broadcast::channel(TOPIC_EVENT_CAPACITY);
+        let task = n0_future::task::spawn({
+            let events = events.clone();
+            async move {
+                while let Some(event) = receiver.next().await {
+                    let event = match event {
+                        Ok(Event::Gossip(GossipEvent::Received(message))) => TopicEvent::Data {
+                            from: message.delivered_from,
+                            data: message.content.to_vec(),
+                        },
+                        Ok(Event::Gossip(GossipEvent::NeighborUp(node_id))) => {
+                            TopicEvent::NeighborUp(node_id)
+                        }
+                        Ok(Event::Gossip(GossipEvent::NeighborDown(node_id))) => {
+                            TopicEvent::NeighborDown(node_id)
+                        }
+                        Ok(_) => continue,
+                        Err(err) => {
+                            log::warn!("话题接收失败: {}", err);
+                            break;
+                        }
+                    };
+                    _ = events.send(event);
+                }
+            }
+        });
+        self.topics.lock().insert(
+            topic,
+            DaemonTopic {
+                sender,
+                events,
+                _task: AbortOnDropHandle::new(task),
+            },
+        );
+        Ok(())
+    }
+}
+
+async fn listen(
+    writer: &mut (impl AsyncWrite + Unpin),
+    topic: TopicId,
+    binary: bool,
+    mut events: broadcast::Receiver<TopicEvent>,
+) -> Result<()> {
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                let event = if binary { event } else { event.into_text() };
+                write_response(writer, &DaemonResponse::Event { topic, event }).await?
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                log::warn!("监听连接落后，丢弃了 {} 条事件", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn read_request_line(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    limit: u64,
+) -> Result<Option<String>> {
+    let mut line = vec![];
+    if reader.take(limit).read_until(b'\n', &mut line).await? == 0 {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        bail!("控制请求过长或不完整");
+    }
+    Ok(Some(String::from_utf8(line)?))
+}
+
+fn tokens_match(left: &str, right: &str) -> bool {
+    left.len() == right.len()
+        && left
+            .bytes()
+            .zip(right.bytes())
+            .fold(0, |diff, (left, right)| diff | (left ^ right))
+            == 0
+}
+
+async fn authenticate(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+    token: &str,
+) -> Result<()> {
+    let hello = read_request_line(reader, MAX_HELLO_LEN)
+        .await?
+        .and_then(|line| serde_json::from_str::<DaemonHello>(&line).ok());
+    if !hello.is_some_and(|hello| tokens_match(&hello.token, token)) {
+        write_response(writer, &DaemonResponse::Error("认证失败".into())).await?;
+        bail!("控制连接认证失败");
+    }
+    write_response(writer, &DaemonResponse::Ok(serde_json::Value::Null)).await
+}
+
+async fn write_response(
+    writer: &mut (impl AsyncWrite + Unpin),
+    response: &DaemonResponse,
+) -> Result<()> {
+    let mut line = serde_json::to_vec(response)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    Ok(())
+}
+
+fn endpoint_path() -> PathBuf {
+    PathBuf::from(CACHE_DIR).join(DAEMON_ENDPOINT_FILE)
+}
+
+fn read_endpoint() -> Result<DaemonEndpoint> {
+    Ok(serde_json::from_slice(
+        &std::fs::read(endpoint_path()).context("守护进程未运行")?,
+    )?)
+}
+
+pub struct DaemonClient {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+impl DaemonClient {
+    pub async fn connect(addr: impl ToSocketAddrs, token: &str) -> Result<Self> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        let mut client = Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        };
+        let mut line = serde_json::to_vec(&DaemonHello {
+            token: token.to_string(),
+        })?;
+        line.push(b'\n');
+        client.writer.write_all(&line).await?;
+        match client.next_response().await? {
+            Some(DaemonResponse::Ok(_)) => Ok(client),
+            Some(DaemonResponse::Error(err)) => bail!(err),
+            _ => bail!("守护进程握手失败"),
+        }
+    }
+    pub async fn connect_default() -> Result<Self> {
+        let endpoint = read_endpoint()?;
+        Self::connect(endpoint.addr, &endpoint.token).await
+    }
+    async fn next_response(&mut self) -> Result<Option<DaemonResponse>> {
+        match self.lines.next_line().await? {
+            Some(line) => Ok(Some(serde_json::from_str(&line)?)),
+            None => Ok(None),
+        }
+    }
+    pub async fn call(&mut self, request: &DaemonRequest) -> Result<serde_json::Value> {
+        let mut line = serde_json::to_vec(request)?;
+        line.push(b'\n');
+        self.writer.write_all(&line).await?;
+        match self.next_response().await? {
+            Some(DaemonResponse::Ok(value)) => Ok(value),
+            Some(DaemonResponse::Error(err)) => bail!(err),
+            Some(DaemonResponse::Event { .. }) => bail!("收到意外的话题事件"),
+            Some(DaemonResponse::Request(_)) => bail!("收到意外的协议请求"),
+            None => bail!("守护进程已断开连接"),
+        }
+    }
+    pub async fn next_event(&mut self) -> Result<Option<(TopicId, TopicEvent)>> {
+        match self.next_response().await? {
+            Some(DaemonResponse::Event { topic, event }) => Ok(Some((topic, event))),
+            Some(DaemonResponse::Error(err)) => bail!(err),
+            Some(_) => bail!("收到意外的响应"),
+            None => Ok(None),
+        }
+    }
+    pub async fn next_request(&mut self) -> Result<Option<RemoteRequest>> {
+        match self.next_response().await? {
+            Some(DaemonResponse::Request(request)) => Ok(Some(request)),
+            Some(DaemonResponse::Error(err)) => bail!(err),
+            Some(_) => bail!("收到意外的响应"),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DaemonNode {
+    endpoint: DaemonEndpoint,
+    node_id: NodeId,
+}
+impl DaemonNode {
+    pub async fn connect(addr: SocketAddr, token: &str) -> Result<Self> {
+        let mut client = DaemonClient::connect(addr, token).await?;
+        let node_addr =
+            serde_json::from_value::<NodeAddr>(client.call(&DaemonRequest::NodeAddr).await?)?;
+        Ok(Self {
+            endpoint: DaemonEndpoint {
+                addr,
+                token: token.to_string(),
+            },
+            node_id: node_addr.node_id,
+        })
+    }
+    pub async fn connect_default() -> Result<Self> {
+        let endpoint = read_endpoint()?;
+        Self::connect(endpoint.addr, &endpoint.token).await
+    }
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+    pub async fn client(&self) -> Result<DaemonClient> {
+        DaemonClient::connect(self.endpoint.addr, &self.endpoint.token).await
+    }
+    async fn call(&self, request: &DaemonRequest) -> Result<serde_json::Value> {
+        self.client().await?.call(request).await
+    }
+    pub async fn share_bytes(&self, data: Vec<u8>) -> Result<String> {
+        Ok(serde_json::from_value(
+            self.call(&DaemonRequest::ShareBytes { data }).await?,
+        )?)
+    }
+    pub async fn read_to_bytes(&self, ticket: String) -> Result<Vec<u8>> {
+        let Base64Bytes(data) =
+            serde_json::from_value(self.call(&DaemonRequest::ReadBytes { ticket }).await?)?;
+        Ok(data)
+    }
+    pub async fn protocol_request(
+        &self,
+        node_addr: NodeAddr,
+        name: String,
+        data: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        let Base64Bytes(data) = serde_json::from_value(
+            self.call(&DaemonRequest::ProtocolRequest {
+                node_addr,
+                name,
+                data,
+            })
+            .await?,
+        )?;
+        Ok(data)
+    }
+    pub async fn join_topic(&self, topic: TopicId, nodes: Vec<NodeAddr>) -> Result<()> {
+        self.call(&DaemonRequest::JoinTopic { topic, nodes })
+            .await?;
+        Ok(())
+    }
+    pub async fn broadcast(&self, topic: TopicId, data: Vec<u8>) -> Result<()> {
+        self.call(&DaemonRequest::BroadcastBytes { topic, data })
+            .await?;
+        Ok(())
+    }
+    pub async fn listen(&self, topic: TopicId) -> Result<DaemonClient> {
+        let mut client = self.client().await?;
+        client
+            .call(&DaemonRequest::Listen {
+                topic,
+                binary: true,
+            })
+            .await?;
+        Ok(client)
+    }
+    pub async fn register_protocol(&self, name: String) -> Result<DaemonClient> {
+        let mut client = self.client().await?;
+        client
+            .call(&DaemonRequest::RegisterProtocol { name })
+            .await?;
+        Ok(client)
+    }
+    pub async fn respond(&self, id: u64, data: Vec<u8>) -> Result<()> {
+        self.call(&DaemonRequest::Respond { id, data }).await?;
+        Ok(())
+    }
+}
+
+impl Starlink {
+    pub async fn serve_daemon(
+        &self,
+        addr: impl ToSocketAddrs,
+        options: DaemonOptions,
+    ) -> Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        let token = rand::random::<[u8; 32]>()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        let endpoint_path = endpoint_path();
+        match std::fs::remove_file(&endpoint_path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        crate::write_private_file(
+            &endpoint_path,
+            &serde_json::to_vec(&DaemonEndpoint {
+                addr: listener.local_addr()?,
+                token: token.clone(),
+            })?,
+        )?;
+        let daemon = Daemon {
+            starlink: self.clone(),
+            options: Arc::new(options.canonicalize()?),
+            token: token.into(),
+            topics: Arc::new(Mutex::new(HashMap::new())),
+            requests: Arc::new(Mutex::new(HashMap::new())),
+            next_request_id: Arc::new(AtomicU64::new(1)),
+        };
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            if !peer.ip().is_loopback() {
+                log::warn!("拒绝非本机的控制连接: {}", peer);
+                continue;
+            }
+            n0_future::task::spawn({
+                let daemon = daemon.clone();
+                async move {
+                    if let Err(err) = daemon.handle_connection(stream).await {
+                        log::warn!("控制连接出错: {}", err);
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use super::*;
+
+    async fn handshake(request: &[u8], token: &str) -> Result<()> {
+        let (client, server) = duplex(4096);
+        let (server_reader, mut server_writer) = tokio::io::split(server);
+        let (_client_reader, mut client_writer) = tokio::io::split(client);
+        client_writer.write_all(request).await?;
+        client_writer.shutdown().await?;
+        authenticate(
+            &mut BufReader::new(server_reader),
+            &mut server_writer,
+            token,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn accepts_only_the_daemon_token() {
+        assert!(
+            handshake(b"{\"token\":\"secret\"}\n", "secret")
+                .await
+                .is_ok()
+        );
+        assert!(
+            handshake(b"{\"token\":\"secreT\"}\n", "secret")
+                .await
+                .is_err()
+        );
+        assert!(handshake(b"{\"token\":\"\"}\n", "secret").await.is_err());
+        assert!(
+            handshake(b"{\"method\":\"node_addr\"}\n", "secret")
+                .await
+                .is_err()
+        );
+        assert!(
+            handshake(b"POST / HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n", "secret")
+                .await
+                .is_err()
+        );
+        assert!(handshake(b"", "secret").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_overlong_and_unterminated_requests() {
+        let mut long = vec![b'a'; MAX_HELLO_LEN as usize + 1];
+        long.push(b'\n');
+        assert!(
+            read_request_line(&mut &long[..], MAX_HELLO_LEN)
+                .await
+                .is_err()
+        );
+        assert!(
+            read_request_line(&mut &b"{}"[..], MAX_HELLO_LEN)
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            read_request_line(&mut &b"{}\n"[..], MAX_HELLO_LEN)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("{}")
+        );
+        assert!(
+            read_request_line(&mut &b""[..], MAX_HELLO_LEN)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn confines_downloads_to_the_download_dir() {
+        let options = DaemonOptions {
+            download_dir: PathBuf::from("/srv/downloads"),
+            share_dirs: vec![],
+        };
+        assert_eq!(
+            options.download_path("b.txt").unwrap(),
+            PathBuf::from("/srv/downloads/b.txt")
+        );
+        for name in ["", "..", "a/b.txt", "../x", "/etc/passwd", "./x"] {
+            assert!(options.download_path(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn default_download_dir_is_absolute() {
+        assert!(default_download_dir().is_absolute());
+        assert!(DaemonOptions::default().download_dir.is_absolute());
+    }
+
+    #[test]
+    fn text_listeners_receive_lossy_messages() {
+        let from = iroh::SecretKey::from_bytes(&[1; 32]).public();
+        let event = TopicEvent::Data {
+            from,
+            data: b"hi\xff".to_vec(),
+        };
+        assert!(matches!(
+            event.clone().into_text(),
+            TopicEvent::Message { content, .. } if content == "hi\u{fffd}"
+        ));
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains("aGn/"), "{}", json);
+        assert!(matches!(
+            serde_json::from_str(&json).unwrap(),
+            TopicEvent::Data { data, .. } if data == b"hi\xff"
+        ));
+    }
+
+    async fn fake_daemon(token: &'static str) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut reader = BufReader::new(reader);
+                    authenticate(&mut reader, &mut writer, token).await?;
+                    while let Some(line) = read_request_line(&mut reader, MAX_REQUEST_LEN).await? {
+                        let value = match serde_json::from_str(&line)? {
+                            DaemonRequest::NodeAddr => serde_json::to_value(NodeAddr::new(
+                                iroh::SecretKey::from_bytes(&[2; 32]).public(),
+                            ))?,
+                            DaemonRequest::ShareBytes { data } => {
+                                serde_json::to_value(format!("ticket-{}", data.len()))?
+                            }
+                            DaemonRequest::ReadBytes { ticket } => {
+                                serde_json::to_value(Base64Bytes(ticket.into_bytes()))?
+                            }
+                            DaemonRequest::RegisterProtocol { name } => {
+                                write_response(
+                                    &mut writer,
+                                    &DaemonResponse::Ok(serde_json::Value::Null),
+                                )
+                                .await?;
+                                write_response(
+                                    &mut writer,
+                                    &DaemonResponse::Request(RemoteRequest {
+                                        id: 7,
+                                        peer: iroh::SecretKey::from_bytes(&[3; 32]).public(),
+                                        data: name.into_bytes(),
+                                    }),
+                                )
+                                .await?;
+                                continue;
+                            }
+                            _ => {
+                                write_response(
+                                    &mut writer,
+                                    &DaemonResponse::Error("不支持".into()),
+                                )
+                                .await?;
+                                continue;
+                            }
+                        };
+                        write_response(&mut writer, &DaemonResponse::Ok(value)).await?;
+                    }
+                    anyhow::Ok(())
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn daemon_node_proxies_binary_calls() {
+        let addr = fake_daemon("secret").await;
+        assert!(DaemonNode::connect(addr, "wrong").await.is_err());
+        let node = DaemonNode::connect(addr, "secret").await.unwrap();
+        assert_eq!(
+            node.node_id(),
+            iroh::SecretKey::from_bytes(&[2; 32]).public()
+        );
+        assert_eq!(
+            node.share_bytes(vec![0, 255, 10]).await.unwrap(),
+            "ticket-3"
+        );
+        assert_eq!(node.read_to_bytes("\u{0}\n".into()).await.unwrap(), b"\0\n");
+        let mut client = node.register_protocol("echo".into()).await.unwrap();
+        let request = client.next_request().await.unwrap().unwrap();
+        assert_eq!(request.id, 7);
+        assert_eq!(request.data, b"echo");
+        assert!(node.respond(7, vec![]).await.is_err());
+    }
+
+    #[test]
+    fn confines_shares_to_the_share_dirs() {
+        let dir =
+            std::env::temp_dir().join(format!("starlink-daemon-test-{}", rand::random::<u64>()));
+        let shared = dir.join("shared");
+        std::fs::create_dir_all(&shared).unwrap();
+        std::fs::write(shared.join("a.txt"), b"a").unwrap();
+        std::fs::write(dir.join("secret.txt"), b"s").unwrap();
+        let options = DaemonOptions {
+            download_dir: dir.join("downloads"),
+            share_dirs: vec![shared.clone()],
+        }
+        .canonicalize()
+        .unwrap();
+        assert!(options.share_path(&shared.join("a.txt")).is_ok());
+        assert!(options.share_path(&shared.join("../secret.txt")).is_err());
+        assert!(options.share_path(&dir.join("secret.txt")).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
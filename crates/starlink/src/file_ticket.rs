@@ -0,0 +1,243 @@
+use std::{
+    fmt,
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use iroh_base::ticket::{self, Ticket};
+#[cfg(not(target_family = "wasm"))]
+use iroh_blobs::ticket::BlobTicket;
+use serde::{Deserialize, Serialize};
+
+#[cfg(target_family = "wasm")]
+use crate::BlobTicket;
+use crate::Starlink;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileTicket {
+    blob: BlobTicket,
+    name: String,
+    size: u64,
+    mime: Option<String>,
+    modified: Option<u64>,
+    description: Option<String>,
+}
+impl FileTicket {
+    pub fn new(blob: BlobTicket, name: impl Into<String>, size: u64) -> Self {
+        Self {
+            blob,
+            name: name.into(),
+            size,
+            mime: None,
+            modified: None,
+            description: None,
+        }
+    }
+    pub fn with_mime(mut self, mime: impl Into<String>) -> Self {
+        self.mime = Some(mime.into());
+        self
+    }
+    pub fn with_modified(mut self, modified: SystemTime) -> Self {
+        self.modified = Some(
+            modified
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+        );
+        self
+    }
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+    pub fn blob(&self) -> &BlobTicket {
+        &self.blob
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    pub fn mime(&self) -> Option<&str> {
+        self.mime.as_deref()
+    }
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+            .and_then(|secs| SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(secs)))
+    }
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum TicketWireFormat {
+    Variant0(Variant0FileTicket),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Variant0FileTicket {
+    blob: Vec<u8>,
+    name: String,
+    size: u64,
+    mime: Option<String>,
+    modified: Option<u64>,
+    description: Option<String>,
+}
+
+impl Ticket for FileTicket {
+    const KIND: &'static str = "file";
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let data = TicketWireFormat::Variant0(Variant0FileTicket {
+            blob: self.blob.to_bytes(),
+            name: self.name.clone(),
+            size: self.size,
+            mime: self.mime.clone(),
+            modified: self.modified,
+            description: self.description.clone(),
+        });
+        postcard::to_stdvec(&data).expect("postcard serialization failed")
+    }
+    fn from_bytes(bytes: &[u8]) -> std::result::Result<Self, ticket::Error> {
+        let TicketWireFormat::Variant0(Variant0FileTicket {
+            blob,
+            name,
+            size,
+            mime,
+            modified,
+            description,
+        }) = postcard::from_bytes(bytes).map_err(ticket::Error::Postcard)?;
+        Ok(Self {
+            blob: BlobTicket::from_bytes(&blob)?,
+            name,
+            size,
+            mime,
+            modified,
+            description,
+        })
+    }
+}
+impl fmt::Display for FileTicket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Ticket::serialize(self))
+    }
+}
+impl FromStr for FileTicket {
+    type Err = ticket::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ticket::deserialize(s)
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn parse_download_ticket(ticket: &str) -> Result<(BlobTicket, String)> {
+    match ticket.parse::<FileTicket>() {
+        Ok(ticket) => {
+            let file_name = Path::new(&ticket.name)
+                .file_name()
+                .context("文件名无效")?
+                .to_string_lossy()
+                .into_owned();
+            Ok((ticket.blob, file_name))
+        }
+        Err(_) => {
+            let ticket = ticket.parse::<BlobTicket>()?;
+            let file_name = ticket.hash().to_string();
+            Ok((ticket, file_name))
+        }
+    }
+}
+
+impl Starlink {
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn share_file(
+        &self,
+        path: std::path::PathBuf,
+        description: Option<String>,
+    ) -> Result<FileTicket> {
+        let metadata = std::fs::metadata(&path)?;
+        let name = path
+            .file_name()
+            .context("路径没有文件名")?
+            .to_string_lossy()
+            .into_owned();
+        let mime = mime_guess::from_path(&path).first();
+        let mut file_ticket = FileTicket::new(self.shared_file(path).await?, name, metadata.len());
+        if let Some(mime) = mime {
+            file_ticket = file_ticket.with_mime(mime.to_string());
+        }
+        if let Ok(modified) = metadata.modified() {
+            file_ticket = file_ticket.with_modified(modified);
+        }
+        if let Some(description) = description {
+            file_ticket = file_ticket.with_description(description);
+        }
+        Ok(file_ticket)
+    }
+    pub async fn save_file_ticket(&self, ticket: FileTicket) -> Result<()> {
+        let file_name = Path::new(&ticket.name)
+            .file_name()
+            .context("文件名无效")?
+            .to_string_lossy()
+            .into_owned();
+        self.save_file(ticket.blob, file_name).await
+    }
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn download_ticket(
+        &self,
+        ticket: &str,
+        dest: Option<std::path::PathBuf>,
+    ) -> Result<std::path::PathBuf> {
+        let (blob, file_name) = parse_download_ticket(ticket)?;
+        self.download_file(blob.clone()).await?.finish().await?;
+        let dest = dest.unwrap_or_else(|| file_name.into());
+        self.save_file(blob, dest.to_string_lossy().into_owned())
+            .await?;
+        Ok(dest)
+    }
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use iroh::{NodeAddr, SecretKey};
+    use iroh_blobs::{BlobFormat, Hash};
+
+    use super::*;
+
+    fn blob_ticket() -> BlobTicket {
+        let node_id = SecretKey::from_bytes(&[7; 32]).public();
+        BlobTicket::new(NodeAddr::new(node_id), Hash::new(b"data"), BlobFormat::Raw).unwrap()
+    }
+
+    #[test]
+    fn round_trips_through_string() {
+        let ticket = FileTicket::new(blob_ticket(), "报告.pdf", 1234)
+            .with_mime("application/pdf")
+            .with_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+            .with_description("季度报告");
+        let parsed = ticket.to_string().parse::<FileTicket>().unwrap();
+        assert_eq!(parsed, ticket);
+        assert_eq!(
+            parsed.modified(),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+        assert_eq!(parsed.mime(), Some("application/pdf"));
+    }
+
+    #[test]
+    fn clamps_modified_before_epoch() {
+        let ticket = FileTicket::new(blob_ticket(), "old.txt", 1)
+            .with_modified(SystemTime::UNIX_EPOCH - Duration::from_secs(60));
+        let parsed = ticket.to_string().parse::<FileTicket>().unwrap();
+        assert_eq!(parsed.modified(), Some(SystemTime::UNIX_EPOCH));
+    }
+
+    #[test]
+    fn rejects_other_ticket_kinds() {
+        assert!(blob_ticket().to_string().parse::<FileTicket>().is_err());
+    }
+}
@@ -0,0 +1,825 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use iroh::NodeAddr;
+use iroh_blobs::{
+    BlobFormat, Hash,
+    rpc::client::blobs::WrapOption,
+    store::{ExportFormat, ExportMode},
+    ticket::BlobTicket,
+    util::SetTagOption,
+};
+use iroh_gossip::{
+    net::{Event, GossipEvent, GossipReceiver, GossipSender},
+    proto::TopicId,
+};
+use n0_future::{
+    StreamExt,
+    task::AbortOnDropHandle,
+    time::{Instant, sleep_until},
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Semaphore, mpsc};
+
+use crate::{CACHE_DIR, Starlink};
+
+const ANNOUNCE_ALL_ROUNDS: u32 = 12;
+const MAX_SYNC_DOWNLOADS: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct SyncFolderOptions {
+    pub ignore_patterns: Vec<String>,
+    pub scan_interval: Duration,
+}
+impl Default for SyncFolderOptions {
+    fn default() -> Self {
+        Self {
+            ignore_patterns: vec![],
+            scan_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SyncFolderStatus {
+    pub path: PathBuf,
+    pub file_count: usize,
+    pub pending: Vec<String>,
+    pub conflicts: Vec<PathBuf>,
+    pub last_scan: Option<SystemTime>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileAnnounce {
+    node_addr: NodeAddr,
+    path: String,
+    hash: Option<Hash>,
+    prev_hash: Option<Hash>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SyncFile {
+    hash: Option<Hash>,
+    size: u64,
+    modified: SystemTime,
+    history: HashSet<Hash>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SyncStateFile {
+    root: PathBuf,
+    files: HashMap<String, SyncFile>,
+}
+
+struct SyncFolderState {
+    topic: TopicId,
+    root: PathBuf,
+    options: SyncFolderOptions,
+    files: HashMap<String, SyncFile>,
+    status: SyncFolderStatus,
+    state_path: PathBuf,
+    dirty: bool,
+}
+impl SyncFolderState {
+    fn load_files(state_path: &Path, root: &Path) -> Result<HashMap<String, SyncFile>> {
+        let state_file = match std::fs::read(state_path) {
+            Ok(bytes) => serde_json::from_slice::<SyncStateFile>(&bytes)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(err) => return Err(err.into()),
+        };
+        if state_file.root != root {
+            return Ok(HashMap::new());
+        }
+        Ok(state_file.files)
+    }
+    fn synced_hashes(&self) -> HashSet<Hash> {
+        self.files.values().filter_map(|file| file.hash).collect()
+    }
+    fn save(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(parent) = self.state_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let state_file = SyncStateFile {
+            root: self.root.clone(),
+            files: std::mem::take(&mut self.files),
+        };
+        let result = serde_json::to_vec(&state_file)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(std::fs::write(&self.state_path, bytes)?));
+        self.files = state_file.files;
+        result?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+pub(crate) struct SyncFolder {
+    state: Arc<Mutex<SyncFolderState>>,
+    _task: AbortOnDropHandle<()>,
+}
+
+struct DownloadDone {
+    id: u64,
+    path: String,
+    hash: Hash,
+    conflict: bool,
+    result: Result<PathBuf>,
+}
+
+struct PendingDownload {
+    id: u64,
+    hash: Hash,
+    _task: AbortOnDropHandle<()>,
+}
+
+struct SyncDownloads {
+    pending: HashMap<String, PendingDownload>,
+    next_id: u64,
+    limit: Arc<Semaphore>,
+    done: mpsc::UnboundedSender<DownloadDone>,
+}
+impl SyncDownloads {
+    fn new(limit: usize) -> (Self, mpsc::UnboundedReceiver<DownloadDone>) {
+        let (done, done_receiver) = mpsc::unbounded_channel();
+        let downloads = Self {
+            pending: HashMap::new(),
+            next_id: 0,
+            limit: Arc::new(Semaphore::new(limit)),
+            done,
+        };
+        (downloads, done_receiver)
+    }
+    fn is_pending(&self, path: &str, hash: Hash) -> bool {
+        self.pending
+            .get(path)
+            .is_some_and(|download| download.hash == hash)
+    }
+    fn cancel(&mut self, path: &str) -> bool {
+        self.pending.remove(path).is_some()
+    }
+    fn start(
+        &mut self,
+        path: String,
+        hash: Hash,
+        conflict: bool,
+        download: impl Future<Output = Result<PathBuf>> + Send + 'static,
+    ) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let task = n0_future::task::spawn({
+            let path = path.clone();
+            let limit = self.limit.clone();
+            let done = self.done.clone();
+            async move {
+                let result = async {
+                    let _permit = limit.acquire().await?;
+                    download.await
+                }
+                .await;
+                let _ = done.send(DownloadDone {
+                    id,
+                    path,
+                    hash,
+                    conflict,
+                    result,
+                });
+            }
+        });
+        self.pending.insert(
+            path,
+            PendingDownload {
+                id,
+                hash,
+                _task: AbortOnDropHandle::new(task),
+            },
+        );
+    }
+    fn finish(&mut self, done: &DownloadDone) -> bool {
+        if self
+            .pending
+            .get(&done.path)
+            .is_some_and(|download| download.id == done.id)
+        {
+            self.pending.remove(&done.path);
+            return true;
+        }
+        false
+    }
+}
+
+enum SyncEvent {
+    Scan,
+    Gossip(Option<Result<Event, iroh_gossip::net::Error>>),
+    Downloaded(DownloadDone),
+}
+
+impl Starlink {
+    pub async fn sync_folder(
+        &self,
+        path: PathBuf,
+        topic: TopicId,
+        peer_node_addrs: Vec<NodeAddr>,
+        options: SyncFolderOptions,
+    ) -> Result<()> {
+        let root = std::fs::canonicalize(&path)?;
+        let peer_node_ids = self.add_peer_node_addrs(peer_node_addrs)?;
+        let (sender, receiver) = self.gossip.subscribe(topic, peer_node_ids)?.split();
+        let state_path = PathBuf::from(CACHE_DIR)
+            .join("sync")
+            .join(format!("{}.json", topic));
+        let files = SyncFolderState::load_files(&state_path, &root).unwrap_or_else(|err| {
+            log::warn!("读取同步状态失败: {}", err);
+            HashMap::new()
+        });
+        self.shares
+            .set_synced(topic, files.values().filter_map(|file| file.hash).collect());
+        let state = Arc::new(Mutex::new(SyncFolderState {
+            topic,
+            root: root.clone(),
+            options,
+            files,
+            status: SyncFolderStatus {
+                path: root,
+                ..Default::default()
+            },
+            state_path,
+            dirty: false,
+        }));
+        let task = n0_future::task::spawn({
+            let starlink = self.clone();
+            let state = state.clone();
+            async move {
+                starlink.sync_folder_loop(state, sender, receiver).await;
+            }
+        });
+        self.sync_folders.lock().insert(
+            topic,
+            SyncFolder {
+                state,
+                _task: AbortOnDropHandle::new(task),
+            },
+        );
+        Ok(())
+    }
+    pub fn stop_sync_folder(&self, topic: TopicId) -> bool {
+        self.shares.set_synced(topic, HashSet::new());
+        self.sync_folders.lock().remove(&topic).is_some()
+    }
+    pub fn sync_folder_status(&self, topic: TopicId) -> Option<SyncFolderStatus> {
+        self.sync_folders
+            .lock()
+            .get(&topic)
+            .map(|sync_folder| sync_folder.state.lock().status.clone())
+    }
+    pub fn sync_folders(&self) -> Vec<(TopicId, SyncFolderStatus)> {
+        self.sync_folders
+            .lock()
+            .iter()
+            .map(|(topic, sync_folder)| (*topic, sync_folder.state.lock().status.clone()))
+            .collect()
+    }
+    async fn sync_folder_loop(
+        &self,
+        state: Arc<Mutex<SyncFolderState>>,
+        sender: GossipSender,
+        mut receiver: GossipReceiver,
+    ) {
+        let scan_interval = state.lock().options.scan_interval;
+        let mut next_scan = Instant::now();
+        let mut round = 0;
+        let (mut downloads, mut download_results) = SyncDownloads::new(MAX_SYNC_DOWNLOADS);
+        loop {
+            let event = n0_future::future::or(
+                async {
+                    sleep_until(next_scan).await;
+                    SyncEvent::Scan
+                },
+                n0_future::future::or(async { SyncEvent::Gossip(receiver.next().await) }, async {
+                    match download_results.recv().await {
+                        Some(done) => SyncEvent::Downloaded(done),
+                        None => n0_future::future::pending().await,
+                    }
+                }),
+            )
+            .await;
+            let result = match event {
+                SyncEvent::Scan => {
+                    next_scan = Instant::now() + scan_interval;
+                    round += 1;
+                    self.scan_sync_folder(&state, &sender, round % ANNOUNCE_ALL_ROUNDS == 1)
+                        .await
+                }
+                SyncEvent::Gossip(Some(Ok(Event::Gossip(GossipEvent::Received(message))))) => {
+                    match postcard::from_bytes::<FileAnnounce>(&message.content) {
+                        Ok(announce) => {
+                            self.apply_file_announce(&state, &mut downloads, announce)
+                                .await
+                        }
+                        Err(err) => Err(err.into()),
+                    }
+                }
+                SyncEvent::Gossip(Some(Ok(_))) => Ok(()),
+                SyncEvent::Gossip(Some(Err(err))) => Err(err.into()),
+                SyncEvent::Gossip(None) => return,
+                SyncEvent::Downloaded(done) => {
+                    if downloads.finish(&done) {
+                        self.finish_sync_download(&state, done).await
+                    } else {
+                        Ok(())
+                    }
+                }
+            };
+            let mut state = state.lock();
+            state.status.last_error = result.err().map(|err| err.to_string());
+            state.status.file_count = state
+                .files
+                .values()
+                .filter(|file| file.hash.is_some())
+                .count();
+            self.shares.set_synced(state.topic, state.synced_hashes());
+            if let Err(err) = state.save() {
+                log::warn!("保存同步状态失败: {}", err);
+            }
+        }
+    }
+    async fn scan_sync_folder(
+        &self,
+        state: &Arc<Mutex<SyncFolderState>>,
+        sender: &GossipSender,
+        announce_all: bool,
+    ) -> Result<()> {
+        let (topic, root, ignore_patterns) = {
+            let state = state.lock();
+            (
+                state.topic,
+                state.root.clone(),
+                state.options.ignore_patterns.clone(),
+            )
+        };
+        let mut found = vec![];
+        walk_folder(&root, &root, &ignore_patterns, &mut found)?;
+        let node_addr = self.router.endpoint().node_addr().await?;
+        let mut announces = vec![];
+        let mut seen = HashSet::new();
+        for (rel_path, abs_path, size, modified) in found {
+            seen.insert(rel_path.clone());
+            let unchanged = state.lock().files.get(&rel_path).is_some_and(|file| {
+                file.hash.is_some() && file.size == size && file.modified == modified
+            });
+            if unchanged {
+                continue;
+            }
+            let add_outcome = self
+                .blobs
+                .client()
+                .add_from_path(
+                    abs_path,
+                    false,
+                    SetTagOption::Named(sync_tag(topic, &rel_path).into()),
+                    WrapOption::NoWrap,
+                )
+                .await?
+                .await?;
+            let mut state = state.lock();
+            state.dirty = true;
+            let file = state.files.entry(rel_path.clone()).or_insert(SyncFile {
+                hash: None,
+                size,
+                modified,
+                history: HashSet::new(),
+            });
+            let prev_hash = file.hash;
+            file.size = size;
+            file.modified = modified;
+            if prev_hash != Some(add_outcome.hash) {
+                file.hash = Some(add_outcome.hash);
+                file.history.insert(add_outcome.hash);
+                announces.push(FileAnnounce {
+                    node_addr: node_addr.clone(),
+                    path: rel_path,
+                    hash: Some(add_outcome.hash),
+                    prev_hash,
+                });
+            }
+        }
+        let mut deleted = vec![];
+        {
+            let mut state = state.lock();
+            for (rel_path, file) in state.files.iter_mut() {
+                if file.hash.is_some() && !seen.contains(rel_path) {
+                    announces.push(FileAnnounce {
+                        node_addr: node_addr.clone(),
+                        path: rel_path.clone(),
+                        hash: None,
+                        prev_hash: file.hash.take(),
+                    });
+                    deleted.push(rel_path.clone());
+                }
+            }
+            if !deleted.is_empty() {
+                state.dirty = true;
+            }
+            if announce_all {
+                let announced = announces
+                    .iter()
+                    .map(|announce| announce.path.clone())
+                    .collect::<HashSet<_>>();
+                for (rel_path, file) in &state.files {
+                    if !announced.contains(rel_path) {
+                        announces.push(FileAnnounce {
+                            node_addr: node_addr.clone(),
+                            path: rel_path.clone(),
+                            hash: file.hash,
+                            prev_hash: None,
+                        });
+                    }
+                }
+            }
+            state.status.last_scan = Some(SystemTime::now());
+            self.shares.set_synced(topic, state.synced_hashes());
+        }
+        for rel_path in deleted {
+            self.blobs
+                .client()
+                .tags()
+                .delete(sync_tag(topic, &rel_path))
+                .await?;
+        }
+        for announce in announces {
+            sender
+                .broadcast(postcard::to_stdvec(&announce)?.into())
+                .await?;
+        }
+        Ok(())
+    }
+    async fn apply_file_announce(
+        &self,
+        state: &Arc<Mutex<SyncFolderState>>,
+        downloads: &mut SyncDownloads,
+        announce: FileAnnounce,
+    ) -> Result<()> {
+        let (topic, root, ignore_patterns) = {
+            let state = state.lock();
+            (
+                state.topic,
+                state.root.clone(),
+                state.options.ignore_patterns.clone(),
+            )
+        };
+        if is_ignored(&announce.path, &ignore_patterns) {
+            return Ok(());
+        }
+        let Some(abs_path) = sync_path(&root, &announce.path) else {
+            log::warn!("忽略不合法的同步路径: {}", announce.path);
+            return Ok(());
+        };
+        let conflict = {
+            let state = state.lock();
+            match state.files.get(&announce.path) {
+                Some(file) => {
+                    if file.hash == announce.hash
+                        || announce
+                            .hash
+                            .is_some_and(|hash| file.history.contains(&hash))
+                    {
+                        return Ok(());
+                    }
+                    file.hash.is_some() && file.hash != announce.prev_hash
+                }
+                None => abs_path.exists(),
+            }
+        };
+        let Some(hash) = announce.hash else {
+            if downloads.cancel(&announce.path) {
+                state
+                    .lock()
+                    .status
+                    .pending
+                    .retain(|path| path != &announce.path);
+            }
+            if !conflict {
+                if abs_path.exists() {
+                    std::fs::remove_file(&abs_path)?;
+                }
+                {
+                    let mut state = state.lock();
+                    if let Some(file) = state.files.get_mut(&announce.path) {
+                        file.hash = None;
+                        state.dirty = true;
+                    }
+                }
+                self.blobs
+                    .client()
+                    .tags()
+                    .delete(sync_tag(topic, &announce.path))
+                    .await?;
+            }
+            return Ok(());
+        };
+        if downloads.is_pending(&announce.path, hash) {
+            return Ok(());
+        }
+        let target_path = if conflict {
+            conflict_path(&abs_path, &announce.node_addr)
+        } else {
+            abs_path
+        };
+        {
+            let mut state = state.lock();
+            state.status.pending.retain(|path| path != &announce.path);
+            state.status.pending.push(announce.path.clone());
+        }
+        let starlink = self.clone();
+        let node_addr = announce.node_addr;
+        downloads.start(announce.path, hash, conflict, async move {
+            starlink
+                .download_file(BlobTicket::new(node_addr, hash, BlobFormat::Raw)?)
+                .await?
+                .finish()
+                .await?;
+            if let Some(parent) = target_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            starlink
+                .blobs
+                .client()
+                .export(
+                    hash,
+                    target_path.clone(),
+                    ExportFormat::Blob,
+                    ExportMode::Copy,
+                )
+                .await?
+                .finish()
+                .await?;
+            Ok(target_path)
+        });
+        Ok(())
+    }
+    async fn finish_sync_download(
+        &self,
+        state: &Arc<Mutex<SyncFolderState>>,
+        done: DownloadDone,
+    ) -> Result<()> {
+        let DownloadDone {
+            path,
+            hash,
+            conflict,
+            result,
+            ..
+        } = done;
+        let topic = {
+            let mut state = state.lock();
+            state.status.pending.retain(|pending| pending != &path);
+            let target_path = result?;
+            if conflict {
+                state.status.conflicts.push(target_path);
+                return Ok(());
+            }
+            let metadata = std::fs::metadata(&target_path)?;
+            state.dirty = true;
+            let file = state.files.entry(path.clone()).or_insert(SyncFile {
+                hash: None,
+                size: 0,
+                modified: SystemTime::UNIX_EPOCH,
+                history: HashSet::new(),
+            });
+            file.hash = Some(hash);
+            file.size = metadata.len();
+            file.modified = metadata.modified()?;
+            file.history.insert(hash);
+            state.topic
+        };
+        self.blobs
+            .client()
+            .tags()
+            .set(sync_tag(topic, &path), hash)
+            .await
+    }
+}
+
+fn sync_path(root: &Path, rel_path: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for part in rel_path.split('/') {
+        if part.contains(['\\', ':']) {
+            return None;
+        }
+        let mut components = Path::new(part).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) if name == part => path.push(name),
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+fn sync_tag(topic: TopicId, rel_path: &str) -> String {
+    format!("sync-{}-{}", topic, rel_path)
+}
+
+fn conflict_path(path: &Path, node_addr: &NodeAddr) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut file_name = format!("{}.sync-conflict-{}", stem, node_addr.node_id.fmt_short());
+    if let Some(extension) = path.extension() {
+        file_name.push('.');
+        file_name.push_str(&extension.to_string_lossy());
+    }
+    path.with_file_name(file_name)
+}
+
+pub(crate) fn walk_folder(
+    root: &Path,
+    dir: &Path,
+    ignore_patterns: &[String],
+    found: &mut Vec<(String, PathBuf, u64, SystemTime)>,
+) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let rel_path = path
+            .strip_prefix(root)?
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if is_ignored(&rel_path, ignore_patterns) {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            walk_folder(root, &path, ignore_patterns, found)?;
+        } else if metadata.is_file() {
+            found.push((rel_path, path, metadata.len(), metadata.modified()?));
+        }
+    }
+    Ok(())
+}
+
+fn is_ignored(rel_path: &str, ignore_patterns: &[String]) -> bool {
+    let file_name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+    ignore_patterns.iter().any(|pattern| {
+        wildcard_match(pattern.as_bytes(), rel_path.as_bytes())
+            || wildcard_match(pattern.as_bytes(), file_name.as_bytes())
+    })
+}
+
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard_match(&pattern[1..], text)
+                || (!text.is_empty() && wildcard_match(pattern, &text[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard_match(&pattern[1..], &text[1..]),
+        (Some(p), Some(t)) if p == t => wildcard_match(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_nested_relative_paths() {
+        let root = Path::new("sync-root");
+        assert_eq!(
+            sync_path(root, "docs/报告.txt"),
+            Some(root.join("docs").join("报告.txt"))
+        );
+    }
+
+    #[test]
+    fn rejects_paths_escaping_the_root() {
+        let root = Path::new("sync-root");
+        for rel_path in [
+            "",
+            "..",
+            "a/../../x",
+            "a/./b",
+            "a//b",
+            "/etc/passwd",
+            "..\\..\\x",
+            "a\\b",
+            "C:\\x",
+            "C:x",
+            "\\\\server\\share",
+        ] {
+            assert_eq!(sync_path(root, rel_path), None, "{}", rel_path);
+        }
+    }
+
+    #[test]
+    fn matches_ignore_patterns() {
+        let patterns = vec!["*.tmp".to_string(), "build/*".to_string()];
+        assert!(is_ignored("a/b/c.tmp", &patterns));
+        assert!(is_ignored("build/out.o", &patterns));
+        assert!(!is_ignored("src/main.rs", &patterns));
+    }
+
+    #[test]
+    fn sync_state_round_trips() {
+        let dir =
+            std::env::temp_dir().join(format!("starlink-sync-test-{}", rand::random::<u64>()));
+        let root = dir.join("root");
+        let mut state = SyncFolderState {
+            topic: TopicId::from_bytes([1; 32]),
+            root: root.clone(),
+            options: SyncFolderOptions::default(),
+            files: HashMap::from([(
+                "a.txt".to_string(),
+                SyncFile {
+                    hash: Some(Hash::new(b"a")),
+                    size: 1,
+                    modified: SystemTime::UNIX_EPOCH,
+                    history: HashSet::from([Hash::new(b"a")]),
+                },
+            )]),
+            status: SyncFolderStatus::default(),
+            state_path: dir.join("state.json"),
+            dirty: true,
+        };
+        state.save().unwrap();
+        assert!(!state.dirty);
+        let files = SyncFolderState::load_files(&state.state_path, &root).unwrap();
+        assert_eq!(files["a.txt"].hash, Some(Hash::new(b"a")));
+        let other_root = SyncFolderState::load_files(&state.state_path, &dir).unwrap();
+        assert!(other_root.is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn newer_announce_supersedes_pending_download() {
+        let (mut downloads, mut results) = SyncDownloads::new(MAX_SYNC_DOWNLOADS);
+        let (_old_release, old_wait) = tokio::sync::oneshot::channel::<()>();
+        downloads.start("a.txt".to_string(), Hash::new(b"old"), false, async move {
+            let _ = old_wait.await;
+            Ok(PathBuf::from("old"))
+        });
+        assert!(downloads.is_pending("a.txt", Hash::new(b"old")));
+        downloads.start("a.txt".to_string(), Hash::new(b"new"), false, async {
+            Ok(PathBuf::from("new"))
+        });
+        assert!(!downloads.is_pending("a.txt", Hash::new(b"old")));
+        let done = results.recv().await.unwrap();
+        assert_eq!(done.hash, Hash::new(b"new"));
+        assert!(downloads.finish(&done));
+        assert!(downloads.pending.is_empty());
+        n0_future::time::sleep(Duration::from_millis(20)).await;
+        assert!(results.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn concurrent_downloads_are_bounded() {
+        let (mut downloads, mut results) = SyncDownloads::new(1);
+        let (release, wait) = tokio::sync::oneshot::channel::<()>();
+        downloads.start("a.txt".to_string(), Hash::new(b"a"), false, async move {
+            let _ = wait.await;
+            Ok(PathBuf::from("a"))
+        });
+        downloads.start("b.txt".to_string(), Hash::new(b"b"), false, async {
+            Ok(PathBuf::from("b"))
+        });
+        n0_future::time::sleep(Duration::from_millis(20)).await;
+        assert!(results.try_recv().is_err());
+        release.send(()).unwrap();
+        let first = results.recv().await.unwrap();
+        let second = results.recv().await.unwrap();
+        assert_eq!(first.path, "a.txt");
+        assert_eq!(second.path, "b.txt");
+        assert!(downloads.finish(&first));
+        assert!(downloads.finish(&second));
+    }
+
+    #[tokio::test]
+    async fn stale_results_are_ignored() {
+        let (mut downloads, mut results) = SyncDownloads::new(MAX_SYNC_DOWNLOADS);
+        downloads.start("a.txt".to_string(), Hash::new(b"a"), false, async {
+            Ok(PathBuf::from("a"))
+        });
+        let done = results.recv().await.unwrap();
+        let (_release, wait) = tokio::sync::oneshot::channel::<()>();
+        downloads.start("a.txt".to_string(), Hash::new(b"b"), false, async move {
+            let _ = wait.await;
+            Ok(PathBuf::from("b"))
+        });
+        assert!(!downloads.finish(&done));
+        assert!(downloads.is_pending("a.txt", Hash::new(b"b")));
+        assert!(downloads.cancel("a.txt"));
+        assert!(!downloads.cancel("a.txt"));
+    }
+}
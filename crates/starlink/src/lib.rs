@@ -1,69 +1,283 @@
 #[cfg(not(target_family = "wasm"))]
-use std::path::PathBuf;
+mod blob_range;
+#[cfg(not(target_family = "wasm"))]
+mod blob_store;
+mod collab_text;
+mod custom_protocols;
+#[cfg(not(target_family = "wasm"))]
+mod daemon;
+mod file_ticket;
+#[cfg(not(target_family = "wasm"))]
+mod folder_sync;
+mod mem_blobs;
+mod replicated_doc;
+mod room_ticket;
+#[cfg(not(target_family = "wasm"))]
+mod shares;
+#[cfg(not(target_family = "wasm"))]
+mod store_verify;
+#[cfg(not(target_family = "wasm"))]
+mod transfer_limits;
+#[cfg(not(target_family = "wasm"))]
+mod transfer_log;
+#[cfg(target_family = "wasm")]
+mod wasm_blobs;
 
 use anyhow::Result;
-use iroh::{Endpoint, NodeAddr, protocol::Router};
+#[cfg(not(target_family = "wasm"))]
+use bytes::Bytes;
+#[cfg(not(target_family = "wasm"))]
+use iroh::SecretKey;
+use iroh::{Endpoint, NodeAddr, NodeId, endpoint::RemoteInfo, protocol::Router};
 use iroh_gossip::{
     net::{Gossip, GossipReceiver, GossipSender},
     proto::TopicId,
 };
+#[cfg(not(target_family = "wasm"))]
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 #[cfg(not(target_family = "wasm"))]
 use iroh_blobs::{
     net_protocol::Blobs,
-    rpc::client::blobs::{DownloadProgress, WrapOption},
+    rpc::client::blobs::{AddOutcome, DownloadProgress, Reader},
     store::{ExportFormat, ExportMode, fs::Store},
-    ticket::BlobTicket,
     util::SetTagOption,
 };
+#[cfg(not(target_family = "wasm"))]
+use n0_future::Stream;
+#[cfg(not(target_family = "wasm"))]
+use parking_lot::Mutex;
+#[cfg(not(target_family = "wasm"))]
+use tokio::io::AsyncRead;
+
+#[cfg(not(target_family = "wasm"))]
+pub use blob_range::BlobRangeReader;
+#[cfg(not(target_family = "wasm"))]
+use blob_store::BlobMetaStore;
+#[cfg(not(target_family = "wasm"))]
+pub use blob_store::{BlobEntry, BlobStoreUsage};
+pub use collab_text::{CollabText, TextChange, TextSelection};
+pub use custom_protocols::ProtocolRequest;
+use custom_protocols::{CUSTOM_PROTOCOLS_ALPN, CustomProtocols};
+#[cfg(not(target_family = "wasm"))]
+pub use daemon::{
+    DAEMON_ADDR, DaemonClient, DaemonNode, DaemonOptions, DaemonRequest, DaemonResponse, PeerInfo,
+    RemoteRequest, TopicEvent, default_download_dir,
+};
+pub use file_ticket::FileTicket;
+#[cfg(not(target_family = "wasm"))]
+use folder_sync::SyncFolder;
+#[cfg(not(target_family = "wasm"))]
+pub use folder_sync::{SyncFolderOptions, SyncFolderStatus};
+#[cfg(not(target_family = "wasm"))]
+pub use iroh_blobs::ticket::BlobTicket;
+use mem_blobs::{MEM_BLOBS_ALPN, MemBlobs};
+pub use replicated_doc::{DocChange, ReplicatedDoc};
+pub use room_ticket::RoomTicket;
+#[cfg(not(target_family = "wasm"))]
+use shares::ShareStore;
+#[cfg(not(target_family = "wasm"))]
+pub use shares::{ShareInfo, ShareOptions};
+#[cfg(not(target_family = "wasm"))]
+pub use store_verify::{BlobStatus, VerifyProgress, VerifyReport};
+#[cfg(not(target_family = "wasm"))]
+use transfer_limits::{ThrottledBlobs, TransferDirection, TransferScheduler};
+#[cfg(not(target_family = "wasm"))]
+pub use transfer_limits::{TransferLimits, TransferPriority, TransferStats};
+#[cfg(not(target_family = "wasm"))]
+use transfer_log::TransferLog;
+#[cfg(not(target_family = "wasm"))]
+pub use transfer_log::{TransferLogEntry, TransferLogFormat, TransferLogKind, TransferLogQuery};
+#[cfg(target_family = "wasm")]
+pub use wasm_blobs::{BlobFormat, BlobTicket, DownloadOutcome, DownloadProgress, Hash, Reader};
+
+#[cfg(not(target_family = "wasm"))]
+const CACHE_DIR: &str = "./cache/";
 
 #[derive(Clone)]
 pub struct Starlink {
     router: Router,
     gossip: Gossip,
+    custom_protocols: CustomProtocols,
     #[cfg(not(target_family = "wasm"))]
     blobs: Blobs<Store>,
+    #[cfg(not(target_family = "wasm"))]
+    blob_meta: BlobMetaStore,
+    #[cfg(not(target_family = "wasm"))]
+    sync_folders: Arc<Mutex<HashMap<TopicId, SyncFolder>>>,
+    #[cfg(not(target_family = "wasm"))]
+    transfers: TransferScheduler,
+    #[cfg(not(target_family = "wasm"))]
+    shares: ShareStore,
+    #[cfg(not(target_family = "wasm"))]
+    transfer_log: TransferLog,
+    #[cfg(target_family = "wasm")]
+    mem_blobs: MemBlobs,
+}
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn write_private_file(path: &Path, bytes: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    Ok(())
+}
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let mut file = std::fs::File::create(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
 }
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn restrict_file_permissions(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            log::warn!("{} 的权限过于宽松，已改为仅所有者可读写", path.display());
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+#[cfg(not(target_family = "wasm"))]
+fn load_secret_key(path: PathBuf) -> Result<SecretKey> {
+    match std::fs::read(&path) {
+        Ok(bytes) => {
+            restrict_file_permissions(&path)?;
+            Ok(SecretKey::from_bytes(
+                &bytes
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("密钥文件已损坏"))?,
+            ))
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let secret_key = SecretKey::from_bytes(&rand::random());
+            write_private_file(&path, &secret_key.to_bytes())?;
+            Ok(secret_key)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
 impl Starlink {
     pub async fn new() -> Result<Self> {
         #[allow(unused_mut)]
         let mut endpoint_builder = Endpoint::builder().discovery_n0();
         #[cfg(not(target_family = "wasm"))]
         {
-            endpoint_builder = endpoint_builder.discovery_local_network().discovery_dht();
+            endpoint_builder = endpoint_builder
+                .discovery_local_network()
+                .discovery_dht()
+                .secret_key(load_secret_key(
+                    PathBuf::from(CACHE_DIR).join("secret_key"),
+                )?);
         }
         let endpoint = endpoint_builder.bind().await?;
         let gossip = Gossip::builder().spawn(endpoint.clone()).await?;
         #[cfg(not(target_family = "wasm"))]
-        let blobs = Blobs::persistent("./cache/").await?.build(&endpoint);
+        let transfer_log = TransferLog::new(PathBuf::from(CACHE_DIR).join("transfer_log.jsonl"));
+        #[cfg(not(target_family = "wasm"))]
+        let shares = ShareStore::load(
+            PathBuf::from(CACHE_DIR).join("shares.json"),
+            transfer_log.clone(),
+        )?;
+        #[cfg(not(target_family = "wasm"))]
+        let blobs = Blobs::persistent(CACHE_DIR)
+            .await?
+            .events(shares.clone().into())
+            .build(&endpoint);
+        #[cfg(not(target_family = "wasm"))]
+        let blob_meta = BlobMetaStore::load(PathBuf::from(CACHE_DIR).join("blob_meta.json"))?;
+        #[cfg(not(target_family = "wasm"))]
+        let transfers = TransferScheduler::default();
+        #[cfg(not(target_family = "wasm"))]
+        let mem_blobs = MemBlobs::new(blobs.clone(), shares.clone(), transfers.clone());
+        #[cfg(target_family = "wasm")]
+        let mem_blobs = MemBlobs::new();
+        let custom_protocols = CustomProtocols::default();
         #[allow(unused_mut)]
-        let mut router_builder =
-            Router::builder(endpoint).accept(iroh_gossip::ALPN, gossip.clone());
+        let mut router_builder = Router::builder(endpoint)
+            .accept(iroh_gossip::ALPN, gossip.clone())
+            .accept(MEM_BLOBS_ALPN, mem_blobs.clone())
+            .accept(CUSTOM_PROTOCOLS_ALPN, custom_protocols.clone());
         #[cfg(not(target_family = "wasm"))]
         {
-            router_builder = router_builder.accept(iroh_blobs::ALPN, blobs.clone());
+            router_builder = router_builder.accept(
+                iroh_blobs::ALPN,
+                ThrottledBlobs::new(blobs.clone(), transfers.clone(), shares.clone()),
+            );
         }
         let router = router_builder.spawn();
         Ok(Self {
             router,
             gossip,
+            custom_protocols,
             #[cfg(not(target_family = "wasm"))]
             blobs,
+            #[cfg(not(target_family = "wasm"))]
+            blob_meta,
+            #[cfg(not(target_family = "wasm"))]
+            sync_folders: Arc::new(Mutex::new(HashMap::new())),
+            #[cfg(not(target_family = "wasm"))]
+            transfers,
+            #[cfg(not(target_family = "wasm"))]
+            shares,
+            #[cfg(not(target_family = "wasm"))]
+            transfer_log,
+            #[cfg(target_family = "wasm")]
+            mem_blobs,
         })
     }
     pub async fn node_addr(&self) -> Result<NodeAddr> {
         self.router.endpoint().node_addr().await
     }
-    pub async fn subscribe_topic(
-        &self,
-        topic: TopicId,
-        peer_node_addrs: Vec<NodeAddr>,
-    ) -> Result<(GossipSender, GossipReceiver)> {
+    pub fn node_id(&self) -> NodeId {
+        self.router.endpoint().node_id()
+    }
+    pub fn peers(&self) -> Vec<RemoteInfo> {
+        self.router.endpoint().remote_info_iter().collect()
+    }
+    fn add_peer_node_addrs(&self, peer_node_addrs: Vec<NodeAddr>) -> Result<Vec<NodeId>> {
         let mut peer_node_ids = vec![];
         for peer_node_addr in peer_node_addrs {
             peer_node_ids.push(peer_node_addr.node_id);
             self.router.endpoint().add_node_addr(peer_node_addr)?;
         }
+        Ok(peer_node_ids)
+    }
+    pub async fn subscribe_topic(
+        &self,
+        topic: TopicId,
+        peer_node_addrs: Vec<NodeAddr>,
+    ) -> Result<(GossipSender, GossipReceiver)> {
+        let peer_node_ids = self.add_peer_node_addrs(peer_node_addrs)?;
         let (sender, receiver) = self
             .gossip
             .subscribe_and_join(topic, peer_node_ids)
@@ -71,27 +285,104 @@ impl Starlink {
             .split();
         Ok((sender, receiver))
     }
+    pub fn subscribe_topic_detached(
+        &self,
+        topic: TopicId,
+        peer_node_addrs: Vec<NodeAddr>,
+    ) -> Result<(GossipSender, GossipReceiver)> {
+        let peer_node_ids = self.add_peer_node_addrs(peer_node_addrs)?;
+        let (sender, receiver) = self.gossip.subscribe(topic, peer_node_ids)?.split();
+        Ok((sender, receiver))
+    }
     #[cfg(not(target_family = "wasm"))]
     pub async fn shared_file(&self, path: PathBuf) -> Result<BlobTicket> {
+        self.shared_file_with_options(path, ShareOptions::default())
+            .await
+    }
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn share_bytes(&self, bytes: impl Into<Bytes>) -> Result<BlobTicket> {
+        let add_outcome = self.blobs.client().add_bytes(bytes).await?;
+        self.shared_outcome(add_outcome, ShareOptions::default())
+            .await
+    }
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn share_reader(
+        &self,
+        reader: impl AsyncRead + Unpin + Send + 'static,
+    ) -> Result<BlobTicket> {
         let add_outcome = self
             .blobs
             .client()
-            .add_from_path(path, false, SetTagOption::Auto, WrapOption::NoWrap)
+            .add_reader(reader, SetTagOption::Auto)
             .await?
             .await?;
-        Ok(BlobTicket::new(
+        self.shared_outcome(add_outcome, ShareOptions::default())
+            .await
+    }
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn share_stream(
+        &self,
+        stream: impl Stream<Item = std::io::Result<Bytes>> + Send + Unpin + 'static,
+    ) -> Result<BlobTicket> {
+        let add_outcome = self
+            .blobs
+            .client()
+            .add_stream(stream, SetTagOption::Auto)
+            .await?
+            .await?;
+        self.shared_outcome(add_outcome, ShareOptions::default())
+            .await
+    }
+    #[cfg(not(target_family = "wasm"))]
+    async fn shared_outcome(
+        &self,
+        add_outcome: AddOutcome,
+        options: ShareOptions,
+    ) -> Result<BlobTicket> {
+        self.blob_meta.touch(add_outcome.hash)?;
+        self.shares
+            .create(add_outcome.hash, add_outcome.size, options)?;
+        self.enforce_blob_quota(&[add_outcome.hash]).await?;
+        BlobTicket::new(
             self.router.endpoint().node_addr().await?,
             add_outcome.hash,
             add_outcome.format,
-        )?)
+        )
     }
     #[cfg(not(target_family = "wasm"))]
     pub async fn download_file(&self, ticket: BlobTicket) -> Result<DownloadProgress> {
-        Ok(self
-            .blobs
-            .client()
-            .download(ticket.hash(), ticket.node_addr().clone())
-            .await?)
+        self.download_file_with_priority(ticket, TransferPriority::Normal)
+            .await
+    }
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn read_to_bytes(&self, ticket: BlobTicket) -> Result<Bytes> {
+        self.reader(ticket).await?.read_to_bytes().await
+    }
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn reader(&self, ticket: BlobTicket) -> Result<Reader> {
+        if !self.blobs.client().has(ticket.hash()).await? {
+            let download_result = match self.download_file(ticket.clone()).await {
+                Ok(download_progress) => download_progress.finish().await.map(|_| ()),
+                Err(err) => Err(err),
+            };
+            if download_result.is_err() {
+                let _permit = self
+                    .transfers
+                    .acquire(TransferDirection::Download, TransferPriority::Normal)
+                    .await?;
+                let bytes = mem_blobs::fetch(
+                    self.router.endpoint(),
+                    ticket.node_addr().clone(),
+                    *ticket.hash().as_bytes(),
+                )
+                .await?;
+                self.throttle_download(ticket.node_addr().node_id, bytes.len() as u64)
+                    .await;
+                self.blobs.client().add_bytes(bytes).await?;
+            }
+        }
+        self.blob_meta.touch(ticket.hash())?;
+        self.blobs.client().read(ticket.hash()).await
     }
     #[cfg(not(target_family = "wasm"))]
     pub async fn save_file(&self, ticket: BlobTicket, file_name: String) -> Result<()> {
@@ -105,6 +396,49 @@ impl Starlink {
             )
             .await?
             .await?;
+        self.blob_meta.touch(ticket.hash())?;
+        self.enforce_blob_quota(&[ticket.hash()]).await?;
         Ok(())
     }
 }
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_key_is_created_private_and_reloaded() {
+        let dir = std::env::temp_dir().join(format!("starlink-key-test-{}", rand::random::<u64>()));
+        let path = dir.join("secret_key");
+        let created = load_secret_key(path.clone()).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert!(write_private_file(&path, b"other").is_err());
+        assert_eq!(
+            load_secret_key(path.clone()).unwrap().to_bytes(),
+            created.to_bytes()
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn loose_secret_key_permissions_are_tightened() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("starlink-key-test-{}", rand::random::<u64>()));
+        let path = dir.join("secret_key");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, [5; 32]).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        load_secret_key(path.clone()).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
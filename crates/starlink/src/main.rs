@@ -0,0 +1,229 @@
+fn main() {
+    cei::main();
+}
+
+#[cfg(not(target_family = "wasm"))]
+mod cei {
+    use std::path::PathBuf;
+
+    use anyhow::Result;
+    use clap::{Parser, Subcommand};
+    use iroh::NodeAddr;
+    use iroh_blobs::Hash;
+    use iroh_gossip::proto::TopicId;
+    use starlink::{BlobEntry, DaemonClient, DaemonRequest, PeerInfo, RoomTicket, TopicEvent};
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    #[derive(Parser)]
+    #[command(
+        name = "starlink",
+        about = "Starlink 点对点命令行工具，需要先启动 starlinkd"
+    )]
+    struct Cli {
+        #[command(subcommand)]
+        command: Command,
+    }
+
+    #[derive(Subcommand)]
+    enum Command {
+        #[command(about = "显示本节点的 ID 和地址")]
+        Id,
+        #[command(about = "分享文件，由守护进程保持在线")]
+        Share { path: PathBuf },
+        #[command(about = "通过票据下载文件，文件保存在守护进程的下载目录中")]
+        Get {
+            ticket: String,
+            #[arg(help = "保存的文件名，不能包含目录，默认使用票据中的文件名")]
+            name: Option<String>,
+        },
+        #[command(about = "创建或加入聊天室")]
+        Room {
+            #[command(subcommand)]
+            command: RoomCommand,
+        },
+        #[command(about = "向聊天室发送一条消息")]
+        Send { ticket: RoomTicket, message: String },
+        #[command(about = "监听聊天室消息")]
+        Listen { ticket: RoomTicket },
+        #[command(about = "列出已知的节点")]
+        Peers,
+        #[command(about = "管理本地数据")]
+        Blobs {
+            #[command(subcommand)]
+            command: BlobsCommand,
+        },
+    }
+
+    #[derive(Subcommand)]
+    enum RoomCommand {
+        #[command(about = "创建新的聊天室")]
+        Create,
+        #[command(about = "加入已有的聊天室")]
+        Join { ticket: RoomTicket },
+    }
+
+    #[derive(Subcommand)]
+    enum BlobsCommand {
+        #[command(about = "列出本地数据")]
+        Ls,
+        #[command(about = "删除本地数据")]
+        Rm { hash: Hash },
+        #[command(about = "清理未标记的数据")]
+        Gc,
+    }
+
+    #[tokio::main]
+    pub async fn main() {
+        env_logger::builder()
+            .filter_level(log::LevelFilter::Off)
+            .filter_module("starlink", log::LevelFilter::Warn)
+            .parse_default_env()
+            .init();
+        if let Err(err) = run(Cli::parse()).await {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+    async fn run(cli: Cli) -> Result<()> {
+        let mut client = DaemonClient::connect_default().await?;
+        match cli.command {
+            Command::Id => {
+                let node_addr = node_addr(&mut client).await?;
+                println!("{}", node_addr.node_id);
+                if let Some(relay_url) = &node_addr.relay_url {
+                    println!("中继: {}", relay_url);
+                }
+                for direct_address in &node_addr.direct_addresses {
+                    println!("地址: {}", direct_address);
+                }
+            }
+            Command::Share { path } => {
+                let path = std::fs::canonicalize(path)?;
+                let ticket = client.call(&DaemonRequest::Share { path }).await?;
+                println!("{}", serde_json::from_value::<String>(ticket)?);
+            }
+            Command::Get { ticket, name } => {
+                let dest = client
+                    .call(&DaemonRequest::Download { ticket, name })
+                    .await?;
+                println!(
+                    "已保存到 {}",
+                    serde_json::from_value::<PathBuf>(dest)?.display()
+                );
+            }
+            Command::Room { command } => {
+                let ticket = match command {
+                    RoomCommand::Create => {
+                        let ticket = RoomTicket::new(
+                            TopicId::from_bytes(rand::random()),
+                            vec![node_addr(&mut client).await?],
+                        );
+                        println!("{}", ticket);
+                        println!("等待其他节点加入…");
+                        ticket
+                    }
+                    RoomCommand::Join { ticket } => ticket,
+                };
+                join_room(&mut client, &ticket).await?;
+                println!("已加入聊天室，输入消息后回车发送");
+                tokio::spawn(read_stdin(ticket.topic()));
+                print_messages(client, ticket.topic()).await?;
+            }
+            Command::Send { ticket, message } => {
+                join_room(&mut client, &ticket).await?;
+                client
+                    .call(&DaemonRequest::Broadcast {
+                        topic: ticket.topic(),
+                        message,
+                    })
+                    .await?;
+            }
+            Command::Listen { ticket } => {
+                join_room(&mut client, &ticket).await?;
+                print_messages(client, ticket.topic()).await?;
+            }
+            Command::Peers => {
+                let peers = client.call(&DaemonRequest::Peers).await?;
+                for peer in serde_json::from_value::<Vec<PeerInfo>>(peers)? {
+                    println!("{}\t{}\t{:?}", peer.node_id, peer.conn_type, peer.latency);
+                }
+            }
+            Command::Blobs { command } => match command {
+                BlobsCommand::Ls => {
+                    let entries = client.call(&DaemonRequest::ListBlobs).await?;
+                    for entry in serde_json::from_value::<Vec<BlobEntry>>(entries)? {
+                        println!(
+                            "{}\t{}\t{}{}",
+                            entry.hash,
+                            entry.size,
+                            entry.tags.join(","),
+                            if entry.pinned { "\t固定" } else { "" }
+                        );
+                    }
+                }
+                BlobsCommand::Rm { hash } => {
+                    client.call(&DaemonRequest::DeleteBlob { hash }).await?;
+                }
+                BlobsCommand::Gc => {
+                    let freed = client.call(&DaemonRequest::GcBlobs).await?;
+                    println!("已释放 {} 字节", serde_json::from_value::<u64>(freed)?);
+                }
+            },
+        }
+        Ok(())
+    }
+    async fn node_addr(client: &mut DaemonClient) -> Result<NodeAddr> {
+        Ok(serde_json::from_value(
+            client.call(&DaemonRequest::NodeAddr).await?,
+        )?)
+    }
+    async fn join_room(client: &mut DaemonClient, ticket: &RoomTicket) -> Result<()> {
+        client
+            .call(&DaemonRequest::JoinTopic {
+                topic: ticket.topic(),
+                nodes: ticket.nodes().to_vec(),
+            })
+            .await?;
+        Ok(())
+    }
+    async fn read_stdin(topic: TopicId) -> Result<()> {
+        let mut client = DaemonClient::connect_default().await?;
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Some(message) = lines.next_line().await? {
+            client
+                .call(&DaemonRequest::Broadcast { topic, message })
+                .await?;
+        }
+        Ok(())
+    }
+    async fn print_messages(mut client: DaemonClient, topic: TopicId) -> Result<()> {
+        client
+            .call(&DaemonRequest::Listen {
+                topic,
+                binary: false,
+            })
+            .await?;
+        while let Some((_, event)) = client.next_event().await? {
+            match event {
+                TopicEvent::Message { from, content } => {
+                    println!("{}: {}", from.fmt_short(), content);
+                }
+                TopicEvent::Data { from, data } => {
+                    println!("{}: {}", from.fmt_short(), String::from_utf8_lossy(&data));
+                }
+                TopicEvent::NeighborUp(node_id) => {
+                    println!("{} 加入", node_id.fmt_short());
+                }
+                TopicEvent::NeighborDown(node_id) => {
+                    println!("{} 离开", node_id.fmt_short());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_family = "wasm")]
+mod cei {
+    pub fn main() {}
+}
@@ -0,0 +1,269 @@
+#[cfg(any(test, target_family = "wasm"))]
+use std::collections::{HashMap, HashSet, VecDeque};
+#[cfg(target_family = "wasm")]
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use iroh::{
+    Endpoint, NodeAddr,
+    endpoint::{Connection, VarInt},
+    protocol::ProtocolHandler,
+};
+use n0_future::boxed::BoxFuture;
+#[cfg(target_family = "wasm")]
+use parking_lot::Mutex;
+
+#[cfg(not(target_family = "wasm"))]
+use iroh::NodeId;
+#[cfg(not(target_family = "wasm"))]
+use iroh_blobs::{Hash, net_protocol::Blobs, store::fs::Store};
+
+#[cfg(not(target_family = "wasm"))]
+use crate::{
+    shares::ShareStore,
+    transfer_limits::{TransferDirection, TransferPriority, TransferScheduler},
+};
+
+pub(crate) const MEM_BLOBS_ALPN: &[u8] = b"starlink/mem-blobs/0";
+const MAX_BLOB_SIZE: usize = 64 * 1024 * 1024;
+#[cfg(any(test, target_family = "wasm"))]
+const MAX_STORE_SIZE: usize = 256 * 1024 * 1024;
+
+#[cfg(any(test, target_family = "wasm"))]
+#[derive(Debug)]
+struct MemStore {
+    blobs: HashMap<[u8; 32], Bytes>,
+    pinned: HashSet<[u8; 32]>,
+    order: VecDeque<[u8; 32]>,
+    size: usize,
+    capacity: usize,
+}
+#[cfg(any(test, target_family = "wasm"))]
+impl Default for MemStore {
+    fn default() -> Self {
+        Self::with_capacity(MAX_STORE_SIZE)
+    }
+}
+#[cfg(any(test, target_family = "wasm"))]
+impl MemStore {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            blobs: HashMap::new(),
+            pinned: HashSet::new(),
+            order: VecDeque::new(),
+            size: 0,
+            capacity,
+        }
+    }
+    fn insert(&mut self, hash: [u8; 32], bytes: Bytes, pin: bool) -> Result<()> {
+        if bytes.len() > MAX_BLOB_SIZE {
+            bail!("数据过大");
+        }
+        if self.blobs.contains_key(&hash) {
+            if pin && self.pinned.insert(hash) {
+                self.order.retain(|queued| *queued != hash);
+            }
+            return Ok(());
+        }
+        while self.size + bytes.len() > self.capacity {
+            let Some(oldest) = self.order.pop_front() else {
+                bail!("内存存储已满，共享中的数据无法被淘汰");
+            };
+            if let Some(removed) = self.blobs.remove(&oldest) {
+                self.size -= removed.len();
+            }
+        }
+        self.size += bytes.len();
+        if pin {
+            self.pinned.insert(hash);
+        } else {
+            self.order.push_back(hash);
+        }
+        self.blobs.insert(hash, bytes);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct MemBlobs {
+    #[cfg(not(target_family = "wasm"))]
+    blobs: Blobs<Store>,
+    #[cfg(not(target_family = "wasm"))]
+    shares: ShareStore,
+    #[cfg(not(target_family = "wasm"))]
+    transfers: TransferScheduler,
+    #[cfg(target_family = "wasm")]
+    store: Arc<Mutex<MemStore>>,
+}
+impl MemBlobs {
+    #[cfg(not(target_family = "wasm"))]
+    pub(crate) fn new(
+        blobs: Blobs<Store>,
+        shares: ShareStore,
+        transfers: TransferScheduler,
+    ) -> Self {
+        Self {
+            blobs,
+            shares,
+            transfers,
+        }
+    }
+    #[cfg(target_family = "wasm")]
+    pub(crate) fn new() -> Self {
+        Self {
+            store: Arc::new(Mutex::new(MemStore::default())),
+        }
+    }
+    #[cfg(target_family = "wasm")]
+    pub(crate) fn insert(&self, bytes: Bytes, pin: bool) -> Result<[u8; 32]> {
+        let hash = *blake3::hash(&bytes).as_bytes();
+        self.store.lock().insert(hash, bytes, pin)?;
+        Ok(hash)
+    }
+    #[cfg(not(target_family = "wasm"))]
+    async fn serve(&self, peer: NodeId, hash: [u8; 32]) -> Result<Option<Bytes>> {
+        let hash = Hash::from(hash);
+        if !self.shares.is_allowed(&hash) || !self.blobs.client().has(hash).await? {
+            return Ok(None);
+        }
+        let mut reader = self.blobs.client().read(hash).await?;
+        if reader.size() > MAX_BLOB_SIZE as u64 {
+            log::warn!("拒绝通过内存协议发送过大的数据 {}", hash);
+            return Ok(None);
+        }
+        let _permit = self
+            .transfers
+            .acquire(TransferDirection::Upload, TransferPriority::Normal)
+            .await?;
+        let bytes = reader.read_to_bytes().await?;
+        self.transfers
+            .throttle(TransferDirection::Upload, peer, bytes.len() as u64)
+            .await;
+        self.shares
+            .record_upload(hash, Some(peer), bytes.len() as u64);
+        Ok(Some(bytes))
+    }
+    #[cfg(target_family = "wasm")]
+    async fn serve(&self, _peer: iroh::NodeId, hash: [u8; 32]) -> Result<Option<Bytes>> {
+        self.get(hash).await
+    }
+    #[cfg(target_family = "wasm")]
+    pub(crate) async fn get(&self, hash: [u8; 32]) -> Result<Option<Bytes>> {
+        Ok(self.store.lock().blobs.get(&hash).cloned())
+    }
+}
+impl ProtocolHandler for MemBlobs {
+    fn accept(&self, connection: Connection) -> BoxFuture<Result<()>> {
+        let mem_blobs = self.clone();
+        Box::pin(async move {
+            let peer = connection.remote_node_id()?;
+            while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+                let mut hash = [0; 32];
+                recv.read_exact(&mut hash).await?;
+                match mem_blobs.serve(peer, hash).await? {
+                    Some(bytes) => {
+                        send.write_all(&bytes).await?;
+                        send.finish()?;
+                    }
+                    None => {
+                        send.reset(VarInt::from_u32(1))?;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+pub(crate) async fn fetch(
+    endpoint: &Endpoint,
+    node_addr: NodeAddr,
+    hash: [u8; 32],
+) -> Result<Bytes> {
+    let connection = endpoint.connect(node_addr, MEM_BLOBS_ALPN).await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send.write_all(&hash).await?;
+    send.finish()?;
+    let bytes = recv.read_to_end(MAX_BLOB_SIZE).await?;
+    connection.close(VarInt::from_u32(0), b"done");
+    if blake3_hash(&bytes) != hash {
+        bail!("数据校验失败");
+    }
+    Ok(bytes.into())
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn blake3_hash(bytes: &[u8]) -> [u8; 32] {
+    *Hash::new(bytes).as_bytes()
+}
+#[cfg(target_family = "wasm")]
+fn blake3_hash(bytes: &[u8]) -> [u8; 32] {
+    *blake3::hash(bytes).as_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blob(data: &[u8]) -> ([u8; 32], Bytes) {
+        (blake3_hash(data), Bytes::copy_from_slice(data))
+    }
+
+    #[test]
+    fn evicts_oldest_unpinned_blob_first() {
+        let mut store = MemStore::with_capacity(8);
+        let (a, a_bytes) = blob(b"aaaa");
+        let (b, b_bytes) = blob(b"bbbb");
+        let (c, c_bytes) = blob(b"cccc");
+        store.insert(a, a_bytes, false).unwrap();
+        store.insert(b, b_bytes, false).unwrap();
+        store.insert(c, c_bytes, false).unwrap();
+        assert!(!store.blobs.contains_key(&a));
+        assert!(store.blobs.contains_key(&b));
+        assert!(store.blobs.contains_key(&c));
+        assert_eq!(store.size, 8);
+    }
+
+    #[test]
+    fn shared_blobs_are_never_evicted() {
+        let mut store = MemStore::with_capacity(8);
+        let (shared, shared_bytes) = blob(b"ssss");
+        let (a, a_bytes) = blob(b"aaaa");
+        let (b, b_bytes) = blob(b"bbbb");
+        store.insert(shared, shared_bytes, true).unwrap();
+        store.insert(a, a_bytes, false).unwrap();
+        store.insert(b, b_bytes, false).unwrap();
+        assert!(store.blobs.contains_key(&shared));
+        assert!(!store.blobs.contains_key(&a));
+        assert!(store.blobs.contains_key(&b));
+    }
+
+    #[test]
+    fn sharing_a_cached_blob_pins_it() {
+        let mut store = MemStore::with_capacity(8);
+        let (a, a_bytes) = blob(b"aaaa");
+        let (b, b_bytes) = blob(b"bbbb");
+        let (c, c_bytes) = blob(b"cccc");
+        store.insert(a, a_bytes.clone(), false).unwrap();
+        store.insert(b, b_bytes, false).unwrap();
+        store.insert(a, a_bytes, true).unwrap();
+        store.insert(c, c_bytes, false).unwrap();
+        assert!(store.blobs.contains_key(&a));
+        assert!(!store.blobs.contains_key(&b));
+    }
+
+    #[test]
+    fn rejects_blob_when_only_shared_blobs_remain() {
+        let mut store = MemStore::with_capacity(8);
+        let (a, a_bytes) = blob(b"aaaa");
+        let (b, b_bytes) = blob(b"bbbb");
+        let (c, c_bytes) = blob(b"cccc");
+        store.insert(a, a_bytes, true).unwrap();
+        store.insert(b, b_bytes, true).unwrap();
+        assert!(store.insert(c, c_bytes, false).is_err());
+        assert!(store.blobs.contains_key(&a));
+        assert!(store.blobs.contains_key(&b));
+        assert_eq!(store.size, 8);
+    }
+}
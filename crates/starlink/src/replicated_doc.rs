@@ -0,0 +1,317 @@
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use bytes::Bytes;
+use iroh::{NodeAddr, NodeId};
+use iroh_gossip::{
+    net::{Event, GossipEvent, GossipReceiver, GossipSender},
+    proto::TopicId,
+};
+use n0_future::{StreamExt, task::AbortOnDropHandle, time::SystemTime};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::Starlink;
+
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(300);
+
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_micros() as u64
+}
+
+#[derive(Debug, Clone)]
+pub struct DocChange {
+    pub key: String,
+    pub value: Option<Bytes>,
+    pub author: NodeId,
+    pub local: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct DocEntry {
+    value: Option<Vec<u8>>,
+    timestamp: u64,
+    author: NodeId,
+}
+impl DocEntry {
+    fn is_newer_than(&self, other: &DocEntry) -> bool {
+        (self.timestamp, self.author) > (other.timestamp, other.author)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum DocMessage {
+    Set { key: String, entry: DocEntry },
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct DocState {
+    clock: u64,
+    entries: BTreeMap<String, DocEntry>,
+}
+impl DocState {
+    fn tick(&mut self, now: u64) -> u64 {
+        self.clock = now.max(self.clock.saturating_add(1));
+        self.clock
+    }
+    fn merge(&mut self, key: &str, entry: &DocEntry, now: u64) -> Result<bool> {
+        if entry.timestamp > now.saturating_add(MAX_CLOCK_SKEW.as_micros() as u64) {
+            anyhow::bail!("文档条目 {} 的时间戳超前本地时钟过多", key);
+        }
+        self.clock = self.clock.max(entry.timestamp);
+        if self
+            .entries
+            .get(key)
+            .is_some_and(|existing| !entry.is_newer_than(existing))
+        {
+            return Ok(false);
+        }
+        self.entries.insert(key.to_string(), entry.clone());
+        Ok(true)
+    }
+}
+
+struct DocInner {
+    topic: TopicId,
+    author: NodeId,
+    path: Option<PathBuf>,
+    state: Mutex<DocState>,
+    sender: GossipSender,
+    changes: broadcast::Sender<DocChange>,
+}
+impl DocInner {
+    fn save(&self, state: &DocState) -> Result<()> {
+        if let Some(path) = &self.path {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, postcard::to_stdvec(state)?)?;
+        }
+        Ok(())
+    }
+    fn merge(&self, key: String, entry: DocEntry) -> Result<()> {
+        let mut state = self.state.lock();
+        if !state.merge(&key, &entry, now_micros())? {
+            return Ok(());
+        }
+        self.save(&state)?;
+        _ = self.changes.send(DocChange {
+            key,
+            value: entry.value.map(Bytes::from),
+            author: entry.author,
+            local: false,
+        });
+        Ok(())
+    }
+    async fn run(self: Arc<Self>, mut receiver: GossipReceiver) {
+        while let Some(event) = receiver.next().await {
+            let result = match event {
+                Ok(Event::Gossip(GossipEvent::Received(message))) => {
+                    match postcard::from_bytes::<DocMessage>(&message.content) {
+                        Ok(DocMessage::Set { key, entry }) => self.merge(key, entry),
+                        Err(err) => Err(err.into()),
+                    }
+                }
+                Ok(Event::Gossip(GossipEvent::NeighborUp(_))) => self.announce_all().await,
+                Ok(_) => Ok(()),
+                Err(err) => Err(err.into()),
+            };
+            if let Err(err) = result {
+                log::warn!("同步文档失败: {}", err);
+            }
+        }
+    }
+    async fn announce_all(&self) -> Result<()> {
+        let entries = self.state.lock().entries.clone();
+        for (key, entry) in entries {
+            self.sender
+                .broadcast_neighbors(postcard::to_stdvec(&DocMessage::Set { key, entry })?.into())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct ReplicatedDoc {
+    inner: Arc<DocInner>,
+    _task: Arc<AbortOnDropHandle<()>>,
+}
+impl ReplicatedDoc {
+    pub fn topic(&self) -> TopicId {
+        self.inner.topic
+    }
+    pub fn get(&self, key: impl AsRef<str>) -> Option<Bytes> {
+        self.inner
+            .state
+            .lock()
+            .entries
+            .get(key.as_ref())
+            .and_then(|entry| entry.value.clone())
+            .map(Bytes::from)
+    }
+    pub fn entries(&self) -> Vec<(String, Bytes)> {
+        self.inner
+            .state
+            .lock()
+            .entries
+            .iter()
+            .filter_map(|(key, entry)| Some((key.clone(), Bytes::from(entry.value.clone()?))))
+            .collect()
+    }
+    pub async fn set(&self, key: impl Into<String>, value: impl Into<Bytes>) -> Result<()> {
+        self.write(key.into(), Some(value.into())).await
+    }
+    pub async fn delete(&self, key: impl Into<String>) -> Result<()> {
+        self.write(key.into(), None).await
+    }
+    pub fn subscribe(&self) -> broadcast::Receiver<DocChange> {
+        self.inner.changes.subscribe()
+    }
+    async fn write(&self, key: String, value: Option<Bytes>) -> Result<()> {
+        let entry = {
+            let mut state = self.inner.state.lock();
+            let entry = DocEntry {
+                value: value.as_ref().map(|value| value.to_vec()),
+                timestamp: state.tick(now_micros()),
+                author: self.inner.author,
+            };
+            state.entries.insert(key.clone(), entry.clone());
+            self.inner.save(&state)?;
+            entry
+        };
+        _ = self.inner.changes.send(DocChange {
+            key: key.clone(),
+            value,
+            author: self.inner.author,
+            local: true,
+        });
+        self.inner
+            .sender
+            .broadcast(postcard::to_stdvec(&DocMessage::Set { key, entry })?.into())
+            .await?;
+        Ok(())
+    }
+}
+
+impl Starlink {
+    pub async fn open_doc(
+        &self,
+        topic: TopicId,
+        peer_node_addrs: Vec<NodeAddr>,
+    ) -> Result<ReplicatedDoc> {
+        let peer_node_ids = self.add_peer_node_addrs(peer_node_addrs)?;
+        #[cfg(not(target_family = "wasm"))]
+        let path = Some(
+            PathBuf::from(crate::CACHE_DIR)
+                .join("docs")
+                .join(format!("{}.bin", topic)),
+        );
+        #[cfg(target_family = "wasm")]
+        let path: Option<PathBuf> = None;
+        let state = match &path {
+            Some(path) if path.exists() => postcard::from_bytes(&std::fs::read(path)?)?,
+            _ => DocState::default(),
+        };
+        let (sender, receiver) = self.gossip.subscribe(topic, peer_node_ids)?.split();
+        let inner = Arc::new(DocInner {
+            topic,
+            author: self.router.endpoint().node_id(),
+            path,
+            state: Mutex::new(state),
+            sender,
+            changes: broadcast::channel(64).0,
+        });
+        let task = n0_future::task::spawn(inner.clone().run(receiver));
+        Ok(ReplicatedDoc {
+            inner,
+            _task: Arc::new(AbortOnDropHandle::new(task)),
+        })
+    }
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use iroh::SecretKey;
+
+    use super::*;
+
+    fn author(seed: u8) -> NodeId {
+        SecretKey::from_bytes(&[seed; 32]).public()
+    }
+
+    fn entry(value: &[u8], timestamp: u64, author: NodeId) -> DocEntry {
+        DocEntry {
+            value: Some(value.to_vec()),
+            timestamp,
+            author,
+        }
+    }
+
+    #[test]
+    fn newer_timestamp_wins() {
+        let mut state = DocState::default();
+        assert!(state.merge("k", &entry(b"a", 10, author(1)), 10).unwrap());
+        assert!(state.merge("k", &entry(b"b", 20, author(1)), 20).unwrap());
+        assert!(!state.merge("k", &entry(b"c", 15, author(2)), 20).unwrap());
+        assert_eq!(state.entries["k"].value.as_deref(), Some(&b"b"[..]));
+        assert_eq!(state.clock, 20);
+    }
+
+    #[test]
+    fn equal_timestamps_resolve_by_author_in_any_order() {
+        let (low, high) = if author(1) < author(2) {
+            (author(1), author(2))
+        } else {
+            (author(2), author(1))
+        };
+        let mut first = DocState::default();
+        first.merge("k", &entry(b"low", 5, low), 5).unwrap();
+        first.merge("k", &entry(b"high", 5, high), 5).unwrap();
+        let mut second = DocState::default();
+        second.merge("k", &entry(b"high", 5, high), 5).unwrap();
+        second.merge("k", &entry(b"low", 5, low), 5).unwrap();
+        assert_eq!(first.entries["k"].author, high);
+        assert_eq!(second.entries["k"].author, high);
+    }
+
+    #[test]
+    fn rejects_timestamps_far_ahead_of_local_clock() {
+        let mut state = DocState::default();
+        let now = 1_000;
+        let skew = MAX_CLOCK_SKEW.as_micros() as u64;
+        assert!(
+            state
+                .merge("k", &entry(b"a", now + skew + 1, author(1)), now)
+                .is_err()
+        );
+        assert!(
+            state
+                .merge("k", &entry(b"a", u64::MAX, author(1)), now)
+                .is_err()
+        );
+        assert!(state.entries.is_empty());
+        assert_eq!(state.clock, 0);
+        assert!(
+            state
+                .merge("k", &entry(b"a", now + skew, author(1)), now)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn clock_saturates_instead_of_overflowing() {
+        let mut state = DocState {
+            clock: u64::MAX,
+            ..Default::default()
+        };
+        assert_eq!(state.tick(0), u64::MAX);
+        let mut state = DocState::default();
+        assert_eq!(state.tick(100), 100);
+        assert_eq!(state.tick(50), 101);
+    }
+}
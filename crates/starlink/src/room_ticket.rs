@@ -0,0 +1,99 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::Result;
+use iroh::NodeAddr;
+use iroh_base::ticket::{self, Ticket};
+use iroh_gossip::proto::TopicId;
+use serde::{Deserialize, Serialize};
+
+use crate::Starlink;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoomTicket {
+    topic: TopicId,
+    nodes: Vec<NodeAddr>,
+}
+impl RoomTicket {
+    pub fn new(topic: TopicId, nodes: Vec<NodeAddr>) -> Self {
+        Self { topic, nodes }
+    }
+    pub fn topic(&self) -> TopicId {
+        self.topic
+    }
+    pub fn nodes(&self) -> &[NodeAddr] {
+        &self.nodes
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum TicketWireFormat {
+    Variant0(Variant0RoomTicket),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Variant0RoomTicket {
+    topic: TopicId,
+    nodes: Vec<NodeAddr>,
+}
+
+impl Ticket for RoomTicket {
+    const KIND: &'static str = "room";
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let data = TicketWireFormat::Variant0(Variant0RoomTicket {
+            topic: self.topic,
+            nodes: self.nodes.clone(),
+        });
+        postcard::to_stdvec(&data).expect("postcard serialization failed")
+    }
+    fn from_bytes(bytes: &[u8]) -> std::result::Result<Self, ticket::Error> {
+        let TicketWireFormat::Variant0(Variant0RoomTicket { topic, nodes }) =
+            postcard::from_bytes(bytes).map_err(ticket::Error::Postcard)?;
+        Ok(Self { topic, nodes })
+    }
+}
+impl fmt::Display for RoomTicket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Ticket::serialize(self))
+    }
+}
+impl FromStr for RoomTicket {
+    type Err = ticket::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ticket::deserialize(s)
+    }
+}
+
+impl Starlink {
+    pub async fn create_room(&self) -> Result<RoomTicket> {
+        self.room_ticket(TopicId::from_bytes(rand::random())).await
+    }
+    pub async fn room_ticket(&self, topic: TopicId) -> Result<RoomTicket> {
+        Ok(RoomTicket::new(topic, vec![self.node_addr().await?]))
+    }
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use iroh::SecretKey;
+
+    use super::*;
+
+    #[test]
+    fn round_trips_through_string() {
+        let node = NodeAddr::new(SecretKey::from_bytes(&[3; 32]).public())
+            .with_relay_url("https://relay.example.com".parse().unwrap());
+        let ticket = RoomTicket::new(TopicId::from_bytes([9; 32]), vec![node]);
+        let parsed = ticket.to_string().parse::<RoomTicket>().unwrap();
+        assert_eq!(parsed, ticket);
+        assert!(ticket.to_string().starts_with(RoomTicket::KIND));
+    }
+
+    #[test]
+    fn rejects_other_ticket_kinds() {
+        let ticket = RoomTicket::new(TopicId::from_bytes([9; 32]), vec![]);
+        let other = ticket.to_string().replacen("room", "blob", 1);
+        assert!(other.parse::<RoomTicket>().is_err());
+    }
+}
@@ -0,0 +1,593 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use iroh::NodeId;
+use iroh_blobs::{
+    Hash,
+    provider::{CustomEventSender, Event},
+    rpc::client::blobs::WrapOption,
+    ticket::BlobTicket,
+    util::SetTagOption,
+};
+use iroh_gossip::proto::TopicId;
+use n0_future::boxed::BoxFuture;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Starlink,
+    transfer_log::{TransferLog, TransferLogKind},
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShareOptions {
+    pub expires_at: Option<SystemTime>,
+    pub max_downloads: Option<u64>,
+    pub in_place: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareInfo {
+    pub hash: Hash,
+    pub created_at: SystemTime,
+    pub expires_at: Option<SystemTime>,
+    pub max_downloads: Option<u64>,
+    pub downloads: u64,
+    pub bytes_sent: u64,
+    pub revoked: bool,
+    #[serde(default)]
+    pub source: Option<PathBuf>,
+    #[serde(default)]
+    pub stale: bool,
+    #[serde(default)]
+    source_size: u64,
+    #[serde(default)]
+    source_modified: Option<SystemTime>,
+    #[serde(default)]
+    size: u64,
+}
+impl ShareInfo {
+    fn new(hash: Hash, size: u64, options: ShareOptions) -> Self {
+        Self {
+            hash,
+            created_at: SystemTime::now(),
+            expires_at: options.expires_at,
+            max_downloads: options.max_downloads,
+            downloads: 0,
+            bytes_sent: 0,
+            revoked: false,
+            source: None,
+            stale: false,
+            source_size: 0,
+            source_modified: None,
+            size,
+        }
+    }
+    pub fn is_active(&self) -> bool {
+        !self.revoked
+            && !self.stale
+            && self
+                .expires_at
+                .is_none_or(|expires_at| SystemTime::now() < expires_at)
+            && self
+                .max_downloads
+                .is_none_or(|max_downloads| self.downloads < max_downloads)
+    }
+    fn source_changed(&self) -> bool {
+        let Some(source) = &self.source else {
+            return false;
+        };
+        match std::fs::metadata(source) {
+            Ok(metadata) => {
+                metadata.len() != self.source_size
+                    || metadata.modified().ok() != self.source_modified
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ShareRequests {
+    peers: HashMap<u64, NodeId>,
+    hashes: HashMap<(u64, u64), Hash>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ShareStore {
+    path: PathBuf,
+    shares: Arc<Mutex<HashMap<Hash, ShareInfo>>>,
+    synced: Arc<Mutex<HashMap<TopicId, HashSet<Hash>>>>,
+    requests: Arc<Mutex<ShareRequests>>,
+    transfer_log: TransferLog,
+}
+impl ShareStore {
+    pub(crate) fn load(path: impl AsRef<Path>, transfer_log: TransferLog) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let shares = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            path,
+            shares: Arc::new(Mutex::new(shares)),
+            synced: Arc::new(Mutex::new(HashMap::new())),
+            requests: Arc::new(Mutex::new(ShareRequests::default())),
+            transfer_log,
+        })
+    }
+    fn save(&self, shares: &HashMap<Hash, ShareInfo>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, serde_json::to_vec(shares)?)?;
+        Ok(())
+    }
+    pub(crate) fn create(&self, hash: Hash, size: u64, options: ShareOptions) -> Result<()> {
+        let mut shares = self.shares.lock();
+        match shares.get_mut(&hash) {
+            Some(share) if !share.revoked => {
+                share.expires_at = options.expires_at;
+                share.max_downloads = options.max_downloads;
+                share.stale = false;
+                share.size = size;
+            }
+            _ => {
+                shares.insert(hash, ShareInfo::new(hash, size, options));
+            }
+        }
+        self.save(&shares)?;
+        self.transfer_log
+            .record(TransferLogKind::ShareCreated, hash, None, 0, None);
+        Ok(())
+    }
+    fn set_source(&self, hash: Hash, path: PathBuf, metadata: &std::fs::Metadata) -> Result<()> {
+        let mut shares = self.shares.lock();
+        if let Some(share) = shares.get_mut(&hash) {
+            share.source = Some(path);
+            share.source_size = metadata.len();
+            share.source_modified = metadata.modified().ok();
+        }
+        self.save(&shares)
+    }
+    fn replace(&self, old: Hash, new: Hash) -> Result<()> {
+        if old == new {
+            return Ok(());
+        }
+        let mut shares = self.shares.lock();
+        if shares.remove(&old).is_some() {
+            self.save(&shares)?;
+        }
+        Ok(())
+    }
+    pub(crate) fn shared_hashes(&self) -> Vec<Hash> {
+        self.shares
+            .lock()
+            .values()
+            .filter(|share| share.is_active())
+            .map(|share| share.hash)
+            .collect()
+    }
+    pub(crate) fn set_synced(&self, topic: TopicId, hashes: HashSet<Hash>) {
+        let mut synced = self.synced.lock();
+        if hashes.is_empty() {
+            synced.remove(&topic);
+        } else {
+            synced.insert(topic, hashes);
+        }
+    }
+    fn is_synced(&self, hash: &Hash) -> bool {
+        self.synced
+            .lock()
+            .values()
+            .any(|hashes| hashes.contains(hash))
+    }
+    pub(crate) fn is_allowed(&self, hash: &Hash) -> bool {
+        {
+            let mut shares = self.shares.lock();
+            if let Some(share) = shares.get_mut(hash) {
+                if !share.stale && share.source_changed() {
+                    share.stale = true;
+                    if let Err(err) = self.save(&shares) {
+                        log::warn!("保存分享记录失败: {}", err);
+                    }
+                } else if share.is_active() {
+                    return true;
+                }
+            }
+        }
+        self.is_synced(hash)
+    }
+    pub(crate) fn connected(&self, connection_id: u64, peer: NodeId) {
+        self.requests.lock().peers.insert(connection_id, peer);
+    }
+    pub(crate) fn disconnected(&self, connection_id: u64) {
+        let mut requests = self.requests.lock();
+        requests.peers.remove(&connection_id);
+        requests
+            .hashes
+            .retain(|(request_connection_id, _), _| *request_connection_id != connection_id);
+    }
+    fn finish_request(
+        &self,
+        connection_id: u64,
+        request_id: u64,
+    ) -> Option<(Hash, Option<NodeId>)> {
+        let mut requests = self.requests.lock();
+        let hash = requests.hashes.remove(&(connection_id, request_id))?;
+        Some((hash, requests.peers.get(&connection_id).copied()))
+    }
+    pub(crate) fn record_upload(&self, hash: Hash, peer: Option<NodeId>, bytes: u64) {
+        self.transfer_log
+            .record(TransferLogKind::Upload, hash, peer, bytes, None);
+        let mut shares = self.shares.lock();
+        if let Some(share) = shares.get_mut(&hash) {
+            if bytes >= share.size {
+                share.downloads += 1;
+            }
+            share.bytes_sent += bytes;
+            if let Err(err) = self.save(&shares) {
+                log::warn!("保存分享记录失败: {}", err);
+            }
+        }
+    }
+    fn handle_event(&self, event: Event) {
+        match event {
+            Event::GetRequestReceived {
+                connection_id,
+                request_id,
+                hash,
+            } => {
+                self.requests
+                    .lock()
+                    .hashes
+                    .insert((connection_id, request_id), hash);
+            }
+            Event::TransferCompleted {
+                connection_id,
+                request_id,
+                stats,
+            } => {
+                let Some((hash, peer)) = self.finish_request(connection_id, request_id) else {
+                    return;
+                };
+                self.record_upload(hash, peer, stats.send.total().size);
+            }
+            Event::TransferAborted {
+                connection_id,
+                request_id,
+                stats,
+            } => {
+                let Some((hash, peer)) = self.finish_request(connection_id, request_id) else {
+                    return;
+                };
+                let bytes = stats.map(|stats| stats.send.total().size).unwrap_or(0);
+                self.transfer_log
+                    .record(TransferLogKind::UploadAborted, hash, peer, bytes, None);
+                let mut shares = self.shares.lock();
+                if let Some(share) = shares.get_mut(&hash) {
+                    share.bytes_sent += bytes;
+                    if let Err(err) = self.save(&shares) {
+                        log::warn!("保存分享记录失败: {}", err);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+impl CustomEventSender for ShareStore {
+    fn send(&self, event: Event) -> BoxFuture<()> {
+        self.handle_event(event);
+        Box::pin(async {})
+    }
+    fn try_send(&self, event: Event) {
+        self.handle_event(event);
+    }
+}
+
+impl Starlink {
+    pub async fn shared_file_with_options(
+        &self,
+        path: PathBuf,
+        options: ShareOptions,
+    ) -> Result<BlobTicket> {
+        let path = std::fs::canonicalize(path)?;
+        let metadata = std::fs::metadata(&path)?;
+        let add_outcome = self
+            .blobs
+            .client()
+            .add_from_path(
+                path.clone(),
+                options.in_place,
+                SetTagOption::Auto,
+                WrapOption::NoWrap,
+            )
+            .await?
+            .await?;
+        let hash = add_outcome.hash;
+        let ticket = self.shared_outcome(add_outcome, options).await?;
+        if options.in_place {
+            self.shares.set_source(hash, path, &metadata)?;
+        }
+        Ok(ticket)
+    }
+    pub async fn shared_folder_with_options(
+        &self,
+        path: PathBuf,
+        options: ShareOptions,
+    ) -> Result<Vec<(PathBuf, BlobTicket)>> {
+        let root = std::fs::canonicalize(path)?;
+        let mut found = vec![];
+        crate::folder_sync::walk_folder(&root, &root, &[], &mut found)?;
+        let mut tickets = vec![];
+        for (_, abs_path, _, _) in found {
+            let ticket = self
+                .shared_file_with_options(abs_path.clone(), options)
+                .await?;
+            tickets.push((abs_path, ticket));
+        }
+        Ok(tickets)
+    }
+    pub async fn refresh_shares(&self) -> Result<Vec<(Hash, BlobTicket)>> {
+        let changed = self
+            .shares
+            .shares
+            .lock()
+            .values()
+            .filter(|share| !share.revoked && (share.stale || share.source_changed()))
+            .filter_map(|share| Some((share.hash, share.source.clone()?, share.clone())))
+            .collect::<Vec<_>>();
+        let mut refreshed = vec![];
+        for (hash, source, share) in changed {
+            {
+                let mut shares = self.shares.shares.lock();
+                if let Some(share) = shares.get_mut(&hash) {
+                    share.stale = true;
+                }
+                self.shares.save(&shares)?;
+            }
+            if !source.exists() {
+                continue;
+            }
+            let ticket = self
+                .shared_file_with_options(
+                    source,
+                    ShareOptions {
+                        expires_at: share.expires_at,
+                        max_downloads: share.max_downloads,
+                        in_place: true,
+                    },
+                )
+                .await?;
+            self.shares.replace(hash, ticket.hash())?;
+            refreshed.push((hash, ticket));
+        }
+        Ok(refreshed)
+    }
+    pub fn set_share_options(&self, hash: Hash, options: ShareOptions) -> Result<bool> {
+        let mut shares = self.shares.shares.lock();
+        let Some(share) = shares.get_mut(&hash) else {
+            return Ok(false);
+        };
+        share.expires_at = options.expires_at;
+        share.max_downloads = options.max_downloads;
+        self.shares.save(&shares)?;
+        Ok(true)
+    }
+    pub fn revoke_share(&self, hash: Hash) -> Result<bool> {
+        let mut shares = self.shares.shares.lock();
+        let Some(share) = shares.get_mut(&hash) else {
+            return Ok(false);
+        };
+        share.revoked = true;
+        self.shares.save(&shares)?;
+        self.transfer_log
+            .record(TransferLogKind::ShareRevoked, hash, None, 0, None);
+        Ok(true)
+    }
+    pub fn shares(&self) -> Vec<ShareInfo> {
+        self.shares.shares.lock().values().cloned().collect()
+    }
+    pub fn active_shares(&self) -> Vec<ShareInfo> {
+        self.shares
+            .shares
+            .lock()
+            .values()
+            .filter(|share| share.is_active())
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> (ShareStore, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("starlink-shares-test-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let store = ShareStore::load(
+            dir.join("shares.json"),
+            TransferLog::new(dir.join("transfer_log.jsonl")),
+        )
+        .unwrap();
+        (store, dir)
+    }
+
+    #[test]
+    fn unshared_hashes_are_not_allowed() {
+        let (store, dir) = store();
+        let hash = Hash::new(b"private");
+        assert!(!store.is_allowed(&hash));
+        store.create(hash, 2, ShareOptions::default()).unwrap();
+        assert!(store.is_allowed(&hash));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn synced_hashes_are_allowed_until_unsynced() {
+        let (store, dir) = store();
+        let hash = Hash::new(b"synced");
+        let topic = TopicId::from_bytes([1; 32]);
+        store.set_synced(topic, HashSet::from([hash]));
+        assert!(store.is_allowed(&hash));
+        store.set_synced(topic, HashSet::new());
+        assert!(!store.is_allowed(&hash));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resharing_keeps_counters() {
+        let (store, dir) = store();
+        let hash = Hash::new(b"shared");
+        store.create(hash, 10, ShareOptions::default()).unwrap();
+        store.record_upload(hash, None, 10);
+        store
+            .create(
+                hash,
+                10,
+                ShareOptions {
+                    max_downloads: Some(5),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let share = store.shares.lock()[&hash].clone();
+        assert_eq!((share.downloads, share.bytes_sent), (1, 10));
+        assert_eq!(share.max_downloads, Some(5));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resharing_revoked_content_creates_a_new_share() {
+        let (store, dir) = store();
+        let hash = Hash::new(b"shared");
+        store
+            .create(
+                hash,
+                10,
+                ShareOptions {
+                    max_downloads: Some(1),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        store.record_upload(hash, None, 10);
+        store.shares.lock().get_mut(&hash).unwrap().revoked = true;
+        assert!(!store.is_allowed(&hash));
+
+        store.create(hash, 10, ShareOptions::default()).unwrap();
+        let share = store.shares.lock()[&hash].clone();
+        assert!(!share.revoked);
+        assert_eq!((share.downloads, share.bytes_sent), (0, 0));
+        assert_eq!(share.max_downloads, None);
+        assert!(store.is_allowed(&hash));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn range_reads_do_not_consume_download_quota() {
+        let (store, dir) = store();
+        let hash = Hash::new(b"large");
+        store
+            .create(
+                hash,
+                1000,
+                ShareOptions {
+                    max_downloads: Some(1),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        for _ in 0..5 {
+            store.record_upload(hash, None, 100);
+        }
+        let share = store.shares.lock()[&hash].clone();
+        assert_eq!((share.downloads, share.bytes_sent), (0, 500));
+        assert!(store.is_allowed(&hash));
+
+        store.record_upload(hash, None, 1008);
+        assert_eq!(store.shares.lock()[&hash].downloads, 1);
+        assert!(!store.is_allowed(&hash));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replaced_stale_share_is_removed() {
+        let (store, dir) = store();
+        let (old, new) = (Hash::new(b"v1"), Hash::new(b"v2"));
+        store.create(old, 2, ShareOptions::default()).unwrap();
+        store.shares.lock().get_mut(&old).unwrap().stale = true;
+        store.create(new, 2, ShareOptions::default()).unwrap();
+        store.replace(old, new).unwrap();
+        assert!(!store.shares.lock().contains_key(&old));
+        assert_eq!(store.shared_hashes(), vec![new]);
+
+        store.shares.lock().get_mut(&new).unwrap().stale = true;
+        store.create(new, 2, ShareOptions::default()).unwrap();
+        store.replace(new, new).unwrap();
+        assert!(store.is_allowed(&new));
+        let reloaded = ShareStore::load(&store.path, store.transfer_log.clone()).unwrap();
+        assert_eq!(reloaded.shares.lock().len(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn download_limit_and_expiry_deactivate_shares() {
+        let (store, dir) = store();
+        let limited = Hash::new(b"limited");
+        store
+            .create(
+                limited,
+                1,
+                ShareOptions {
+                    max_downloads: Some(1),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(store.is_allowed(&limited));
+        store.record_upload(limited, None, 1);
+        assert!(!store.is_allowed(&limited));
+
+        let expired = Hash::new(b"expired");
+        store
+            .create(
+                expired,
+                1,
+                ShareOptions {
+                    expires_at: Some(SystemTime::now() - std::time::Duration::from_secs(1)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(!store.is_allowed(&expired));
+        assert!(store.shared_hashes().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn changed_source_marks_share_stale() {
+        let (store, dir) = store();
+        let source = dir.join("source.txt");
+        std::fs::write(&source, b"v1").unwrap();
+        let hash = Hash::new(b"v1");
+        store.create(hash, 2, ShareOptions::default()).unwrap();
+        store
+            .set_source(hash, source.clone(), &std::fs::metadata(&source).unwrap())
+            .unwrap();
+        assert!(store.is_allowed(&hash));
+        std::fs::write(&source, b"v2 longer").unwrap();
+        assert!(!store.is_allowed(&hash));
+        assert!(store.shares.lock()[&hash].stale);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
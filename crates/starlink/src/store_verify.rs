@@ -0,0 +1,393 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow, bail};
+use iroh::{Endpoint, NodeAddr};
+use iroh_blobs::{
+    BlobFormat, Hash,
+    store::{ExportMode, ImportMode, MapEntry, ReadableStore, Store as BaoStore, fs::Store},
+    ticket::BlobTicket,
+    util::progress::IgnoreProgressSender,
+};
+use iroh_io::AsyncSliceReader;
+use n0_future::{Stream, StreamExt};
+use tokio::sync::mpsc;
+
+use crate::{
+    CACHE_DIR, Starlink,
+    transfer_limits::{self, TransferPriority, TransferScheduler},
+};
+
+const VERIFY_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlobStatus {
+    Valid,
+    Corrupt,
+    Incomplete { size: u64, expected_size: u64 },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub checked: usize,
+    pub corrupt: Vec<Hash>,
+    pub incomplete: Vec<Hash>,
+    pub repaired: Vec<Hash>,
+    pub unrepaired: Vec<Hash>,
+}
+
+#[derive(Debug, Clone)]
+pub enum VerifyProgress {
+    Started { total: usize },
+    Checked { hash: Hash, status: BlobStatus },
+    Repaired { hash: Hash },
+    RepairFailed { hash: Hash, error: String },
+    Done(VerifyReport),
+}
+
+type VerifySender = mpsc::UnboundedSender<Result<VerifyProgress>>;
+
+impl Starlink {
+    pub fn verify_store(
+        &self,
+        repair: bool,
+    ) -> impl Stream<Item = Result<VerifyProgress>> + Send + Unpin + 'static {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        n0_future::task::spawn({
+            let starlink = self.clone();
+            async move {
+                if let Err(err) = starlink.verify_store_inner(repair, &sender).await {
+                    _ = sender.send(Err(err));
+                }
+            }
+        });
+        Box::pin(n0_future::stream::unfold(
+            receiver,
+            |mut receiver| async move { receiver.recv().await.map(|item| (item, receiver)) },
+        ))
+    }
+    async fn verify_store_inner(&self, repair: bool, sender: &VerifySender) -> Result<()> {
+        let mut blobs = vec![];
+        let mut blob_stream = self.blobs.client().list().await?;
+        while let Some(blob) = blob_stream.next().await {
+            blobs.push(blob?.hash);
+        }
+        let mut incomplete = vec![];
+        let mut incomplete_stream = self.blobs.client().list_incomplete().await?;
+        while let Some(blob) = incomplete_stream.next().await {
+            incomplete.push(blob?);
+        }
+        _ = sender.send(Ok(VerifyProgress::Started {
+            total: blobs.len() + incomplete.len(),
+        }));
+        let mut report = VerifyReport::default();
+        for hash in blobs {
+            let status = if self.rehash_blob(hash).await.unwrap_or(false) {
+                BlobStatus::Valid
+            } else {
+                report.corrupt.push(hash);
+                BlobStatus::Corrupt
+            };
+            report.checked += 1;
+            _ = sender.send(Ok(VerifyProgress::Checked { hash, status }));
+        }
+        for blob in incomplete {
+            report.checked += 1;
+            report.incomplete.push(blob.hash);
+            _ = sender.send(Ok(VerifyProgress::Checked {
+                hash: blob.hash,
+                status: BlobStatus::Incomplete {
+                    size: blob.size,
+                    expected_size: blob.expected_size,
+                },
+            }));
+        }
+        if repair {
+            for hash in report.corrupt.iter().chain(&report.incomplete) {
+                let corrupt = report.corrupt.contains(hash);
+                match self.repair_blob(*hash, corrupt).await {
+                    Ok(()) => {
+                        report.repaired.push(*hash);
+                        _ = sender.send(Ok(VerifyProgress::Repaired { hash: *hash }));
+                    }
+                    Err(err) => {
+                        report.unrepaired.push(*hash);
+                        _ = sender.send(Ok(VerifyProgress::RepairFailed {
+                            hash: *hash,
+                            error: err.to_string(),
+                        }));
+                    }
+                }
+            }
+        }
+        _ = sender.send(Ok(VerifyProgress::Done(report)));
+        Ok(())
+    }
+    async fn rehash_blob(&self, hash: Hash) -> Result<bool> {
+        let starlink = self.clone();
+        self.blobs
+            .rt()
+            .try_spawn(move || async move { rehash(starlink.blobs.store(), hash).await })?
+            .await?
+    }
+    async fn repair_blob(&self, hash: Hash, corrupt: bool) -> Result<()> {
+        let starlink = self.clone();
+        self.blobs
+            .rt()
+            .try_spawn(move || async move {
+                repair(
+                    starlink.blobs.store(),
+                    &starlink.transfers,
+                    starlink.router.endpoint(),
+                    starlink.blob_meta.providers(hash),
+                    &PathBuf::from(CACHE_DIR).join(format!("repair-{}", hash.to_hex())),
+                    hash,
+                    corrupt,
+                )
+                .await
+            })?
+            .await?
+    }
+}
+
+async fn rehash<S: BaoStore>(store: &S, hash: Hash) -> Result<bool> {
+    let Some(entry) = store.get(&hash).await? else {
+        bail!("数据不存在");
+    };
+    let mut reader = entry.data_reader().await?;
+    let size = reader.size().await?;
+    let mut hasher = blake3::Hasher::new();
+    let mut offset = 0;
+    while offset < size {
+        let bytes = reader.read_at(offset, VERIFY_BUFFER_SIZE).await?;
+        if bytes.is_empty() {
+            break;
+        }
+        hasher.update(&bytes);
+        offset += bytes.len() as u64;
+    }
+    Ok(hasher.finalize().as_bytes() == hash.as_bytes())
+}
+
+async fn fetch_ticket<S: BaoStore>(
+    store: &S,
+    transfers: &TransferScheduler,
+    endpoint: &Endpoint,
+    ticket: &BlobTicket,
+) -> Result<()> {
+    let (sender, _receiver) = mpsc::unbounded_channel();
+    transfer_limits::fetch(
+        store,
+        transfers,
+        endpoint,
+        ticket,
+        TransferPriority::Normal,
+        &sender,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn replace_corrupt<S: BaoStore>(
+    store: &S,
+    transfers: &TransferScheduler,
+    endpoint: &Endpoint,
+    ticket: &BlobTicket,
+    staging_dir: &Path,
+) -> Result<()> {
+    let hash = ticket.hash();
+    let staging = Store::load(staging_dir).await?;
+    let result = async {
+        fetch_ticket(&staging, transfers, endpoint, ticket).await?;
+        if rehash(store, hash).await.unwrap_or(false) {
+            return Ok(());
+        }
+        let staged_path = staging_dir.join("repaired.data");
+        staging
+            .export(
+                hash,
+                staged_path.clone(),
+                ExportMode::Copy,
+                Box::new(|_| Ok(())),
+            )
+            .await?;
+        store.delete(vec![hash]).await?;
+        store
+            .import_file(
+                staged_path,
+                ImportMode::Copy,
+                BlobFormat::Raw,
+                IgnoreProgressSender::default(),
+            )
+            .await?;
+        anyhow::Ok(())
+    }
+    .await;
+    staging.shutdown().await;
+    drop(staging);
+    if let Err(err) = std::fs::remove_dir_all(staging_dir) {
+        log::warn!("清理修复目录失败: {}", err);
+    }
+    result
+}
+
+async fn repair<S: BaoStore>(
+    store: &S,
+    transfers: &TransferScheduler,
+    endpoint: &Endpoint,
+    providers: Vec<NodeAddr>,
+    staging_dir: &Path,
+    hash: Hash,
+    corrupt: bool,
+) -> Result<()> {
+    if providers.is_empty() {
+        bail!("没有已知的提供者");
+    }
+    let mut last_error = None;
+    for provider in providers {
+        let ticket = BlobTicket::new(provider, hash, BlobFormat::Raw)?;
+        let result = if corrupt {
+            replace_corrupt(store, transfers, endpoint, &ticket, staging_dir).await
+        } else {
+            fetch_ticket(store, transfers, endpoint, &ticket).await
+        };
+        match result {
+            Ok(()) => return Ok(()),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow!("修复失败")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom, Write};
+
+    use bytes::Bytes;
+    use iroh::{RelayMode, SecretKey, protocol::Router};
+    use iroh_blobs::{
+        HashAndFormat,
+        net_protocol::Blobs,
+        store::{EntryStatus, Map, MapMut},
+    };
+
+    use super::*;
+
+    fn test_data(len: usize) -> Bytes {
+        (0..len).map(|i| (i % 251) as u8).collect::<Vec<_>>().into()
+    }
+
+    async fn endpoint() -> Endpoint {
+        Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap()
+    }
+
+    async fn local_store(data: Bytes) -> (Store, Hash, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("starlink-verify-test-{}", rand::random::<u64>()));
+        let store = Store::load(&dir).await.unwrap();
+        let tag = store.import_bytes(data, BlobFormat::Raw).await.unwrap();
+        let hash = *tag.hash();
+        store.create_tag(HashAndFormat::raw(hash)).await.unwrap();
+        (store, hash, dir)
+    }
+
+    fn corrupt(dir: &Path, hash: Hash) -> PathBuf {
+        let path = dir.join("data").join(format!("{}.data", hash.to_hex()));
+        let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(4096)).unwrap();
+        file.write_all(&[0xff; 64]).unwrap();
+        file.sync_all().unwrap();
+        path
+    }
+
+    async fn read_complete(store: &Store, hash: Hash) -> Bytes {
+        let entry = store.get(&hash).await.unwrap().unwrap();
+        let mut reader = MapEntry::data_reader(&entry).await.unwrap();
+        let size = reader.size().await.unwrap();
+        reader.read_at(0, size as usize).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn verify_detects_corruption() {
+        let (store, hash, dir) = local_store(test_data(100 * 1024)).await;
+        assert!(rehash(&store, hash).await.unwrap());
+
+        corrupt(&dir, hash);
+        assert!(!rehash(&store, hash).await.unwrap());
+
+        store.shutdown().await;
+        drop(store);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn repair_keeps_data_on_fetch_failure() {
+        let (store, hash, dir) = local_store(test_data(100 * 1024)).await;
+        let data_path = corrupt(&dir, hash);
+        let corrupted = std::fs::read(&data_path).unwrap();
+        let endpoint = endpoint().await;
+        let unreachable = NodeAddr::new(SecretKey::from_bytes(&[7; 32]).public());
+
+        let result = repair(
+            &store,
+            &TransferScheduler::default(),
+            &endpoint,
+            vec![unreachable],
+            &dir.join("staging"),
+            hash,
+            true,
+        )
+        .await;
+        assert!(result.is_err());
+        assert!(matches!(
+            store.entry_status(&hash).await.unwrap(),
+            EntryStatus::Complete
+        ));
+        assert_eq!(std::fs::read(&data_path).unwrap(), corrupted);
+        assert!(!dir.join("staging").exists());
+
+        endpoint.close().await;
+        store.shutdown().await;
+        drop(store);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn repair_replaces_corrupt_blob_from_provider() {
+        let data = test_data(100 * 1024);
+        let (store, hash, dir) = local_store(data.clone()).await;
+        corrupt(&dir, hash);
+        let provider_endpoint = endpoint().await;
+        let blobs = Blobs::memory().build(&provider_endpoint);
+        let router = Router::builder(provider_endpoint)
+            .accept(iroh_blobs::ALPN, blobs.clone())
+            .spawn();
+        blobs.client().add_bytes(data.clone()).await.unwrap();
+        let node_addr = router.endpoint().node_addr().await.unwrap();
+        let endpoint = endpoint().await;
+
+        repair(
+            &store,
+            &TransferScheduler::default(),
+            &endpoint,
+            vec![node_addr],
+            &dir.join("staging"),
+            hash,
+            true,
+        )
+        .await
+        .unwrap();
+        assert!(rehash(&store, hash).await.unwrap());
+        assert_eq!(read_complete(&store, hash).await, data);
+        assert!(!dir.join("staging").exists());
+
+        endpoint.close().await;
+        router.shutdown().await.unwrap();
+        store.shutdown().await;
+        drop(store);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,1135 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap},
+    io,
+    num::NonZeroU64,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use anyhow::{Result, bail};
+use bao_tree::{ChunkRanges, io::BaoContentItem};
+use bytes::Bytes;
+use iroh::{Endpoint, NodeAddr, NodeId, endpoint::Connection, protocol::ProtocolHandler};
+use iroh_blobs::{
+    BlobFormat, Hash, HashAndFormat,
+    get::{
+        Stats,
+        db::{BlobId, BlobInfo, DownloadProgress as BytesDownloadProgress, blob_info},
+        fsm::{self, AtBlobHeader, AtEndBlob, BlobContentNext, ConnectedNext, EndBlobNext},
+    },
+    hashseq::HashSeq,
+    net_protocol::Blobs,
+    protocol::{GetRequest, RangeSpecSeq},
+    provider::{CustomEventSender, Event, EventSender},
+    rpc::client::blobs::DownloadProgress,
+    store::{BaoBatchWriter, Map, MapEntry, MapEntryMut, Store as BaoStore, fs::Store},
+    ticket::BlobTicket,
+};
+use iroh_io::{AsyncSliceReader, AsyncSliceReaderExt};
+use n0_future::{
+    boxed::BoxFuture,
+    time::{Instant, sleep},
+};
+use parking_lot::Mutex;
+use tokio::sync::{OwnedMutexGuard, mpsc, oneshot};
+
+use crate::{Starlink, shares::ShareStore, transfer_log::TransferLogKind};
+
+const PEER_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransferLimits {
+    pub max_concurrent_transfers: Option<usize>,
+    pub download_rate: Option<u64>,
+    pub upload_rate: Option<u64>,
+    pub peer_download_rate: Option<u64>,
+    pub peer_upload_rate: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TransferPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransferStats {
+    pub active_transfers: usize,
+    pub queued_transfers: usize,
+    pub downloaded: u64,
+    pub uploaded: u64,
+    pub download_throttled: Duration,
+    pub upload_throttled: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum TransferDirection {
+    Download,
+    Upload,
+}
+
+#[derive(Default)]
+struct RateLimiter {
+    available: f64,
+    updated: Option<Instant>,
+}
+impl RateLimiter {
+    fn is_idle(&self, now: Instant) -> bool {
+        self.updated
+            .is_none_or(|updated| now.duration_since(updated) >= PEER_IDLE_TIMEOUT)
+    }
+    fn reserve(&mut self, rate: Option<u64>, bytes: u64, now: Instant) -> Duration {
+        let Some(rate) = rate.filter(|rate| *rate > 0).map(|rate| rate as f64) else {
+            self.updated = None;
+            return Duration::ZERO;
+        };
+        let available = match self.updated {
+            Some(updated) => {
+                (self.available + now.duration_since(updated).as_secs_f64() * rate).min(rate)
+            }
+            None => rate,
+        };
+        self.available = available - bytes as f64;
+        self.updated = Some(now);
+        if self.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.available / rate)
+        }
+    }
+}
+
+struct QueuedTransfer {
+    priority: TransferPriority,
+    seq: u64,
+    sender: oneshot::Sender<TransferPermit>,
+}
+impl PartialEq for QueuedTransfer {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for QueuedTransfer {}
+impl PartialOrd for QueuedTransfer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for QueuedTransfer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.priority, Reverse(self.seq)).cmp(&(other.priority, Reverse(other.seq)))
+    }
+}
+
+#[derive(Default)]
+struct TransferSlots {
+    active: usize,
+    queue: BinaryHeap<QueuedTransfer>,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    limits: TransferLimits,
+    download: RateLimiter,
+    upload: RateLimiter,
+    peers: HashMap<(NodeId, TransferDirection), RateLimiter>,
+    peers_evicted: Option<Instant>,
+    download_slots: TransferSlots,
+    upload_slots: TransferSlots,
+    next_seq: u64,
+    stats: TransferStats,
+}
+impl SchedulerState {
+    fn slots(&mut self, direction: TransferDirection) -> &mut TransferSlots {
+        match direction {
+            TransferDirection::Download => &mut self.download_slots,
+            TransferDirection::Upload => &mut self.upload_slots,
+        }
+    }
+    fn update_slot_stats(&mut self) {
+        self.stats.active_transfers = self.download_slots.active + self.upload_slots.active;
+        self.stats.queued_transfers =
+            self.download_slots.queue.len() + self.upload_slots.queue.len();
+    }
+    fn evict_idle_peers(&mut self, now: Instant) {
+        if self
+            .peers_evicted
+            .is_some_and(|evicted| now.duration_since(evicted) < PEER_IDLE_TIMEOUT)
+        {
+            return;
+        }
+        self.peers_evicted = Some(now);
+        self.peers.retain(|_, limiter| !limiter.is_idle(now));
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct TransferScheduler {
+    state: Arc<Mutex<SchedulerState>>,
+    fetches: Arc<Mutex<HashMap<Hash, Weak<tokio::sync::Mutex<()>>>>>,
+}
+impl std::fmt::Debug for TransferScheduler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransferScheduler").finish_non_exhaustive()
+    }
+}
+impl TransferScheduler {
+    fn limits(&self) -> TransferLimits {
+        self.state.lock().limits
+    }
+    fn set_limits(&self, limits: TransferLimits) {
+        self.state.lock().limits = limits;
+        self.dispatch(TransferDirection::Download);
+        self.dispatch(TransferDirection::Upload);
+    }
+    fn stats(&self) -> TransferStats {
+        self.state.lock().stats
+    }
+    pub(crate) async fn acquire(
+        &self,
+        direction: TransferDirection,
+        priority: TransferPriority,
+    ) -> Result<TransferPermit> {
+        let receiver = {
+            let mut state = self.state.lock();
+            let (sender, receiver) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.slots(direction).queue.push(QueuedTransfer {
+                priority,
+                seq,
+                sender,
+            });
+            state.update_slot_stats();
+            receiver
+        };
+        self.dispatch(direction);
+        Ok(receiver.await?)
+    }
+    async fn lock_fetch(&self, hash: Hash) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut fetches = self.fetches.lock();
+            fetches.retain(|_, lock| lock.strong_count() > 0);
+            match fetches.get(&hash).and_then(Weak::upgrade) {
+                Some(lock) => lock,
+                None => {
+                    let lock = Arc::new(tokio::sync::Mutex::new(()));
+                    fetches.insert(hash, Arc::downgrade(&lock));
+                    lock
+                }
+            }
+        };
+        lock.lock_owned().await
+    }
+    fn dispatch(&self, direction: TransferDirection) {
+        loop {
+            let sender = {
+                let mut state = self.state.lock();
+                let max = state.limits.max_concurrent_transfers;
+                let slots = state.slots(direction);
+                if max.is_some_and(|max| slots.active >= max) {
+                    return;
+                }
+                let Some(queued) = slots.queue.pop() else {
+                    return;
+                };
+                slots.active += 1;
+                state.update_slot_stats();
+                queued.sender
+            };
+            _ = sender.send(TransferPermit {
+                scheduler: self.clone(),
+                direction,
+            });
+        }
+    }
+    pub(crate) async fn throttle(&self, direction: TransferDirection, peer: NodeId, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock();
+            let now = Instant::now();
+            state.evict_idle_peers(now);
+            let limits = state.limits;
+            let (rate, peer_rate) = match direction {
+                TransferDirection::Download => (limits.download_rate, limits.peer_download_rate),
+                TransferDirection::Upload => (limits.upload_rate, limits.peer_upload_rate),
+            };
+            let limiter = match direction {
+                TransferDirection::Download => &mut state.download,
+                TransferDirection::Upload => &mut state.upload,
+            };
+            let wait = limiter.reserve(rate, bytes, now);
+            let wait = wait.max(
+                state
+                    .peers
+                    .entry((peer, direction))
+                    .or_default()
+                    .reserve(peer_rate, bytes, now),
+            );
+            match direction {
+                TransferDirection::Download => {
+                    state.stats.downloaded += bytes;
+                    state.stats.download_throttled += wait;
+                }
+                TransferDirection::Upload => {
+                    state.stats.uploaded += bytes;
+                    state.stats.upload_throttled += wait;
+                }
+            }
+            wait
+        };
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+pub(crate) struct TransferPermit {
+    scheduler: TransferScheduler,
+    direction: TransferDirection,
+}
+impl Drop for TransferPermit {
+    fn drop(&mut self) {
+        {
+            let mut state = self.scheduler.state.lock();
+            state.slots(self.direction).active -= 1;
+            state.update_slot_stats();
+        }
+        self.scheduler.dispatch(self.direction);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ThrottledBlobs {
+    blobs: Blobs<Store>,
+    scheduler: TransferScheduler,
+    shares: ShareStore,
+}
+impl ThrottledBlobs {
+    pub(crate) fn new(
+        blobs: Blobs<Store>,
+        scheduler: TransferScheduler,
+        shares: ShareStore,
+    ) -> Self {
+        Self {
+            blobs,
+            scheduler,
+            shares,
+        }
+    }
+}
+impl ProtocolHandler for ThrottledBlobs {
+    fn accept(&self, connection: Connection) -> BoxFuture<Result<()>> {
+        let blobs = self.blobs.clone();
+        let scheduler = self.scheduler.clone();
+        let shares = self.shares.clone();
+        Box::pin(async move {
+            let connection_id = connection.stable_id() as u64;
+            let peer = connection.remote_node_id()?;
+            let events = ThrottledEvents {
+                events: blobs.events().clone(),
+                scheduler: scheduler.clone(),
+                permits: Default::default(),
+            };
+            let map = ThrottledMap {
+                store: blobs.store().clone(),
+                scheduler,
+                shares: shares.clone(),
+                peer,
+            };
+            shares.connected(connection_id, peer);
+            iroh_blobs::provider::handle_connection(
+                connection,
+                map,
+                events.into(),
+                blobs.rt().clone(),
+            )
+            .await;
+            shares.disconnected(connection_id);
+            Ok(())
+        })
+    }
+    fn shutdown(&self) -> BoxFuture<()> {
+        self.blobs.shutdown()
+    }
+}
+
+#[derive(Clone)]
+struct ThrottledEvents {
+    events: EventSender,
+    scheduler: TransferScheduler,
+    permits: Arc<Mutex<HashMap<u64, TransferPermit>>>,
+}
+impl std::fmt::Debug for ThrottledEvents {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ThrottledEvents").finish_non_exhaustive()
+    }
+}
+impl ThrottledEvents {
+    fn release(&self, event: &Event) {
+        if let Event::TransferCompleted { request_id, .. }
+        | Event::TransferAborted { request_id, .. } = event
+        {
+            let permit = self.permits.lock().remove(request_id);
+            drop(permit);
+        }
+    }
+}
+impl CustomEventSender for ThrottledEvents {
+    fn send(&self, event: Event) -> BoxFuture<()> {
+        let events = self.clone();
+        Box::pin(async move {
+            if let Event::GetRequestReceived { request_id, .. } = event {
+                match events
+                    .scheduler
+                    .acquire(TransferDirection::Upload, TransferPriority::Normal)
+                    .await
+                {
+                    Ok(permit) => {
+                        events.permits.lock().insert(request_id, permit);
+                    }
+                    Err(err) => log::warn!("获取上传名额失败: {}", err),
+                }
+            }
+            events.release(&event);
+            events.events.send(|| event).await;
+        })
+    }
+    fn try_send(&self, event: Event) {
+        self.release(&event);
+        self.events.try_send(|| event);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ThrottledMap {
+    store: Store,
+    scheduler: TransferScheduler,
+    shares: ShareStore,
+    peer: NodeId,
+}
+impl Map for ThrottledMap {
+    type Entry = ThrottledEntry;
+
+    async fn get(&self, hash: &Hash) -> io::Result<Option<Self::Entry>> {
+        if !self.shares.is_allowed(hash) {
+            return Ok(None);
+        }
+        Ok(self.store.get(hash).await?.map(|entry| ThrottledEntry {
+            entry,
+            scheduler: self.scheduler.clone(),
+            peer: self.peer,
+        }))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ThrottledEntry {
+    entry: <Store as Map>::Entry,
+    scheduler: TransferScheduler,
+    peer: NodeId,
+}
+impl MapEntry for ThrottledEntry {
+    fn hash(&self) -> Hash {
+        self.entry.hash()
+    }
+    fn size(&self) -> iroh_blobs::store::BaoBlobSize {
+        self.entry.size()
+    }
+    fn is_complete(&self) -> bool {
+        self.entry.is_complete()
+    }
+    async fn outboard(&self) -> io::Result<impl bao_tree::io::fsm::Outboard> {
+        MapEntry::outboard(&self.entry).await
+    }
+    async fn data_reader(&self) -> io::Result<impl AsyncSliceReader> {
+        Ok(ThrottledReader {
+            reader: MapEntry::data_reader(&self.entry).await?,
+            scheduler: self.scheduler.clone(),
+            peer: self.peer,
+        })
+    }
+}
+
+struct ThrottledReader<R> {
+    reader: R,
+    scheduler: TransferScheduler,
+    peer: NodeId,
+}
+impl<R: AsyncSliceReader> AsyncSliceReader for ThrottledReader<R> {
+    async fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Bytes> {
+        let bytes = self.reader.read_at(offset, len).await?;
+        self.scheduler
+            .throttle(TransferDirection::Upload, self.peer, bytes.len() as u64)
+            .await;
+        Ok(bytes)
+    }
+    async fn size(&mut self) -> io::Result<u64> {
+        self.reader.size().await
+    }
+}
+
+type ProgressSender = mpsc::UnboundedSender<Result<BytesDownloadProgress>>;
+
+async fn missing_ranges<S: BaoStore>(store: &S, hash: Hash) -> Result<ChunkRanges> {
+    let info = blob_info(store, &hash).await?;
+    let missing = info.missing_ranges();
+    Ok(match info {
+        BlobInfo::Partial { .. } if missing.is_empty() => ChunkRanges::all(),
+        _ => missing,
+    })
+}
+
+pub(crate) async fn fetch<S: BaoStore>(
+    store: &S,
+    transfers: &TransferScheduler,
+    endpoint: &Endpoint,
+    ticket: &BlobTicket,
+    priority: TransferPriority,
+    sender: &ProgressSender,
+) -> Result<u64> {
+    let _fetch = transfers.lock_fetch(ticket.hash()).await;
+    let _permit = transfers
+        .acquire(TransferDirection::Download, priority)
+        .await?;
+    match ticket.format() {
+        BlobFormat::Raw => {
+            fetch_blob(
+                store,
+                transfers,
+                endpoint,
+                ticket.hash(),
+                ticket.node_addr().clone(),
+                sender,
+            )
+            .await
+        }
+        BlobFormat::HashSeq => {
+            fetch_hash_seq(
+                store,
+                transfers,
+                endpoint,
+                ticket.hash(),
+                ticket.node_addr().clone(),
+                sender,
+            )
+            .await
+        }
+    }
+}
+
+async fn fetch_blob<S: BaoStore>(
+    store: &S,
+    transfers: &TransferScheduler,
+    endpoint: &Endpoint,
+    hash: Hash,
+    node_addr: NodeAddr,
+    sender: &ProgressSender,
+) -> Result<u64> {
+    let missing = missing_ranges(store, hash).await?;
+    if missing.is_empty() {
+        _ = sender.send(Ok(BytesDownloadProgress::AllDone(Default::default())));
+        return Ok(0);
+    }
+    let peer = node_addr.node_id;
+    let connection = endpoint.connect(node_addr, iroh_blobs::ALPN).await?;
+    _ = sender.send(Ok(BytesDownloadProgress::Connected));
+    let request = GetRequest::new(hash, RangeSpecSeq::from_ranges([missing]));
+    let ConnectedNext::StartRoot(start_root) =
+        fsm::start(connection, request).next().await?.next().await?
+    else {
+        bail!("意外的响应");
+    };
+    let end_blob = receive_blob(
+        store,
+        transfers,
+        start_root.next(),
+        BlobId::Root,
+        peer,
+        sender,
+    )
+    .await?;
+    let EndBlobNext::Closing(closing) = end_blob.next() else {
+        bail!("意外的响应");
+    };
+    let stats = closing.next().await?;
+    store.create_tag(HashAndFormat::raw(hash)).await?;
+    let bytes_read = stats.bytes_read;
+    _ = sender.send(Ok(BytesDownloadProgress::AllDone(stats)));
+    Ok(bytes_read)
+}
+
+async fn fetch_hash_seq<S: BaoStore>(
+    store: &S,
+    transfers: &TransferScheduler,
+    endpoint: &Endpoint,
+    hash: Hash,
+    node_addr: NodeAddr,
+    sender: &ProgressSender,
+) -> Result<u64> {
+    let peer = node_addr.node_id;
+    let connection = endpoint.connect(node_addr, iroh_blobs::ALPN).await?;
+    _ = sender.send(Ok(BytesDownloadProgress::Connected));
+    let mut stats = Stats::default();
+    let root_missing = missing_ranges(store, hash).await?;
+    if !root_missing.is_empty() {
+        let request = GetRequest::new(hash, RangeSpecSeq::from_ranges([root_missing]));
+        let ConnectedNext::StartRoot(start_root) = fsm::start(connection.clone(), request)
+            .next()
+            .await?
+            .next()
+            .await?
+        else {
+            bail!("意外的响应");
+        };
+        let end_blob = receive_blob(
+            store,
+            transfers,
+            start_root.next(),
+            BlobId::Root,
+            peer,
+            sender,
+        )
+        .await?;
+        let EndBlobNext::Closing(closing) = end_blob.next() else {
+            bail!("意外的响应");
+        };
+        stats.bytes_read += closing.next().await?.bytes_read;
+    }
+    let Some(root) = store.get(&hash).await? else {
+        bail!("哈希序列不存在");
+    };
+    let children = HashSeq::try_from(root.data_reader().await?.read_to_end().await?)?
+        .into_iter()
+        .collect::<Vec<_>>();
+    let mut ranges = vec![ChunkRanges::empty()];
+    for child in &children {
+        ranges.push(missing_ranges(store, *child).await?);
+    }
+    let request = GetRequest::new(hash, RangeSpecSeq::from_ranges(ranges));
+    let mut next = match fsm::start(connection, request).next().await?.next().await? {
+        ConnectedNext::StartChild(start_child) => EndBlobNext::MoreChildren(start_child),
+        ConnectedNext::Closing(closing) => EndBlobNext::Closing(closing),
+        ConnectedNext::StartRoot(_) => bail!("意外的响应"),
+    };
+    let closing = loop {
+        match next {
+            EndBlobNext::MoreChildren(start_child) => {
+                let offset = start_child.child_offset();
+                let Some(child) = children.get(offset as usize) else {
+                    break start_child.finish();
+                };
+                let id = BlobId::Child(NonZeroU64::MIN.saturating_add(offset));
+                next = receive_blob(store, transfers, start_child.next(*child), id, peer, sender)
+                    .await?
+                    .next();
+            }
+            EndBlobNext::Closing(closing) => break closing,
+        }
+    };
+    let child_stats = closing.next().await?;
+    stats.bytes_read += child_stats.bytes_read;
+    stats.bytes_written += child_stats.bytes_written;
+    stats.elapsed += child_stats.elapsed;
+    store.create_tag(HashAndFormat::hash_seq(hash)).await?;
+    let bytes_read = stats.bytes_read;
+    _ = sender.send(Ok(BytesDownloadProgress::AllDone(stats)));
+    Ok(bytes_read)
+}
+
+async fn receive_blob<S: BaoStore>(
+    store: &S,
+    transfers: &TransferScheduler,
+    header: AtBlobHeader,
+    child: BlobId,
+    peer: NodeId,
+    sender: &ProgressSender,
+) -> Result<AtEndBlob> {
+    let hash = header.hash();
+    let id = u64::from(child);
+    let (mut content, size) = header.next().await?;
+    let entry = store.get_or_create(hash, size).await?;
+    let mut writer = entry.batch_writer().await?;
+    _ = sender.send(Ok(BytesDownloadProgress::Found {
+        id,
+        child,
+        hash,
+        size,
+    }));
+    let mut batch = vec![];
+    let end_blob = loop {
+        match content.next().await {
+            BlobContentNext::More((next, item)) => {
+                let item = item?;
+                let leaf_end = match &item {
+                    BaoContentItem::Leaf(leaf) => {
+                        Some((leaf.offset + leaf.data.len() as u64, leaf.data.len()))
+                    }
+                    BaoContentItem::Parent(_) => None,
+                };
+                batch.push(item);
+                if let Some((offset, len)) = leaf_end {
+                    writer.write_batch(size, std::mem::take(&mut batch)).await?;
+                    if sender.is_closed() {
+                        bail!("下载已取消");
+                    }
+                    _ = sender.send(Ok(BytesDownloadProgress::Progress { id, offset }));
+                    transfers
+                        .throttle(TransferDirection::Download, peer, len as u64)
+                        .await;
+                }
+                content = next;
+            }
+            BlobContentNext::Done(end_blob) => break end_blob,
+        }
+    };
+    if !batch.is_empty() {
+        writer.write_batch(size, batch).await?;
+    }
+    writer.sync().await?;
+    drop(writer);
+    store.insert_complete(entry).await?;
+    _ = sender.send(Ok(BytesDownloadProgress::Done { id }));
+    Ok(end_blob)
+}
+
+impl Starlink {
+    pub fn transfer_limits(&self) -> TransferLimits {
+        self.transfers.limits()
+    }
+    pub fn set_transfer_limits(&self, limits: TransferLimits) {
+        self.transfers.set_limits(limits);
+    }
+    pub fn transfer_stats(&self) -> TransferStats {
+        self.transfers.stats()
+    }
+    pub async fn download_file_with_priority(
+        &self,
+        ticket: BlobTicket,
+        priority: TransferPriority,
+    ) -> Result<DownloadProgress> {
+        self.blob_meta.touch(ticket.hash())?;
+        self.blob_meta
+            .add_provider(ticket.hash(), ticket.node_addr().clone())?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let starlink = self.clone();
+        self.blobs.rt().try_spawn_detached(move || async move {
+            let result = fetch(
+                starlink.blobs.store(),
+                &starlink.transfers,
+                starlink.router.endpoint(),
+                &ticket,
+                priority,
+                &sender,
+            )
+            .await;
+            let peer = Some(ticket.node_addr().node_id);
+            match result {
+                Ok(bytes) => starlink.transfer_log.record(
+                    TransferLogKind::Download,
+                    ticket.hash(),
+                    peer,
+                    bytes,
+                    None,
+                ),
+                Err(err) => {
+                    starlink.transfer_log.record(
+                        TransferLogKind::DownloadFailed,
+                        ticket.hash(),
+                        peer,
+                        0,
+                        Some(err.to_string()),
+                    );
+                    _ = sender.send(Err(err));
+                }
+            }
+        })?;
+        Ok(DownloadProgress::new(Box::pin(n0_future::stream::unfold(
+            receiver,
+            |mut receiver| async move { receiver.recv().await.map(|item| (item, receiver)) },
+        ))))
+    }
+    pub(crate) async fn throttle_download(&self, peer: NodeId, bytes: u64) {
+        self.transfers
+            .throttle(TransferDirection::Download, peer, bytes)
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bao_tree::ChunkNum;
+    use std::path::PathBuf;
+
+    use iroh::{RelayMode, SecretKey, protocol::Router};
+    use iroh_blobs::store::{EntryStatus, MapMut, mem};
+
+    use super::*;
+
+    fn peer(seed: u8) -> NodeId {
+        SecretKey::from_bytes(&[seed; 32]).public()
+    }
+
+    fn test_data(len: usize, seed: u8) -> Bytes {
+        (0..len)
+            .map(|i| (i % 251) as u8 ^ seed)
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    async fn endpoint() -> Endpoint {
+        Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap()
+    }
+
+    async fn provider() -> (Router, Blobs<mem::Store>) {
+        let endpoint = endpoint().await;
+        let blobs = Blobs::memory().build(&endpoint);
+        let router = Router::builder(endpoint)
+            .accept(iroh_blobs::ALPN, blobs.clone())
+            .spawn();
+        (router, blobs)
+    }
+
+    async fn client_store() -> (Store, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("starlink-fetch-test-{}", rand::random::<u64>()));
+        (Store::load(&dir).await.unwrap(), dir)
+    }
+
+    async fn close_client(store: Store, dir: PathBuf, endpoint: Endpoint, router: Router) {
+        store.shutdown().await;
+        drop(store);
+        endpoint.close().await;
+        router.shutdown().await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    async fn fetch_prefix(
+        store: &Store,
+        endpoint: &Endpoint,
+        node_addr: NodeAddr,
+        hash: Hash,
+        chunks: u64,
+    ) {
+        let connection = endpoint.connect(node_addr, iroh_blobs::ALPN).await.unwrap();
+        let request = GetRequest::new(
+            hash,
+            RangeSpecSeq::from_ranges([ChunkRanges::from(..ChunkNum(chunks))]),
+        );
+        let ConnectedNext::StartRoot(start_root) = fsm::start(connection, request)
+            .next()
+            .await
+            .unwrap()
+            .next()
+            .await
+            .unwrap()
+        else {
+            panic!("unexpected response");
+        };
+        let (mut content, size) = start_root.next().next().await.unwrap();
+        let entry = store.get_or_create(hash, size).await.unwrap();
+        let mut writer = entry.batch_writer().await.unwrap();
+        let end_blob = loop {
+            match content.next().await {
+                BlobContentNext::More((next, item)) => {
+                    writer.write_batch(size, vec![item.unwrap()]).await.unwrap();
+                    content = next;
+                }
+                BlobContentNext::Done(end_blob) => break end_blob,
+            }
+        };
+        writer.sync().await.unwrap();
+        let EndBlobNext::Closing(closing) = end_blob.next() else {
+            panic!("unexpected response");
+        };
+        closing.next().await.unwrap();
+    }
+
+    async fn read_complete(store: &Store, hash: Hash) -> Bytes {
+        let entry = store.get(&hash).await.unwrap().unwrap();
+        assert!(entry.is_complete());
+        MapEntry::data_reader(&entry)
+            .await
+            .unwrap()
+            .read_to_end()
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn unlimited_rate_never_waits() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+        assert_eq!(limiter.reserve(None, u64::MAX, now), Duration::ZERO);
+        assert_eq!(limiter.reserve(Some(0), 1 << 40, now), Duration::ZERO);
+    }
+
+    #[test]
+    fn waits_for_debt_and_refills_over_time() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+        assert_eq!(limiter.reserve(Some(1000), 1000, now), Duration::ZERO);
+        assert_eq!(
+            limiter.reserve(Some(1000), 500, now),
+            Duration::from_millis(500)
+        );
+        let later = now + Duration::from_secs(2);
+        assert_eq!(limiter.reserve(Some(1000), 500, later), Duration::ZERO);
+        assert_eq!(
+            limiter.reserve(Some(1000), 1000, later),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn burst_is_capped_at_one_second_of_rate() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+        limiter.reserve(Some(1000), 0, now);
+        let later = now + Duration::from_secs(60);
+        assert_eq!(
+            limiter.reserve(Some(1000), 2000, later),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn idle_peer_limiters_are_evicted() {
+        let scheduler = TransferScheduler::default();
+        scheduler.set_limits(TransferLimits {
+            peer_upload_rate: Some(1 << 30),
+            ..Default::default()
+        });
+        for seed in 0..3 {
+            scheduler
+                .throttle(TransferDirection::Upload, peer(seed), 1)
+                .await;
+        }
+        let mut state = scheduler.state.lock();
+        assert_eq!(state.peers.len(), 3);
+        let later = Instant::now() + PEER_IDLE_TIMEOUT;
+        state.evict_idle_peers(later);
+        assert!(state.peers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrency_cap_applies_per_direction_in_priority_order() {
+        let scheduler = TransferScheduler::default();
+        scheduler.set_limits(TransferLimits {
+            max_concurrent_transfers: Some(1),
+            ..Default::default()
+        });
+        let download = scheduler
+            .acquire(TransferDirection::Download, TransferPriority::Normal)
+            .await
+            .unwrap();
+        let upload = scheduler
+            .acquire(TransferDirection::Upload, TransferPriority::Normal)
+            .await
+            .unwrap();
+        assert_eq!(scheduler.stats().active_transfers, 2);
+
+        let low = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move {
+                scheduler
+                    .acquire(TransferDirection::Download, TransferPriority::Low)
+                    .await
+            }
+        });
+        tokio::task::yield_now().await;
+        let high = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move {
+                scheduler
+                    .acquire(TransferDirection::Download, TransferPriority::High)
+                    .await
+            }
+        });
+        while scheduler.stats().queued_transfers < 2 {
+            tokio::task::yield_now().await;
+        }
+        drop(upload);
+        assert_eq!(scheduler.stats().queued_transfers, 2);
+
+        drop(download);
+        let high = high.await.unwrap().unwrap();
+        assert!(!low.is_finished());
+        drop(high);
+        drop(low.await.unwrap().unwrap());
+        assert_eq!(scheduler.stats().active_transfers, 0);
+        assert_eq!(scheduler.stats().queued_transfers, 0);
+    }
+
+    #[tokio::test]
+    async fn upload_permit_is_held_per_request() {
+        let scheduler = TransferScheduler::default();
+        scheduler.set_limits(TransferLimits {
+            max_concurrent_transfers: Some(1),
+            ..Default::default()
+        });
+        let events = ThrottledEvents {
+            events: EventSender::default(),
+            scheduler: scheduler.clone(),
+            permits: Default::default(),
+        };
+        let request = |request_id| Event::GetRequestReceived {
+            connection_id: 1,
+            hash: Hash::new(b"a"),
+            request_id,
+        };
+        assert_eq!(scheduler.stats().active_transfers, 0);
+        events.send(request(0)).await;
+        assert_eq!(scheduler.stats().active_transfers, 1);
+
+        let second = tokio::spawn({
+            let events = events.clone();
+            async move { events.send(request(4)).await }
+        });
+        while scheduler.stats().queued_transfers < 1 {
+            tokio::task::yield_now().await;
+        }
+        assert!(!second.is_finished());
+        events.try_send(Event::TransferAborted {
+            connection_id: 1,
+            request_id: 0,
+            stats: None,
+        });
+        second.await.unwrap();
+        assert_eq!(scheduler.stats().active_transfers, 1);
+        events
+            .send(Event::TransferCompleted {
+                connection_id: 1,
+                request_id: 4,
+                stats: Default::default(),
+            })
+            .await;
+        assert_eq!(scheduler.stats().active_transfers, 0);
+        assert_eq!(scheduler.stats().queued_transfers, 0);
+    }
+
+    #[tokio::test]
+    async fn resumes_partial_blob_with_missing_ranges_only() {
+        let (router, blobs) = provider().await;
+        let data = test_data(1 << 20, 0);
+        let hash = blobs.client().add_bytes(data.clone()).await.unwrap().hash;
+        let node_addr = router.endpoint().node_addr().await.unwrap();
+        let endpoint = endpoint().await;
+        let (store, dir) = client_store().await;
+        fetch_prefix(&store, &endpoint, node_addr.clone(), hash, 512).await;
+        assert!(matches!(
+            store.entry_status(&hash).await.unwrap(),
+            EntryStatus::Partial
+        ));
+
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let ticket = BlobTicket::new(node_addr, hash, BlobFormat::Raw).unwrap();
+        let bytes_read = fetch(
+            &store,
+            &TransferScheduler::default(),
+            &endpoint,
+            &ticket,
+            TransferPriority::Normal,
+            &sender,
+        )
+        .await
+        .unwrap();
+        assert!(bytes_read < data.len() as u64 * 3 / 4, "{}", bytes_read);
+        assert_eq!(read_complete(&store, hash).await, data);
+        close_client(store, dir, endpoint, router).await;
+    }
+
+    #[tokio::test]
+    async fn hash_seq_fetch_skips_children_present_locally() {
+        let (router, blobs) = provider().await;
+        let first = test_data(256 << 10, 1);
+        let second = test_data(256 << 10, 2);
+        let first_hash = blobs.client().add_bytes(first.clone()).await.unwrap().hash;
+        let second_hash = blobs.client().add_bytes(second.clone()).await.unwrap().hash;
+        let hash_seq = [first_hash, second_hash].into_iter().collect::<HashSeq>();
+        let hash = blobs
+            .client()
+            .add_bytes(hash_seq.into_inner())
+            .await
+            .unwrap()
+            .hash;
+        let node_addr = router.endpoint().node_addr().await.unwrap();
+        let endpoint = endpoint().await;
+        let (store, dir) = client_store().await;
+        let _first_tag = store
+            .import_bytes(first.clone(), BlobFormat::Raw)
+            .await
+            .unwrap();
+
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let ticket = BlobTicket::new(node_addr, hash, BlobFormat::HashSeq).unwrap();
+        let bytes_read = fetch(
+            &store,
+            &TransferScheduler::default(),
+            &endpoint,
+            &ticket,
+            TransferPriority::Normal,
+            &sender,
+        )
+        .await
+        .unwrap();
+        assert!(
+            bytes_read < (first.len() + second.len()) as u64,
+            "{}",
+            bytes_read
+        );
+        assert_eq!(read_complete(&store, first_hash).await, first);
+        assert_eq!(read_complete(&store, second_hash).await, second);
+        close_client(store, dir, endpoint, router).await;
+    }
+
+    #[tokio::test]
+    async fn concurrent_fetches_of_one_blob_download_it_once() {
+        let (router, blobs) = provider().await;
+        let data = test_data(256 << 10, 3);
+        let hash = blobs.client().add_bytes(data.clone()).await.unwrap().hash;
+        let node_addr = router.endpoint().node_addr().await.unwrap();
+        let endpoint = endpoint().await;
+        let (store, dir) = client_store().await;
+        let transfers = TransferScheduler::default();
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let ticket = BlobTicket::new(node_addr, hash, BlobFormat::Raw).unwrap();
+        let (first, second) = tokio::join!(
+            fetch(
+                &store,
+                &transfers,
+                &endpoint,
+                &ticket,
+                TransferPriority::Normal,
+                &sender
+            ),
+            fetch(
+                &store,
+                &transfers,
+                &endpoint,
+                &ticket,
+                TransferPriority::Normal,
+                &sender
+            ),
+        );
+        let mut bytes_read = [first.unwrap(), second.unwrap()];
+        bytes_read.sort();
+        assert_eq!(bytes_read[0], 0);
+        assert!(bytes_read[1] >= data.len() as u64);
+        assert_eq!(read_complete(&store, hash).await, data);
+        assert!(
+            transfers
+                .fetches
+                .lock()
+                .values()
+                .all(|lock| lock.strong_count() == 0)
+        );
+        close_client(store, dir, endpoint, router).await;
+    }
+}
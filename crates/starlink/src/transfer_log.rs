@@ -0,0 +1,216 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use iroh::NodeId;
+use iroh_blobs::Hash;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::Starlink;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransferLogKind {
+    Upload,
+    UploadAborted,
+    Download,
+    DownloadFailed,
+    ShareCreated,
+    ShareRevoked,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferLogEntry {
+    pub time: SystemTime,
+    pub kind: TransferLogKind,
+    pub hash: Hash,
+    pub peer: Option<NodeId>,
+    pub bytes: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TransferLogQuery {
+    pub kinds: Option<Vec<TransferLogKind>>,
+    pub hash: Option<Hash>,
+    pub peer: Option<NodeId>,
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+}
+impl TransferLogQuery {
+    fn matches(&self, entry: &TransferLogEntry) -> bool {
+        self.kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(&entry.kind))
+            && self.hash.is_none_or(|hash| hash == entry.hash)
+            && self.peer.is_none_or(|peer| Some(peer) == entry.peer)
+            && self.since.is_none_or(|since| entry.time >= since)
+            && self.until.is_none_or(|until| entry.time < until)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferLogFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TransferLog {
+    path: PathBuf,
+    lock: Arc<Mutex<()>>,
+}
+impl TransferLog {
+    pub(crate) fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+    pub(crate) fn record(
+        &self,
+        kind: TransferLogKind,
+        hash: Hash,
+        peer: Option<NodeId>,
+        bytes: u64,
+        error: Option<String>,
+    ) {
+        let entry = TransferLogEntry {
+            time: SystemTime::now(),
+            kind,
+            hash,
+            peer,
+            bytes,
+            error,
+        };
+        if let Err(err) = self.append(&entry) {
+            log::warn!("写入传输日志失败: {}", err);
+        }
+    }
+    fn append(&self, entry: &TransferLogEntry) -> Result<()> {
+        let _lock = self.lock.lock();
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        file.write_all(&line)?;
+        Ok(())
+    }
+    fn query(&self, query: &TransferLogQuery) -> Result<Vec<TransferLogEntry>> {
+        let _lock = self.lock.lock();
+        let content = match std::fs::read(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let mut entries = vec![];
+        for (index, line) in String::from_utf8_lossy(&content).lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let entry = match serde_json::from_str(line) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    log::warn!("跳过传输日志第 {} 行: {}", index + 1, err);
+                    continue;
+                }
+            };
+            if query.matches(&entry) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl Starlink {
+    pub fn transfer_log(&self, query: &TransferLogQuery) -> Result<Vec<TransferLogEntry>> {
+        self.transfer_log.query(query)
+    }
+    pub fn export_transfer_log(
+        &self,
+        path: impl AsRef<Path>,
+        query: &TransferLogQuery,
+        format: TransferLogFormat,
+    ) -> Result<usize> {
+        let entries = self.transfer_log.query(query)?;
+        let content = match format {
+            TransferLogFormat::Json => serde_json::to_vec_pretty(&entries)?,
+            TransferLogFormat::Csv => {
+                let mut content = String::from("time,kind,hash,peer,bytes,error\n");
+                for entry in &entries {
+                    let time = entry
+                        .time
+                        .duration_since(UNIX_EPOCH)
+                        .map(|time| time.as_millis())
+                        .unwrap_or_default();
+                    content.push_str(&format!(
+                        "{},{:?},{},{},{},{}\n",
+                        time,
+                        entry.kind,
+                        entry.hash,
+                        entry.peer.map(|peer| peer.to_string()).unwrap_or_default(),
+                        entry.bytes,
+                        csv_field(entry.error.as_deref().unwrap_or_default()),
+                    ));
+                }
+                content.into_bytes()
+            }
+        };
+        std::fs::write(path, content)?;
+        Ok(entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_skips_corrupt_lines() {
+        let dir = std::env::temp_dir().join(format!("starlink-log-test-{}", rand::random::<u64>()));
+        let log = TransferLog::new(dir.join("transfer_log.jsonl"));
+        log.record(TransferLogKind::Upload, Hash::new(b"a"), None, 1, None);
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&log.path)
+            .unwrap();
+        file.write_all(b"{\"time\":\n\xff\xfe garbage\n").unwrap();
+        log.record(TransferLogKind::Download, Hash::new(b"b"), None, 2, None);
+
+        let entries = log.query(&TransferLogQuery::default()).unwrap();
+        assert_eq!(entries.len(), 2);
+        let downloads = log
+            .query(&TransferLogQuery {
+                kinds: Some(vec![TransferLogKind::Download]),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(downloads.len(), 1);
+        assert_eq!(downloads[0].hash, Hash::new(b"b"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_log_is_empty() {
+        let log = TransferLog::new(std::env::temp_dir().join("starlink-missing-log.jsonl"));
+        assert!(log.query(&TransferLogQuery::default()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::Cursor,
+    net::SocketAddr,
+    pin::Pin,
+    str::FromStr,
+    task::{Context, Poll},
+};
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use iroh::{NodeAddr, NodeId, RelayUrl};
+use iroh_base::ticket::{self, Ticket};
+use n0_future::{Stream, StreamExt, task::JoinHandle};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+use web_sys::wasm_bindgen::JsCast;
+
+use crate::{Starlink, mem_blobs};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlobFormat {
+    Raw,
+    HashSeq,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Hash([u8; 32]);
+impl Hash {
+    pub fn new(buf: impl AsRef<[u8]>) -> Self {
+        Self(*blake3::hash(buf.as_ref()).as_bytes())
+    }
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+impl From<[u8; 32]> for Hash {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut text = data_encoding::BASE32_NOPAD.encode(&self.0);
+        text.make_ascii_lowercase();
+        f.write_str(&text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadOutcome {
+    pub local_size: u64,
+    pub downloaded_size: u64,
+}
+
+#[derive(Debug)]
+pub struct DownloadProgress {
+    task: JoinHandle<Result<DownloadOutcome>>,
+}
+impl DownloadProgress {
+    pub async fn finish(self) -> Result<DownloadOutcome> {
+        self.task.await?
+    }
+}
+
+#[derive(Debug)]
+pub struct Reader {
+    inner: Cursor<Bytes>,
+}
+impl Reader {
+    pub fn size(&self) -> u64 {
+        self.inner.get_ref().len() as u64
+    }
+    pub fn is_complete(&self) -> bool {
+        true
+    }
+    pub async fn read_to_bytes(&mut self) -> Result<Bytes> {
+        let len = self.inner.get_ref().len();
+        let position = (self.inner.position() as usize).min(len);
+        let bytes = self.inner.get_ref().slice(position..);
+        self.inner.set_position(len as u64);
+        Ok(bytes)
+    }
+}
+impl AsyncRead for Reader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobTicket {
+    node: NodeAddr,
+    format: BlobFormat,
+    hash: Hash,
+}
+impl BlobTicket {
+    pub fn new(node: NodeAddr, hash: Hash, format: BlobFormat) -> Result<Self> {
+        Ok(Self { node, format, hash })
+    }
+    pub fn node_addr(&self) -> &NodeAddr {
+        &self.node
+    }
+    pub fn format(&self) -> BlobFormat {
+        self.format
+    }
+    pub fn hash(&self) -> Hash {
+        self.hash
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum TicketWireFormat {
+    Variant0(Variant0BlobTicket),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Variant0BlobTicket {
+    node: Variant0NodeAddr,
+    format: BlobFormat,
+    hash: [u8; 32],
+}
+
+#[derive(Serialize, Deserialize)]
+struct Variant0NodeAddr {
+    node_id: NodeId,
+    info: Variant0AddrInfo,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Variant0AddrInfo {
+    relay_url: Option<RelayUrl>,
+    direct_addresses: BTreeSet<SocketAddr>,
+}
+
+impl Ticket for BlobTicket {
+    const KIND: &'static str = "blob";
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let data = TicketWireFormat::Variant0(Variant0BlobTicket {
+            node: Variant0NodeAddr {
+                node_id: self.node.node_id,
+                info: Variant0AddrInfo {
+                    relay_url: self.node.relay_url.clone(),
+                    direct_addresses: self.node.direct_addresses.clone(),
+                },
+            },
+            format: self.format,
+            hash: self.hash.0,
+        });
+        postcard::to_stdvec(&data).expect("postcard serialization failed")
+    }
+    fn from_bytes(bytes: &[u8]) -> std::result::Result<Self, ticket::Error> {
+        let TicketWireFormat::Variant0(Variant0BlobTicket { node, format, hash }) =
+            postcard::from_bytes(bytes).map_err(ticket::Error::Postcard)?;
+        Ok(Self {
+            node: NodeAddr {
+                node_id: node.node_id,
+                relay_url: node.info.relay_url,
+                direct_addresses: node.info.direct_addresses,
+            },
+            format,
+            hash: Hash(hash),
+        })
+    }
+}
+impl fmt::Display for BlobTicket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Ticket::serialize(self))
+    }
+}
+impl FromStr for BlobTicket {
+    type Err = ticket::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ticket::deserialize(s)
+    }
+}
+
+impl Starlink {
+    pub async fn shared_file(&self, file: web_sys::Blob) -> Result<BlobTicket> {
+        let buffer = wasm_bindgen_futures::JsFuture::from(file.array_buffer())
+            .await
+            .map_err(|err| anyhow!("{:?}", err))?;
+        self.share_bytes(js_sys::Uint8Array::new(&buffer).to_vec())
+            .await
+    }
+    pub async fn share_bytes(&self, bytes: impl Into<Bytes>) -> Result<BlobTicket> {
+        let hash = self.mem_blobs.insert(bytes.into(), true)?;
+        BlobTicket::new(
+            self.router.endpoint().node_addr().await?,
+            Hash(hash),
+            BlobFormat::Raw,
+        )
+    }
+    pub async fn share_reader(
+        &self,
+        mut reader: impl AsyncRead + Unpin + 'static,
+    ) -> Result<BlobTicket> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes).await?;
+        self.share_bytes(bytes).await
+    }
+    pub async fn share_stream(
+        &self,
+        mut stream: impl Stream<Item = std::io::Result<Bytes>> + Unpin + 'static,
+    ) -> Result<BlobTicket> {
+        let mut bytes = vec![];
+        while let Some(chunk) = stream.next().await {
+            bytes.extend_from_slice(&chunk?);
+        }
+        self.share_bytes(bytes).await
+    }
+    async fn load_blob(&self, ticket: &BlobTicket) -> Result<(Bytes, bool)> {
+        if let Some(bytes) = self.mem_blobs.get(ticket.hash.0).await? {
+            return Ok((bytes, false));
+        }
+        let bytes = mem_blobs::fetch(
+            self.router.endpoint(),
+            ticket.node_addr().clone(),
+            ticket.hash.0,
+        )
+        .await?;
+        self.mem_blobs.insert(bytes.clone(), false)?;
+        Ok((bytes, true))
+    }
+    pub async fn download_file(&self, ticket: BlobTicket) -> Result<DownloadProgress> {
+        let starlink = self.clone();
+        let task = n0_future::task::spawn(async move {
+            let (bytes, downloaded) = starlink.load_blob(&ticket).await?;
+            let size = bytes.len() as u64;
+            Ok(if downloaded {
+                DownloadOutcome {
+                    local_size: 0,
+                    downloaded_size: size,
+                }
+            } else {
+                DownloadOutcome {
+                    local_size: size,
+                    downloaded_size: 0,
+                }
+            })
+        });
+        Ok(DownloadProgress { task })
+    }
+    pub async fn read_to_bytes(&self, ticket: BlobTicket) -> Result<Bytes> {
+        self.reader(ticket).await?.read_to_bytes().await
+    }
+    pub async fn reader(&self, ticket: BlobTicket) -> Result<Reader> {
+        let (bytes, _) = self.load_blob(&ticket).await?;
+        Ok(Reader {
+            inner: Cursor::new(bytes),
+        })
+    }
+    pub async fn save_file(&self, ticket: BlobTicket, file_name: String) -> Result<()> {
+        let bytes = self.read_to_bytes(ticket).await?;
+        let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes.as_ref()));
+        let blob = web_sys::Blob::new_with_u8_array_sequence(&parts)
+            .map_err(|err| anyhow!("{:?}", err))?;
+        let url =
+            web_sys::Url::create_object_url_with_blob(&blob).map_err(|err| anyhow!("{:?}", err))?;
+        let anchor = web_sys::window()
+            .and_then(|window| window.document())
+            .ok_or_else(|| anyhow!("没有找到文档"))?
+            .create_element("a")
+            .map_err(|err| anyhow!("{:?}", err))?
+            .dyn_into::<web_sys::HtmlAnchorElement>()
+            .map_err(|_| anyhow!("这个元素不是链接"))?;
+        anchor.set_href(&url);
+        anchor.set_download(&file_name);
+        anchor.click();
+        web_sys::Url::revoke_object_url(&url).map_err(|err| anyhow!("{:?}", err))?;
+        Ok(())
+    }
+}